@@ -2,6 +2,9 @@ import { KeyPair, Worker } from 'near-workspaces';
 import { before, after, test, describe } from 'node:test';
 import { expect } from 'chai';
 
+// Added to the storage balance of the account, which pays for the storage of its scripts
+const SCRIPT_STORAGE_DEPOSIT = (10n ** 24n).toString();
+
 describe('run simple js', { only: true }, () => {
     /**
      * @type {Worker}
@@ -45,7 +48,7 @@ describe('run simple js', { only: true }, () => {
             contract.accountId, 'submit_script',
             {
                 script: `print('world');(function() { return 5678; })();`
-            }
+            }, { attachedDeposit: SCRIPT_STORAGE_DEPOSIT }
         );
         const result = await contract.callRaw(
             contract.accountId,
@@ -76,7 +79,7 @@ const args = JSON.parse(env.input());
 const result = env.verify_signed_message('${messageToBeSigned}', args.signature, '${contract.accountId}') ?
                     'valid' : 'invalid';
 env.value_return(JSON.stringify(result));            `
-            }
+            }, { attachedDeposit: SCRIPT_STORAGE_DEPOSIT }
         );
 
         await contract.call(
@@ -115,7 +118,7 @@ const args = JSON.parse(env.input());
 const result = env.ed25519_verify(new Uint8Array(args.signature), new Uint8Array(args.message), new Uint8Array(args.public_key));
 env.value_return(JSON.stringify(result ? 'valid' : 'invalid'));
 `
-            }
+            }, { attachedDeposit: SCRIPT_STORAGE_DEPOSIT }
         );
 
         let result = await contract.view(
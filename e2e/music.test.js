@@ -5,6 +5,9 @@ import { readFile } from 'fs/promises';
 import musicscript from './musicscript.js';
 import { wrapJSmusicInTemplate } from '../web4/webassemblymusic/musictemplate.js';
 
+// Added to the storage balance of the account, which pays for the storage of its scripts
+const SCRIPT_STORAGE_DEPOSIT = (10n ** 24n).toString();
+
 describe('create music in JS', () => {
     /**
      * @type {Worker}
@@ -40,7 +43,7 @@ describe('create music in JS', () => {
             'submit_script',
             {
                 script: musicscript
-            }
+            }, { attachedDeposit: SCRIPT_STORAGE_DEPOSIT }
         );
         const result = await contract.call(contract.accountId,
             'run_script_for_account_no_return',
@@ -60,7 +63,7 @@ describe('create music in JS', () => {
             'submit_script',
             {
                 script: scriptToUpload
-            }
+            }, { attachedDeposit: SCRIPT_STORAGE_DEPOSIT }
         );
         const result = await contract.view(
             'run_script_for_account_no_return',
@@ -61,7 +61,7 @@ export function refund_unspent() {
 }
 
 export function buy_tokens_for_near() {
-  if (env.attached_deposit() === 500_000_000_000_000_000_000_000n.toString()) {
+  if (env.attached_deposit() === 500_000_000_000_000_000_000_000n) {
     env.ft_transfer_internal(
       env.current_account_id(),
       env.predecessor_account_id(),
@@ -15,8 +15,6 @@ NOTES:
   - To prevent the deployed contract from being modified or deleted, it should not have any access
     keys on its account.
 */
-use std::ffi::CString;
-
 use near_contract_standards::fungible_token::metadata::{
     FungibleTokenMetadata, FungibleTokenMetadataProvider, FT_METADATA_SPEC,
 };
@@ -28,10 +26,15 @@ use near_sdk::json_types::U128;
 use near_sdk::near;
 use near_sdk::{env, log, near_bindgen, AccountId, NearToken, PanicOnDefault, PromiseOrValue};
 use quickjs_rust_near::jslib::{
-    add_function_to_js, arg_to_str, compile_js, js_call_function, load_js_bytecode, to_js_string,
+    add_event_functions, add_promise_functions, compile_js, js_callback, load_js_bundle,
+    load_js_bytecode, with_host_functions, HostFunctions, HostResult, JsValue, ModuleBundle,
 };
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
 
 const JS_BYTECODE_STORAGE_KEY: &[u8] = b"JS";
+const JS_BUNDLE_STORAGE_KEY: &[u8] = b"JSB";
 
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
@@ -43,8 +46,6 @@ pub struct Contract {
 }
 
 const DATA_IMAGE_SVG_NEAR_ICON: &str = "data:image/svg+xml,%3Csvg xmlns='http://www.w3.org/2000/svg' viewBox='0 0 288 288'%3E%3Cg id='l' data-name='l'%3E%3Cpath d='M187.58,79.81l-30.1,44.69a3.2,3.2,0,0,0,4.75,4.2L191.86,103a1.2,1.2,0,0,1,2,.91v80.46a1.2,1.2,0,0,1-2.12.77L102.18,77.93A15.35,15.35,0,0,0,90.47,72.5H87.34A15.34,15.34,0,0,0,72,87.84V201.16A15.34,15.34,0,0,0,87.34,216.5h0a15.35,15.35,0,0,0,13.08-7.31l30.1-44.69a3.2,3.2,0,0,0-4.75-4.2L96.14,186a1.2,1.2,0,0,1-2-.91V104.61a1.2,1.2,0,0,1,2.12-.77l89.55,107.23a15.35,15.35,0,0,0,11.71,5.43h3.13A15.34,15.34,0,0,0,216,201.16V87.84A15.34,15.34,0,0,0,200.66,72.5h0A15.35,15.35,0,0,0,187.58,79.81Z'/%3E%3C/g%3E%3C/svg%3E";
-static mut CONTRACT_REF_MUT: *mut Contract = 0 as *mut Contract;
-static mut CONTRACT_REF: *const Contract = 0 as *const Contract;
 
 #[near_bindgen]
 impl Contract {
@@ -90,113 +91,68 @@ impl Contract {
     }
 
     fn store_js_bytecode(&self, bytecode: Vec<u8>) {
+        env::storage_remove(JS_BUNDLE_STORAGE_KEY);
         env::storage_write(JS_BYTECODE_STORAGE_KEY, &bytecode);
     }
 
-    fn load_js_bytecode(&self) -> i64 {
-        let bytecode = env::storage_read(JS_BYTECODE_STORAGE_KEY).unwrap();
-        return load_js_bytecode(bytecode.as_ptr(), bytecode.len());
+    fn store_js_bundle(&self, bundle: ModuleBundle) {
+        env::storage_remove(JS_BYTECODE_STORAGE_KEY);
+        env::storage_write(JS_BUNDLE_STORAGE_KEY, &bundle.to_bytes());
     }
 
-    unsafe fn add_mut_js_functions(&mut self) {
-        CONTRACT_REF_MUT = self as *mut Contract;
-        add_function_to_js(
-            "clear_data",
-            |ctx: i32, _this_val: i64, _argc: i32, argv: i32| -> i64 {
-                let key = arg_to_str(ctx, 0, argv);
-                (*CONTRACT_REF_MUT).data_map.remove(&key);
-                0
-            },
-            1,
-        );
-
-        add_function_to_js(
-            "set_data",
-            |ctx: i32, _this_val: i64, _argc: i32, argv: i32| -> i64 {
-                let key = arg_to_str(ctx, 0, argv);
-                let value = arg_to_str(ctx, 1, argv);
-                (*CONTRACT_REF_MUT).data_map.insert(&key, &value);
-                0
-            },
-            2,
-        );
-
-        add_function_to_js(
-            "ft_transfer",
-            |ctx: i32, _this_val: i64, _argc: i32, argv: i32| -> i64 {
-                let receiver_id = arg_to_str(ctx, 0, argv).parse().unwrap();
-                let amount: U128 = U128(arg_to_str(ctx, 1, argv).parse::<u128>().unwrap());
-                (*CONTRACT_REF_MUT).ft_transfer(receiver_id, amount, None);
-                return 0;
-            },
-            2,
-        );
-
-        add_function_to_js(
-            "ft_transfer_internal",
-            |ctx: i32, _this_val: i64, _argc: i32, argv: i32| -> i64 {
-                let sender_id = arg_to_str(ctx, 0, argv).parse().unwrap();
-                let receiver_id = arg_to_str(ctx, 1, argv).parse().unwrap();
-                let amount: U128 = U128(arg_to_str(ctx, 2, argv).parse::<u128>().unwrap());
-                (*CONTRACT_REF_MUT).token.internal_transfer(
-                    &sender_id,
-                    &receiver_id,
-                    amount.0,
-                    None,
-                );
-                return 0;
-            },
-            2,
-        );
-    }
-
-    unsafe fn add_js_functions(&self) {
-        CONTRACT_REF = self as *const Contract;
-
-        add_function_to_js(
-            "get_data",
-            |ctx: i32, _this_val: i64, _argc: i32, argv: i32| -> i64 {
-                let key = arg_to_str(ctx, 0, argv);
-                let value = (*CONTRACT_REF)
-                    .data_map
-                    .get(&key)
-                    .unwrap_or_else(|| "".to_string());
-                to_js_string(ctx, value)
-            },
-            1,
-        );
-
-        add_function_to_js(
-            "ft_balance_of",
-            move |ctx: i32, _this_val: i64, _argc: i32, argv: i32| -> i64 {
-                let account_id = arg_to_str(ctx, 0, argv).parse().unwrap();
-                let balance = (*CONTRACT_REF).ft_balance_of(account_id).0;
-                return to_js_string(ctx, balance.to_string());
-            },
-            1,
-        );
+    fn load_js_bytecode(&self) -> JsValue {
+        let result = match env::storage_read(JS_BUNDLE_STORAGE_KEY) {
+            Some(bundle) => load_js_bundle(&ModuleBundle::from_bytes(&bundle).unwrap()),
+            None => {
+                let bytecode = env::storage_read(JS_BYTECODE_STORAGE_KEY).unwrap();
+                load_js_bytecode(bytecode.as_ptr(), bytecode.len())
+            }
+        };
+        return result.unwrap_or_else(|err| env::panic_str(&err.to_string()));
     }
 
     #[payable]
     pub fn call_js_func(&mut self, function_name: String) {
         let jsmod = self.load_js_bytecode();
+        let contract = RefCell::new(self);
+
+        with_host_functions(|host| {
+            add_js_functions(host, &contract);
+            add_mut_js_functions(host, &contract);
+            add_promise_functions(host);
+            add_event_functions(host);
+            jsmod
+                .call_function(&function_name)
+                .unwrap_or_else(|err| env::panic_str(&err.to_string()));
+        });
+    }
 
-        unsafe {
-            self.add_js_functions();
-            self.add_mut_js_functions();
-            let function_name_cstr = CString::new(function_name).unwrap();
-            js_call_function(jsmod, function_name_cstr.as_ptr() as i32);
-        }
+    /// Passes the results of promises created by JavaScript to the exported function
+    /// `function_name`. Scripts schedule it with `env.promise_then` on the contract itself.
+    pub fn js_callback(&mut self, function_name: String) {
+        let jsmod = self.load_js_bytecode();
+        let contract = RefCell::new(self);
+
+        with_host_functions(|host| {
+            add_js_functions(host, &contract);
+            add_mut_js_functions(host, &contract);
+            add_promise_functions(host);
+            add_event_functions(host);
+            js_callback(&jsmod, &function_name)
+                .unwrap_or_else(|err| env::panic_str(&err.to_string()));
+        });
     }
 
     pub fn view_js_func(&self, function_name: String) {
         let jsmod = self.load_js_bytecode();
-
-        unsafe {
-            self.add_js_functions();
-            let function_name_cstr = CString::new(function_name).unwrap();
-            js_call_function(jsmod, function_name_cstr.as_ptr() as i32);
-        }
+        let contract = RefCell::new(self);
+
+        with_host_functions(|host| {
+            add_js_functions(host, &contract);
+            jsmod
+                .call_function(&function_name)
+                .unwrap_or_else(|err| env::panic_str(&err.to_string()));
+        });
     }
 
     pub fn post_javascript(&mut self, javascript: String) {
@@ -206,13 +162,32 @@ impl Contract {
         self.store_js_bytecode(compile_js(javascript, Some("main.js".to_string())));
     }
 
+    /// Posts several JavaScript modules, given by name, that can `import` each other. The
+    /// functions called with `call_js_func` and `view_js_func` are exported by `entry_module`.
+    pub fn post_javascript_modules(
+        &mut self,
+        entry_module: String,
+        modules: HashMap<String, String>,
+    ) {
+        if env::signer_account_id() != env::current_account_id() {
+            env::panic_str("Unauthorized");
+        }
+        if !modules.contains_key(&entry_module) {
+            env::panic_str(&format!("Entry module {} is missing", entry_module));
+        }
+        self.store_js_bundle(ModuleBundle::compile(entry_module, modules));
+    }
+
     pub fn web4_get(&self) {
         let jsmod = self.load_js_bytecode();
-        let web4_get_str = CString::new("web4_get").unwrap();
-        unsafe {
-            self.add_js_functions();
-            js_call_function(jsmod, web4_get_str.as_ptr() as i32);
-        }
+        let contract = RefCell::new(self);
+
+        with_host_functions(|host| {
+            add_js_functions(host, &contract);
+            jsmod
+                .call_function("web4_get")
+                .unwrap_or_else(|err| env::panic_str(&err.to_string()));
+        });
     }
 
     fn on_account_closed(&mut self, account_id: AccountId, balance: u128) {
@@ -224,6 +199,49 @@ impl Contract {
     }
 }
 
+fn add_js_functions<'a, C: Deref<Target = Contract>>(
+    host: &mut HostFunctions<'a>,
+    contract: &'a RefCell<C>,
+) {
+    host.add("get_data", move |key: String| -> HostResult<String> {
+        Ok(contract.borrow().data_map.get(&key).unwrap_or_default())
+    });
+    host.add("ft_balance_of", move |account_id: AccountId| -> HostResult<U128> {
+        Ok(contract.borrow().ft_balance_of(account_id))
+    });
+}
+
+fn add_mut_js_functions<'a, C: DerefMut<Target = Contract>>(
+    host: &mut HostFunctions<'a>,
+    contract: &'a RefCell<C>,
+) {
+    host.add("clear_data", move |key: String| -> HostResult<()> {
+        contract.borrow_mut().data_map.remove(&key);
+        Ok(())
+    });
+    host.add("set_data", move |key: String, value: String| -> HostResult<()> {
+        contract.borrow_mut().data_map.insert(&key, &value);
+        Ok(())
+    });
+    host.add(
+        "ft_transfer",
+        move |receiver_id: AccountId, amount: U128| -> HostResult<()> {
+            contract.borrow_mut().ft_transfer(receiver_id, amount, None);
+            Ok(())
+        },
+    );
+    host.add(
+        "ft_transfer_internal",
+        move |sender_id: AccountId, receiver_id: AccountId, amount: u128| -> HostResult<()> {
+            contract
+                .borrow_mut()
+                .token
+                .internal_transfer(&sender_id, &receiver_id, amount, None);
+            Ok(())
+        },
+    );
+}
+
 near_contract_standards::impl_fungible_token_core!(Contract, token, on_tokens_burned);
 
 #[near]
@@ -276,7 +294,7 @@ mod tests {
     use quickjs_rust_near_testenv::testenv::{
         alice, assert_latest_return_value_string_eq, bob, set_attached_deposit,
         set_block_timestamp, set_current_account_id, set_input, set_predecessor_account_id,
-        setup_test_env,
+        set_promise_results, setup_test_env,
     };
 
     const TOTAL_SUPPLY: u128 = 1_000_000_000_000_000;
@@ -332,6 +350,70 @@ mod tests {
         assert_latest_return_value_string_eq("hello".to_string());
     }
 
+    #[test]
+    fn test_javascript_modules() {
+        setup_test_env();
+
+        let mut contract = Contract::new_default_meta(bob().into(), TOTAL_SUPPLY.into());
+        set_current_account_id(bob());
+        set_predecessor_account_id(bob());
+        contract.post_javascript_modules(
+            "main.js".to_string(),
+            HashMap::from([
+                (
+                    "main.js".to_string(),
+                    "
+        import { greeting } from './greeting.js';
+        export function hello() {
+            env.value_return(greeting('modules'));
+        }
+        "
+                    .to_string(),
+                ),
+                (
+                    "greeting.js".to_string(),
+                    "export const greeting = (name) => `hello ${name}`;".to_string(),
+                ),
+            ]),
+        );
+        contract.call_js_func("hello".to_string());
+        assert_latest_return_value_string_eq("hello modules".to_string());
+
+        contract.post_javascript(
+            "
+        export function hello() {
+            env.value_return(\"hello\");
+        }
+        "
+            .to_string(),
+        );
+        contract.call_js_func("hello".to_string());
+        assert_latest_return_value_string_eq("hello".to_string());
+    }
+
+    #[test]
+    fn test_js_callback() {
+        setup_test_env();
+
+        let mut contract = Contract::new_default_meta(bob().into(), TOTAL_SUPPLY.into());
+        set_current_account_id(bob());
+        set_predecessor_account_id(bob());
+        contract.post_javascript(
+            "
+        export function on_price(price) {
+            if (price.status !== 'successful') {
+                env.panic('price unavailable');
+            }
+            env.set_data('price', String.fromCharCode(...price.data));
+        }
+        "
+            .to_string(),
+        );
+        set_promise_results(vec![Some(b"42".to_vec())]);
+        contract.js_callback("on_price".to_string());
+        assert_eq!("42", contract.data_map.get(&"price".to_string()).unwrap());
+    }
+
     #[test]
     fn test_js_check_balance() {
         setup_test_env();
@@ -364,7 +446,7 @@ mod tests {
             "
         export function transfer_2_000_to_alice() {
             const amount = 2_000n;
-            env.ft_transfer('alice.near', amount.toString());
+            env.ft_transfer('alice.near', amount);
         }
         "
             .to_string(),
@@ -448,7 +530,7 @@ mod tests {
             "
         export function transfer_2_000_from_bob_to_alice() {
             const amount = 2_000n;
-            env.ft_transfer_internal('bob.near','alice.near', amount.toString());
+            env.ft_transfer_internal('bob.near','alice.near', amount);
             env.value_return(transfer_id);
         }"
             .to_string(),
@@ -1,9 +1,9 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::{base64, env, near_bindgen};
 use quickjs_rust_near::jslib::{
-    add_function_to_js, arg_to_str, compile_js, js_call_function, load_js_bytecode, to_js_string,
+    add_function_to_js, arg_to_str, compile_js, load_js_bytecode, to_js_string, CodeStorage,
+    JsValue,
 };
-use std::ffi::CString;
 
 const JS_BYTECODE_STORAGE_KEY: &[u8] = b"JS";
 const JS_CONTENT_RESOURCE_PREFIX: &str = "JSC_";
@@ -13,17 +13,18 @@ const JS_CONTENT_RESOURCE_PREFIX: &str = "JSC_";
 #[borsh(crate="near_sdk::borsh")]
 pub struct Contract {}
 
-static mut CONTRACT_REF: *const Contract = 0 as *const Contract;
-
 #[near_bindgen]
 impl Contract {
     unsafe fn add_js_functions(&self) {
-        CONTRACT_REF = self as *const Contract;
         add_function_to_js(
             "get_content_base64",
             |ctx: i32, _this_val: i64, _argc: i32, argv: i32| -> i64 {
+                let key = match arg_to_str(ctx, 0, argv) {
+                    Ok(key) => key,
+                    Err(err) => return err.throw(ctx),
+                };
                 let mut prefixed_key = JS_CONTENT_RESOURCE_PREFIX.to_owned();
-                prefixed_key.push_str(arg_to_str(ctx, 0, argv).as_str());
+                prefixed_key.push_str(key.as_str());
                 let data = env::storage_read(&prefixed_key.as_bytes()).unwrap();
                 return to_js_string(ctx, base64::encode(data));
             },
@@ -31,13 +32,14 @@ impl Contract {
         );
     }
 
-    fn load_js_bytecode(&self) -> i64 {
+    fn load_js_bytecode(&self) -> JsValue {
         let bytecode = env::storage_read(JS_BYTECODE_STORAGE_KEY).unwrap();
-        return load_js_bytecode(bytecode.as_ptr(), bytecode.len());
+        return load_js_bytecode(bytecode.as_ptr(), bytecode.len())
+            .unwrap_or_else(|err| env::panic_str(&err.to_string()));
     }
 
     fn store_js_bytecode(&self, bytecode: Vec<u8>) {
-        env::storage_write(JS_BYTECODE_STORAGE_KEY, &bytecode);
+        CodeStorage::new(JS_BYTECODE_STORAGE_KEY).store(&bytecode);
     }
 
     pub fn call_js_func(&self, function_name: String) {
@@ -45,28 +47,40 @@ impl Contract {
 
         unsafe {
             self.add_js_functions();
-            let function_name_cstr = CString::new(function_name).unwrap();
-            js_call_function(jsmod, function_name_cstr.as_ptr() as i32);
         }
+        jsmod
+            .call_function(&function_name)
+            .unwrap_or_else(|err| env::panic_str(&err.to_string()));
     }
 
     pub fn web4_get(&self) {
         let jsmod = self.load_js_bytecode();
-        let web4_get_str = CString::new("web4_get").unwrap();
         unsafe {
             self.add_js_functions();
-            js_call_function(jsmod, web4_get_str.as_ptr() as i32);
         }
+        jsmod
+            .call_function("web4_get")
+            .unwrap_or_else(|err| env::panic_str(&err.to_string()));
     }
 
-    pub fn post_quickjs_bytecode(&mut self, bytecodebase64: String) {
+    /// Stores module bytecode after checking that this contract can load it. With
+    /// `expected_hash`, the SHA-256 of the bytecode in hex, the upload fails unless it matches.
+    pub fn post_quickjs_bytecode(&mut self, bytecodebase64: String, expected_hash: Option<String>) {
         assert_eq!(
             env::predecessor_account_id(),
             env::current_account_id(),
             "Unauthorized"
         );
-        let bytecode: Result<Vec<u8>, base64::DecodeError> = base64::decode(&bytecodebase64);
-        self.store_js_bytecode(bytecode.unwrap());
+        let bytecode = base64::decode(&bytecodebase64)
+            .unwrap_or_else(|err| env::panic_str(&format!("invalid base64 bytecode: {}", err)));
+        CodeStorage::new(JS_BYTECODE_STORAGE_KEY)
+            .store_verified(&bytecode, expected_hash.as_deref())
+            .unwrap_or_else(|err| env::panic_str(&err.to_string()));
+    }
+
+    /// The SHA-256 in hex of the stored bytecode, also when posted as JavaScript.
+    pub fn get_js_code_hash(&self) -> Option<String> {
+        CodeStorage::new(JS_BYTECODE_STORAGE_KEY).code_hash()
     }
 
     pub fn post_javascript(&mut self, javascript: String) {
@@ -95,7 +109,7 @@ impl Contract {
 mod tests {
     use super::*;
 
-    use quickjs_rust_near::jslib::compile_js;
+    use quickjs_rust_near::jslib::{bytecode_hash, compile_js};
     use quickjs_rust_near_testenv::testenv::{
         alice, assert_latest_return_value_string_eq, bob,
         set_current_account_id, set_input,
@@ -118,9 +132,11 @@ mod tests {
             String::from_utf8(CONTRACT_JS.to_vec()).unwrap(),
             Some("main.js".to_string()),
         );
+        let hash = bytecode_hash(&bytecode);
         let bytecodebase64 = base64::encode(bytecode);
 
-        contract.post_quickjs_bytecode(bytecodebase64);
+        contract.post_quickjs_bytecode(bytecodebase64, Some(hash.clone()));
+        assert_eq!(Some(hash), contract.get_js_code_hash());
         contract.post_content(
             "/index.html".to_string(),
             base64::encode("<html><body>hello</body></html>".to_string()),
@@ -1,4 +1,5 @@
 #!/bin/bash
 echo "Posting quickjs bytecode to $1"
 QUICKJS_BYTECODE=`cat $2 | base64`
-near call $1 post_quickjs_bytecode --gas=300000000000000 --accountId=$1 "{\"bytecodebase64\": \"$QUICKJS_BYTECODE\"}"
+EXPECTED_HASH=`sha256sum $2 | cut -d ' ' -f 1`
+near call $1 post_quickjs_bytecode --gas=300000000000000 --accountId=$1 "{\"bytecodebase64\": \"$QUICKJS_BYTECODE\", \"expected_hash\": \"$EXPECTED_HASH\"}"
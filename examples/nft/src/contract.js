@@ -204,11 +204,9 @@ export function nft_mint() {
 /**
  * @returns
  */
-export function nft_payout() {
-  const args = JSON.parse(env.input());
-  const balance = BigInt(args.balance);
+export function nft_payout(token_id, balance, max_len_payout) {
   const payout = {};
-  const token_owner_id = JSON.parse(env.nft_token(args.token_id)).owner_id;
+  const token_owner_id = JSON.parse(env.nft_token(token_id)).owner_id;
   const contract_owner = env.contract_owner();
 
   const addPayout = (account, amount) => {
@@ -13,14 +13,18 @@ use near_sdk::{near,
     NearToken, PanicOnDefault, Promise, PromiseOrValue,
 };
 use payouts::{Payout, Payouts};
+use quickjs_rust_near::jslib::host::JsArgs;
 use quickjs_rust_near::jslib::{
-    add_function_to_js, arg_to_number, arg_to_str, compile_js, js_call_function, js_get_property,
-    js_get_string, load_js_bytecode, to_js_string,
+    add_event_functions, add_promise_functions, add_storage_functions, compile_js, js_callback,
+    load_js_bytecode, with_host_functions, CodeStorage, HostFunctions, HostResult, JsError,
+    JsValue, SourceMap,
 };
-use std::ffi::CStr;
-use std::ffi::CString;
+use std::cell::RefCell;
+use std::ops::{Deref, DerefMut};
 
 const JS_BYTECODE_STORAGE_KEY: &[u8] = b"JS";
+const JS_SOURCE_MAP_STORAGE_KEY: &[u8] = b"JS_SOURCE_MAP";
+const STAGED_JS_SOURCE_MAP_STORAGE_KEY: &[u8] = b"JS_SOURCE_MAP_STAGED";
 const JS_CONTENT_RESOURCE_PREFIX: &str = "JSC_";
 const ENCRYPTED_CONTENT_STORAGE_PREFIX: &str = "ENC_";
 
@@ -33,6 +37,37 @@ enum StorageKey {
     Approval,
 }
 
+/// `err` located in the original sources, if there is a source map under `source_map_key`.
+fn with_stored_source_map(err: JsError, source_map_key: &[u8]) -> JsError {
+    let source_map = env::storage_read(source_map_key)
+        .and_then(|source_map| SourceMap::parse(&String::from_utf8_lossy(&source_map)).ok());
+    match source_map {
+        Some(source_map) => err.with_source_map("main.js", &source_map),
+        None => err,
+    }
+}
+
+/// Panics with `err`, located in the original sources if the JavaScript was posted with a source
+/// map.
+fn js_panic(err: JsError) -> ! {
+    env::panic_str(&with_stored_source_map(err, JS_SOURCE_MAP_STORAGE_KEY).to_string())
+}
+
+/// Stores `source_map` under `key` after checking it, or removes the one stored if `None`.
+fn store_source_map(key: &[u8], source_map: Option<String>) {
+    match source_map {
+        Some(source_map) => {
+            if let Err(err) = SourceMap::parse(&source_map) {
+                env::panic_str(&format!("invalid source map: {}", err));
+            }
+            env::storage_write(key, source_map.as_bytes());
+        }
+        None => {
+            env::storage_remove(key);
+        }
+    }
+}
+
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 #[borsh(crate="near_sdk::borsh")]
@@ -40,309 +75,143 @@ pub struct Contract {
     tokens: NonFungibleToken,
 }
 
-static mut CONTRACT_REF: *const Contract = 0 as *const Contract;
-
 #[near_bindgen]
 impl Contract {
-    unsafe fn add_js_functions(&self) {
-        CONTRACT_REF = self as *const Contract;
-        add_function_to_js(
-            "get_content_base64",
-            |ctx: i32, _this_val: i64, _argc: i32, argv: i32| -> i64 {
-                let mut prefixed_key = JS_CONTENT_RESOURCE_PREFIX.to_owned();
-                prefixed_key.push_str(arg_to_str(ctx, 0, argv).as_str());
-                let data = env::storage_read(&prefixed_key.as_bytes()).unwrap();
-                return to_js_string(ctx, base64::encode(data));
-            },
-            1,
-        );
-        add_function_to_js(
-            "contract_owner",
-            |ctx: i32, _this_val: i64, _argc: i32, _argv: i32| -> i64 {
-                return to_js_string(ctx, (*CONTRACT_REF).tokens.owner_id.to_string());
-            },
-            0,
-        );
-        add_function_to_js(
-            "nft_token",
-            |ctx: i32, _this_val: i64, _argc: i32, argv: i32| -> i64 {
-                let token_id = arg_to_str(ctx, 0, argv).to_string();
-                return to_js_string(
-                    ctx,
-                    serde_json::to_string(&(*CONTRACT_REF).tokens.nft_token(token_id).unwrap())
-                        .unwrap(),
-                );
-            },
-            1,
-        );
-        add_function_to_js(
-            "nft_supply_for_owner",
-            |ctx: i32, _this_val: i64, _argc: i32, argv: i32| -> i64 {
-                return (*CONTRACT_REF)
-                    .nft_supply_for_owner(arg_to_str(ctx, 0, argv).parse().unwrap())
-                    .0 as i64;
-            },
-            1,
-        );
-        add_function_to_js(
-            "nft_tokens",
-            |ctx: i32, _this_val: i64, _argc: i32, argv: i32| -> i64 {
-                let str = serde_json::to_string(&(*CONTRACT_REF).nft_tokens(
-                    Some(U128::from(arg_to_number(ctx, 0, argv) as u128)),
-                    Some(arg_to_number(ctx, 1, argv) as u64),
-                ))
-                .unwrap();
-
-                return to_js_string(ctx, str);
-            },
-            3,
-        );
-
-        // Crypto functions for encrypted content
-        add_function_to_js(
-            "ristretto_basepoint_mul",
-            |ctx: i32, _this_val: i64, _argc: i32, argv: i32| -> i64 {
-                let scalar_b64 = arg_to_str(ctx, 0, argv);
-                let scalar_bytes = base64::decode(&scalar_b64).unwrap_or_else(|_| vec![]);
-
-                match crypto::ristretto_basepoint_mul(&scalar_bytes) {
-                    Ok(result) => to_js_string(ctx, base64::encode(result)),
-                    Err(e) => {
-                        env::log_str(&format!("ristretto_basepoint_mul error: {}", e));
-                        to_js_string(ctx, String::new())
-                    }
-                }
-            },
-            1,
-        );
-
-        add_function_to_js(
-            "ristretto_scalar_mul",
-            |ctx: i32, _this_val: i64, _argc: i32, argv: i32| -> i64 {
-                let scalar_b64 = arg_to_str(ctx, 0, argv);
-                let point_b64 = arg_to_str(ctx, 1, argv);
-                let scalar_bytes = base64::decode(&scalar_b64).unwrap_or_else(|_| vec![]);
-                let point_bytes = base64::decode(&point_b64).unwrap_or_else(|_| vec![]);
-
-                match crypto::ristretto_scalar_mul(&scalar_bytes, &point_bytes) {
-                    Ok(result) => to_js_string(ctx, base64::encode(result)),
-                    Err(e) => {
-                        env::log_str(&format!("ristretto_scalar_mul error: {}", e));
-                        to_js_string(ctx, String::new())
-                    }
-                }
-            },
-            2,
-        );
-
-        add_function_to_js(
-            "ristretto_point_add",
-            |ctx: i32, _this_val: i64, _argc: i32, argv: i32| -> i64 {
-                let point1_b64 = arg_to_str(ctx, 0, argv);
-                let point2_b64 = arg_to_str(ctx, 1, argv);
-                let point1_bytes = base64::decode(&point1_b64).unwrap_or_else(|_| vec![]);
-                let point2_bytes = base64::decode(&point2_b64).unwrap_or_else(|_| vec![]);
-
-                match crypto::ristretto_point_add(&point1_bytes, &point2_bytes) {
-                    Ok(result) => to_js_string(ctx, base64::encode(result)),
-                    Err(e) => {
-                        env::log_str(&format!("ristretto_point_add error: {}", e));
-                        to_js_string(ctx, String::new())
-                    }
-                }
-            },
-            2,
-        );
-
-        add_function_to_js(
-            "ristretto_point_sub",
-            |ctx: i32, _this_val: i64, _argc: i32, argv: i32| -> i64 {
-                let point1_b64 = arg_to_str(ctx, 0, argv);
-                let point2_b64 = arg_to_str(ctx, 1, argv);
-                let point1_bytes = base64::decode(&point1_b64).unwrap_or_else(|_| vec![]);
-                let point2_bytes = base64::decode(&point2_b64).unwrap_or_else(|_| vec![]);
-
-                match crypto::ristretto_point_sub(&point1_bytes, &point2_bytes) {
-                    Ok(result) => to_js_string(ctx, base64::encode(result)),
-                    Err(e) => {
-                        env::log_str(&format!("ristretto_point_sub error: {}", e));
-                        to_js_string(ctx, String::new())
-                    }
-                }
-            },
-            2,
-        );
-
-        add_function_to_js(
-            "verify_reencryption_proof",
-            |ctx: i32, _this_val: i64, _argc: i32, argv: i32| -> i64 {
-                let old_c1 = arg_to_str(ctx, 0, argv);
-                let old_c2 = arg_to_str(ctx, 1, argv);
-                let old_pk = arg_to_str(ctx, 2, argv);
-                let new_c1 = arg_to_str(ctx, 3, argv);
-                let new_c2 = arg_to_str(ctx, 4, argv);
-                let new_pk = arg_to_str(ctx, 5, argv);
-                let commit_r_old = arg_to_str(ctx, 6, argv);
-                let commit_s_old = arg_to_str(ctx, 7, argv);
-                let commit_r_new = arg_to_str(ctx, 8, argv);
-                let commit_s_new = arg_to_str(ctx, 9, argv);
-                let response_s = arg_to_str(ctx, 10, argv);
-                let response_r_old = arg_to_str(ctx, 11, argv);
-                let response_r_new = arg_to_str(ctx, 12, argv);
-
-                match crypto::verify_reencryption_proof_base64(
-                    &old_c1, &old_c2, &old_pk,
-                    &new_c1, &new_c2, &new_pk,
-                    &commit_r_old, &commit_s_old,
-                    &commit_r_new, &commit_s_new,
-                    &response_s, &response_r_old, &response_r_new,
-                ) {
-                    Ok(true) => 1i64,
-                    Ok(false) => 0i64,
-                    Err(e) => {
-                        env::log_str(&format!("verify_reencryption_proof error: {}", e));
-                        0i64
-                    }
-                }
-            },
-            13,
-        );
-
-        // NFT transfer function for marketplace
-        add_function_to_js(
-            "internal_transfer_unguarded",
-            |ctx: i32, _this_val: i64, _argc: i32, argv: i32| -> i64 {
-                let token_id = arg_to_str(ctx, 0, argv).to_string();
-                let from = arg_to_str(ctx, 1, argv).parse().unwrap();
-                let to = arg_to_str(ctx, 2, argv).parse().unwrap();
-
-                let contract = CONTRACT_REF as *mut Contract;
-                (*contract).tokens.internal_transfer_unguarded(&token_id, &from, &to);
-
-                1i64 // Return success
-            },
-            3,
-        );
-
-        // Storage functions for encrypted content (with ENC_ prefix for isolation)
-        add_function_to_js(
-            "storage_read",
-            |ctx: i32, _this_val: i64, _argc: i32, argv: i32| -> i64 {
-                let key = arg_to_str(ctx, 0, argv);
-                let mut prefixed_key = ENCRYPTED_CONTENT_STORAGE_PREFIX.to_owned();
-                prefixed_key.push_str(&key);
-
-                match env::storage_read(prefixed_key.as_bytes()) {
-                    Some(value) => {
-                        // Return the value as a string
-                        match String::from_utf8(value) {
-                            Ok(s) => to_js_string(ctx, s),
-                            Err(_) => 0i64 // Return null for invalid UTF-8
-                        }
-                    }
-                    None => 0i64 // Return JS null for non-existent keys
-                }
-            },
-            1,
-        );
-
-        add_function_to_js(
-            "storage_write",
-            |ctx: i32, _this_val: i64, _argc: i32, argv: i32| -> i64 {
-                let key = arg_to_str(ctx, 0, argv);
-                let value = arg_to_str(ctx, 1, argv);
-                let mut prefixed_key = ENCRYPTED_CONTENT_STORAGE_PREFIX.to_owned();
-                prefixed_key.push_str(&key);
-
-                env::storage_write(prefixed_key.as_bytes(), value.as_bytes());
-                0i64 // Return undefined
-            },
-            2,
-        );
-
-        add_function_to_js(
-            "storage_remove",
-            |ctx: i32, _this_val: i64, _argc: i32, argv: i32| -> i64 {
-                let key = arg_to_str(ctx, 0, argv);
-                let mut prefixed_key = ENCRYPTED_CONTENT_STORAGE_PREFIX.to_owned();
-                prefixed_key.push_str(&key);
-
-                env::storage_remove(prefixed_key.as_bytes());
-                0i64 // Return undefined
-            },
-            1,
-        );
-
-        add_function_to_js(
-            "transfer",
-            |ctx: i32, _this_val: i64, _argc: i32, argv: i32| -> i64 {
-                let receiver_id: AccountId = arg_to_str(ctx, 0, argv).parse().unwrap();
-                let amount_str = arg_to_str(ctx, 1, argv);
-                let amount: u128 = amount_str.parse().unwrap();
-
-                let promise_idx = env::promise_batch_create(&receiver_id);
-                env::promise_batch_action_transfer(promise_idx, NearToken::from_yoctonear(amount));
-
-                0i64 // Return undefined
-            },
-            2,
-        );
-    }
-
-    fn load_js_bytecode(&self) -> i64 {
+    fn load_js_bytecode(&self) -> JsValue {
         let bytecode = env::storage_read(JS_BYTECODE_STORAGE_KEY).unwrap();
-        return load_js_bytecode(bytecode.as_ptr(), bytecode.len());
+        return load_js_bytecode(bytecode.as_ptr(), bytecode.len())
+            .unwrap_or_else(|err| js_panic(err));
     }
 
     fn store_js_bytecode(&self, bytecode: Vec<u8>) {
-        env::storage_write(JS_BYTECODE_STORAGE_KEY, &bytecode);
+        CodeStorage::new(JS_BYTECODE_STORAGE_KEY).store(&bytecode);
     }
 
     /// Call a JavaScript function (view-only, cannot modify storage)
     pub fn call_js_func(&self, function_name: String) {
         let jsmod = self.load_js_bytecode();
-
-        unsafe {
-            self.add_js_functions();
-            let function_name_cstr = CString::new(function_name).unwrap();
-            js_call_function(jsmod, function_name_cstr.as_ptr() as i32);
-        }
+        let contract = RefCell::new(self);
+
+        with_host_functions(|host| {
+            add_js_functions(host, &contract);
+            jsmod
+                .call_function(&function_name)
+                .unwrap_or_else(|err| js_panic(err));
+        });
     }
 
     /// Call a JavaScript function that can modify storage
     #[payable]
     pub fn call_js_func_mut(&mut self, function_name: String) {
         let jsmod = self.load_js_bytecode();
+        let contract = RefCell::new(self);
+
+        with_host_functions(|host| {
+            add_js_functions(host, &contract);
+            add_mut_js_functions(host, &contract);
+            add_promise_functions(host);
+            add_event_functions(host);
+            jsmod
+                .call_function(&function_name)
+                .unwrap_or_else(|err| js_panic(err));
+        });
+    }
 
-        unsafe {
-            self.add_js_functions();
-            let function_name_cstr = CString::new(function_name).unwrap();
-            js_call_function(jsmod, function_name_cstr.as_ptr() as i32);
-        }
+    /// Passes the results of promises created by JavaScript to the exported function
+    /// `function_name`. Scripts schedule it with `env.promise_then` on the contract itself.
+    pub fn js_callback(&mut self, function_name: String) {
+        let jsmod = self.load_js_bytecode();
+        let contract = RefCell::new(self);
+
+        with_host_functions(|host| {
+            add_js_functions(host, &contract);
+            add_mut_js_functions(host, &contract);
+            add_promise_functions(host);
+            add_event_functions(host);
+            js_callback(&jsmod, &function_name)
+                .unwrap_or_else(|err| js_panic(err));
+        });
     }
 
     pub fn web4_get(&self) {
         let jsmod = self.load_js_bytecode();
-        let web4_get_str = CString::new("web4_get").unwrap();
-        unsafe {
-            self.add_js_functions();
-            js_call_function(jsmod, web4_get_str.as_ptr() as i32);
-        }
+        let contract = RefCell::new(self);
+
+        with_host_functions(|host| {
+            add_js_functions(host, &contract);
+            jsmod
+                .call_function("web4_get")
+                .unwrap_or_else(|err| js_panic(err));
+        });
     }
 
-    pub fn post_quickjs_bytecode(&mut self, bytecodebase64: String) {
+    /// Stores module bytecode after checking that this contract can load it. With
+    /// `expected_hash`, the SHA-256 of the bytecode in hex, the upload fails unless it matches.
+    pub fn post_quickjs_bytecode(&mut self, bytecodebase64: String, expected_hash: Option<String>) {
         if env::predecessor_account_id() != self.tokens.owner_id {
             env::panic_str("Unauthorized");
         }
-        let bytecode: Result<Vec<u8>, base64::DecodeError> = base64::decode(&bytecodebase64);
-        self.store_js_bytecode(bytecode.unwrap());
+        let bytecode = base64::decode(&bytecodebase64)
+            .unwrap_or_else(|err| env::panic_str(&format!("invalid base64 bytecode: {}", err)));
+        CodeStorage::new(JS_BYTECODE_STORAGE_KEY)
+            .store_verified(&bytecode, expected_hash.as_deref())
+            .unwrap_or_else(|err| env::panic_str(&err.to_string()));
+        env::storage_remove(JS_SOURCE_MAP_STORAGE_KEY);
     }
 
-    pub fn post_javascript(&mut self, javascript: String) {
+    /// The SHA-256 in hex of the stored bytecode, also when posted as JavaScript.
+    pub fn get_js_code_hash(&self) -> Option<String> {
+        CodeStorage::new(JS_BYTECODE_STORAGE_KEY).code_hash()
+    }
+
+    /// Compiles and stores `javascript`, with the source map of the bundle or TypeScript it was
+    /// generated from, if any, for reporting errors at their location in the original sources.
+    pub fn post_javascript(&mut self, javascript: String, source_map: Option<String>) {
         if env::predecessor_account_id() != self.tokens.owner_id {
             env::panic_str("Unauthorized");
         }
         self.store_js_bytecode(compile_js(javascript, Some("main.js".to_string())));
+        store_source_map(JS_SOURCE_MAP_STORAGE_KEY, source_map);
+    }
+
+    /// Compiles and stages `javascript`, with its source map if any, to replace the live code
+    /// once `activate_javascript` is called. Returns the hash of the staged bytecode.
+    pub fn stage_javascript(&mut self, javascript: String, source_map: Option<String>) -> String {
+        if env::predecessor_account_id() != self.tokens.owner_id {
+            env::panic_str("Unauthorized");
+        }
+        let bytecode = compile_js(javascript, Some("main.js".to_string()));
+        let hash = CodeStorage::new(JS_BYTECODE_STORAGE_KEY)
+            .stage(&bytecode)
+            .unwrap_or_else(|err| env::panic_str(&format!("invalid bytecode: {}", err)));
+        store_source_map(STAGED_JS_SOURCE_MAP_STORAGE_KEY, source_map);
+        hash
+    }
+
+    /// Makes the staged JavaScript live, after calling its `migrate(previous_code_hash)` export,
+    /// if it has one, with the same host functions as `call_js_func_mut`. If the migration
+    /// throws, the previous code stays live. Returns the hash of the activated bytecode.
+    pub fn activate_javascript(&mut self) -> String {
+        if env::predecessor_account_id() != self.tokens.owner_id {
+            env::panic_str("Unauthorized");
+        }
+        let contract = RefCell::new(self);
+        let hash = CodeStorage::new(JS_BYTECODE_STORAGE_KEY)
+            .activate(|host| {
+                add_js_functions(host, &contract);
+                add_mut_js_functions(host, &contract);
+                add_promise_functions(host);
+                add_event_functions(host);
+            })
+            .unwrap_or_else(|err| {
+                let err = with_stored_source_map(err, STAGED_JS_SOURCE_MAP_STORAGE_KEY);
+                env::panic_str(&format!("could not activate the staged JavaScript: {}", err))
+            });
+        let source_map = env::storage_read(STAGED_JS_SOURCE_MAP_STORAGE_KEY)
+            .map(|source_map| String::from_utf8(source_map).unwrap());
+        store_source_map(JS_SOURCE_MAP_STORAGE_KEY, source_map);
+        env::storage_remove(STAGED_JS_SOURCE_MAP_STORAGE_KEY);
+        hash
     }
 
     pub fn post_content(&mut self, key: String, valuebase64: String) {
@@ -358,22 +227,18 @@ impl Contract {
     #[payable]
     pub fn nft_mint(&mut self, token_id: TokenId, token_owner_id: AccountId) -> Token {
         let jsmod = self.load_js_bytecode();
-        let nft_mint_str = CString::new("nft_mint").unwrap();
-        unsafe {
-            self.add_js_functions();
-
-            let mint_metadata_json_string = CStr::from_ptr(js_get_string(js_call_function(
-                jsmod,
-                nft_mint_str.as_ptr() as i32,
-            )) as *const i8)
-            .to_str()
-            .unwrap();
-
-            let parsed_json = serde_json::from_str(mint_metadata_json_string);
-            let token_metadata: TokenMetadata = parsed_json.unwrap();
-            self.tokens
-                .internal_mint(token_id, token_owner_id, Some(token_metadata))
-        }
+        let contract = RefCell::new(&*self);
+        let mint_metadata_json_string = with_host_functions(|host| {
+            add_js_functions(host, &contract);
+            jsmod
+                .call_function("nft_mint")
+                .unwrap_or_else(|err| js_panic(err))
+                .to_string()
+        });
+        let parsed_json = serde_json::from_str(&mint_metadata_json_string);
+        let token_metadata: TokenMetadata = parsed_json.unwrap();
+        self.tokens
+            .internal_mint(token_id, token_owner_id, Some(token_metadata))
     }
 
     #[payable]
@@ -426,23 +291,18 @@ impl Payouts for Contract {
     /// Given a `token_id` and NEAR-denominated balance, return the `Payout`.
     /// struct for the given token. Panic if the length of the payout exceeds
     /// `max_len_payout.
-    #[allow(unused_variables)]
     fn nft_payout(&self, token_id: String, balance: U128, max_len_payout: Option<u32>) -> Payout {
         let jsmod = self.load_js_bytecode();
-        let nft_payout_str = CString::new("nft_payout").unwrap();
-        unsafe {
-            self.add_js_functions();
-
-            let nft_payout_json_string = CStr::from_ptr(js_get_string(js_call_function(
-                jsmod,
-                nft_payout_str.as_ptr() as i32,
-            )) as *const i8)
-            .to_str()
-            .unwrap();
-
-            let parsed_json = serde_json::from_str(nft_payout_json_string);
-            return parsed_json.unwrap();
-        }
+        let contract = RefCell::new(self);
+        let nft_payout_json_string = with_host_functions(|host| {
+            add_js_functions(host, &contract);
+            jsmod
+                .call_function_with_args("nft_payout", (token_id, balance.0, max_len_payout))
+                .unwrap_or_else(|err| js_panic(err))
+                .to_string()
+        });
+        let parsed_json = serde_json::from_str(&nft_payout_json_string);
+        return parsed_json.unwrap();
     }
 
     /// Given a `token_id` and NEAR-denominated balance, transfer the token
@@ -465,6 +325,152 @@ impl Payouts for Contract {
     }
 }
 
+fn add_js_functions<'a, C: Deref<Target = Contract>>(
+    host: &mut HostFunctions<'a>,
+    contract: &'a RefCell<C>,
+) {
+    host.add("get_content_base64", |key: String| -> HostResult<String> {
+        let mut prefixed_key = JS_CONTENT_RESOURCE_PREFIX.to_owned();
+        prefixed_key.push_str(key.as_str());
+        let data = env::storage_read(prefixed_key.as_bytes())
+            .ok_or_else(|| format!("no content stored for {}", key))?;
+        Ok(base64::encode(data))
+    });
+    host.add("contract_owner", move || -> HostResult<AccountId> {
+        Ok(contract.borrow().tokens.owner_id.clone())
+    });
+    host.add("nft_token", move |token_id: TokenId| -> HostResult<String> {
+        Ok(serde_json::to_string(
+            &contract.borrow().tokens.nft_token(token_id),
+        )?)
+    });
+    host.add(
+        "nft_supply_for_owner",
+        move |account_id: AccountId| -> HostResult<u64> {
+            Ok(contract.borrow().nft_supply_for_owner(account_id).0 as u64)
+        },
+    );
+    host.add(
+        "nft_tokens",
+        move |from_index: Option<u64>, limit: Option<u64>| -> HostResult<String> {
+            Ok(serde_json::to_string(&contract.borrow().nft_tokens(
+                from_index.map(|from_index| U128::from(from_index as u128)),
+                limit,
+            ))?)
+        },
+    );
+
+    // Crypto functions for encrypted content
+    host.add(
+        "ristretto_basepoint_mul",
+        |scalar_b64: String| -> HostResult<String> {
+            let scalar_bytes = base64::decode(&scalar_b64).unwrap_or_else(|_| vec![]);
+
+            match crypto::ristretto_basepoint_mul(&scalar_bytes) {
+                Ok(result) => Ok(base64::encode(result)),
+                Err(e) => {
+                    env::log_str(&format!("ristretto_basepoint_mul error: {}", e));
+                    Ok(String::new())
+                }
+            }
+        },
+    );
+    host.add(
+        "ristretto_scalar_mul",
+        |scalar_b64: String, point_b64: String| -> HostResult<String> {
+            let scalar_bytes = base64::decode(&scalar_b64).unwrap_or_else(|_| vec![]);
+            let point_bytes = base64::decode(&point_b64).unwrap_or_else(|_| vec![]);
+
+            match crypto::ristretto_scalar_mul(&scalar_bytes, &point_bytes) {
+                Ok(result) => Ok(base64::encode(result)),
+                Err(e) => {
+                    env::log_str(&format!("ristretto_scalar_mul error: {}", e));
+                    Ok(String::new())
+                }
+            }
+        },
+    );
+    host.add(
+        "ristretto_point_add",
+        |point1_b64: String, point2_b64: String| -> HostResult<String> {
+            let point1_bytes = base64::decode(&point1_b64).unwrap_or_else(|_| vec![]);
+            let point2_bytes = base64::decode(&point2_b64).unwrap_or_else(|_| vec![]);
+
+            match crypto::ristretto_point_add(&point1_bytes, &point2_bytes) {
+                Ok(result) => Ok(base64::encode(result)),
+                Err(e) => {
+                    env::log_str(&format!("ristretto_point_add error: {}", e));
+                    Ok(String::new())
+                }
+            }
+        },
+    );
+    host.add(
+        "ristretto_point_sub",
+        |point1_b64: String, point2_b64: String| -> HostResult<String> {
+            let point1_bytes = base64::decode(&point1_b64).unwrap_or_else(|_| vec![]);
+            let point2_bytes = base64::decode(&point2_b64).unwrap_or_else(|_| vec![]);
+
+            match crypto::ristretto_point_sub(&point1_bytes, &point2_bytes) {
+                Ok(result) => Ok(base64::encode(result)),
+                Err(e) => {
+                    env::log_str(&format!("ristretto_point_sub error: {}", e));
+                    Ok(String::new())
+                }
+            }
+        },
+    );
+    host.add(
+        "verify_reencryption_proof",
+        |args: &JsArgs| -> HostResult<bool> {
+            let arg = |index: usize| args.decode::<String>(index);
+
+            match crypto::verify_reencryption_proof_base64(
+                &arg(0)?, &arg(1)?, &arg(2)?,
+                &arg(3)?, &arg(4)?, &arg(5)?,
+                &arg(6)?, &arg(7)?,
+                &arg(8)?, &arg(9)?,
+                &arg(10)?, &arg(11)?, &arg(12)?,
+            ) {
+                Ok(valid) => Ok(valid),
+                Err(e) => {
+                    env::log_str(&format!("verify_reencryption_proof error: {}", e));
+                    Ok(false)
+                }
+            }
+        },
+    );
+
+    // Storage functions for encrypted content (with ENC_ prefix for isolation)
+    add_storage_functions(host, ENCRYPTED_CONTENT_STORAGE_PREFIX);
+
+    host.add(
+        "transfer",
+        |receiver_id: AccountId, amount: u128| -> HostResult<()> {
+            let promise_idx = env::promise_batch_create(&receiver_id);
+            env::promise_batch_action_transfer(promise_idx, NearToken::from_yoctonear(amount));
+            Ok(())
+        },
+    );
+}
+
+fn add_mut_js_functions<'a, C: DerefMut<Target = Contract>>(
+    host: &mut HostFunctions<'a>,
+    contract: &'a RefCell<C>,
+) {
+    // NFT transfer function for marketplace
+    host.add(
+        "internal_transfer_unguarded",
+        move |token_id: TokenId, from: AccountId, to: AccountId| -> HostResult<bool> {
+            contract
+                .borrow_mut()
+                .tokens
+                .internal_transfer_unguarded(&token_id, &from, &to);
+            Ok(true)
+        },
+    );
+}
+
 near_contract_standards::impl_non_fungible_token_core!(Contract, tokens);
 near_contract_standards::impl_non_fungible_token_approval!(Contract, tokens);
 near_contract_standards::impl_non_fungible_token_enumeration!(Contract, tokens);
@@ -473,40 +479,18 @@ near_contract_standards::impl_non_fungible_token_enumeration!(Contract, tokens);
 impl NonFungibleTokenMetadataProvider for Contract {
     fn nft_metadata(&self) -> NFTContractMetadata {
         let jsmod = self.load_js_bytecode();
-
-        unsafe {
-            let nft_metadata_str = CString::new("nft_metadata").unwrap();
-            let name_str = CString::new("name").unwrap();
-            let symbol_str = CString::new("symbol").unwrap();
-            let icon_str = CString::new("icon").unwrap();
-
-            let val = js_call_function(jsmod, nft_metadata_str.as_ptr() as i32);
-            let name = CStr::from_ptr(
-                js_get_string(js_get_property(val, name_str.as_ptr() as i32)) as *const i8,
-            )
-            .to_str()
-            .unwrap();
-            let symbol = CStr::from_ptr(js_get_string(js_get_property(
-                val,
-                symbol_str.as_ptr() as i32,
-            )) as *const i8)
-            .to_str()
-            .unwrap();
-            let icon = CStr::from_ptr(
-                js_get_string(js_get_property(val, icon_str.as_ptr() as i32)) as *const i8,
-            )
-            .to_str()
-            .unwrap();
-
-            NFTContractMetadata {
-                spec: NFT_METADATA_SPEC.to_string(),
-                name: name.to_string(),
-                symbol: symbol.to_string(),
-                icon: Some(icon.to_string()),
-                base_uri: None,
-                reference: None,
-                reference_hash: None,
-            }
+        let val = jsmod
+            .call_function("nft_metadata")
+            .unwrap_or_else(|err| js_panic(err));
+
+        NFTContractMetadata {
+            spec: NFT_METADATA_SPEC.to_string(),
+            name: val.get_property("name").to_string(),
+            symbol: val.get_property("symbol").to_string(),
+            icon: Some(val.get_property("icon").to_string()),
+            base_uri: None,
+            reference: None,
+            reference_hash: None,
         }
     }
 }
@@ -518,7 +502,7 @@ mod tests {
     use super::*;
 
     use near_sdk::NearToken;
-    use quickjs_rust_near::jslib::compile_js;
+    use quickjs_rust_near::jslib::{bytecode_hash, compile_js};
     use quickjs_rust_near_testenv::testenv::{
         alice, assert_latest_return_value_contains, assert_latest_return_value_string_eq, bob,
         carol, set_attached_deposit, set_block_timestamp, set_current_account_id, set_input,
@@ -536,9 +520,11 @@ mod tests {
             String::from_utf8(CONTRACT_JS.to_vec()).unwrap(),
             Some("main.js".to_string()),
         );
+        let hash = bytecode_hash(&bytecode);
         let bytecodebase64 = base64::encode(bytecode);
 
-        contract.post_quickjs_bytecode(bytecodebase64);
+        contract.post_quickjs_bytecode(bytecodebase64, Some(hash.clone()));
+        assert_eq!(Some(hash), contract.get_js_code_hash());
         let metadata = contract.nft_metadata();
         assert_eq!(
             "WebAssembly Music by Peter Salomonsen".to_string(),
@@ -570,6 +556,7 @@ mod tests {
         }
         "
             .to_string(),
+            None,
         );
 
         contract.call_js_func("get_supply_for_bob".to_string());
@@ -600,7 +587,7 @@ mod tests {
         );
         let bytecodebase64 = base64::encode(bytecode);
 
-        contract.post_quickjs_bytecode(bytecodebase64);
+        contract.post_quickjs_bytecode(bytecodebase64, None);
         contract.post_content(
             "/serviceworker.js".to_string(),
             base64::encode("print('serviceworker');".to_string()),
@@ -740,6 +727,7 @@ mod tests {
         }
         "
             .to_string(),
+            None,
         );
 
         set_input("{\"from_index\": 0}".try_into().unwrap());
@@ -777,6 +765,7 @@ mod tests {
         }
         "
             .to_string(),
+            None,
         );
 
         set_attached_deposit(NearToken::from_yoctonear(1960000000000000000000));
@@ -805,11 +794,9 @@ mod tests {
                     description: 'test_description'
                 });
             }
-            export function nft_payout() {
-                const args = JSON.parse(env.input());
-                const balance = BigInt(args.balance);
+            export function nft_payout(token_id, balance, max_len_payout) {
                 const payout = {};
-                const token_owner_id = JSON.parse(env.nft_token(args.token_id)).owner_id;
+                const token_owner_id = JSON.parse(env.nft_token(token_id)).owner_id;
                 const contract_owner = env.contract_owner();
               
                 const addPayout = (account, amount) => {
@@ -825,6 +812,7 @@ mod tests {
             }              
         "
             .to_string(),
+            None,
         );
 
         set_attached_deposit(NearToken::from_yoctonear(2000000000000000000000));
@@ -832,8 +820,7 @@ mod tests {
         let token_id = "5544332".to_string();
         contract.nft_mint(token_id.to_owned(), alice());
 
-        set_input("{\"token_id\": \"5544332\", \"balance\": \"1000000000000000000000000\",\"max_len_payout\": \"3\"}".try_into().unwrap());
-        let ret = contract.nft_payout("1".to_string(), U128(10000_0000000000_0000000000), Some(3));
+        let ret = contract.nft_payout(token_id, U128(10000_0000000000_0000000000), Some(3));
         assert_eq!(
             U128(2000_0000000000_0000000000).0,
             ret.payout.get(&contract.tokens.owner_id).unwrap().0
@@ -862,6 +849,7 @@ mod tests {
         }
         "
             .to_string(),
+            None,
         );
         contract.post_content(
             "/files/testfile.js".to_string(),
@@ -885,12 +873,46 @@ mod tests {
         }
         "
             .to_string(),
+            None,
         );
 
         contract.call_js_func("get_contract_owner".to_string());
         assert_latest_return_value_string_eq(contract.tokens.owner_id.to_string());
     }
 
+    #[test]
+    fn test_staged_javascript_upgrade() {
+        setup_test_env();
+        set_current_account_id(bob());
+        set_predecessor_account_id(bob());
+
+        let mut contract = Contract::new();
+        contract.post_javascript(
+            "export function get_version() { env.value_return('1'); }".to_string(),
+            None,
+        );
+        let previous_hash = contract.get_js_code_hash().unwrap();
+
+        let staged_hash = contract.stage_javascript(
+            "
+        export function get_version() { env.value_return('2'); }
+        export function migrate(previous_code_hash) {
+            env.value_return('migrated from ' + previous_code_hash);
+        }
+        "
+            .to_string(),
+            None,
+        );
+        contract.call_js_func("get_version".to_string());
+        assert_latest_return_value_string_eq("1".to_string());
+
+        assert_eq!(staged_hash, contract.activate_javascript());
+        assert_latest_return_value_string_eq(format!("migrated from {}", previous_hash));
+        assert_eq!(Some(staged_hash), contract.get_js_code_hash());
+        contract.call_js_func("get_version".to_string());
+        assert_latest_return_value_string_eq("2".to_string());
+    }
+
     #[test]
     fn test_nft_token() {
         setup_test_env();
@@ -913,6 +935,7 @@ mod tests {
         }
         "
             .to_string(),
+            None,
         );
         set_attached_deposit(NearToken::from_yoctonear(1860000000000000000000));
         contract.nft_mint("1".to_string(), bob());
@@ -943,6 +966,7 @@ mod tests {
             }
         "
             .to_string(),
+            None,
         );
 
         set_attached_deposit(NearToken::from_yoctonear(2080000000000000000000));
@@ -94,7 +94,7 @@ export function nft_mint() {
       BigInt(totalStorageBytes) * BigInt("10000000000000000000");
     const attached = env.attached_deposit();
 
-    if (BigInt(attached) < storageCost) {
+    if (attached < storageCost) {
       env.panic(
         `Insufficient storage deposit. Required: ${storageCost} yoctoNEAR (${totalStorageBytes} bytes), ` +
           `Attached: ${attached} yoctoNEAR`,
@@ -129,7 +129,7 @@ export function nft_mint() {
  */
 export function nft_token() {
   const { token_id } = JSON.parse(env.input());
-  const owner_id = env.storage_read(`nft:${token_id}`);
+  const owner_id = env.storage_read_string(`nft:${token_id}`);
 
   if (!owner_id) {
     env.value_return("null");
@@ -165,7 +165,7 @@ export function nft_token() {
  */
 export function get_encryption_pubkey() {
   const { token_id } = JSON.parse(env.input());
-  const pubkey = env.storage_read(`encryption_key:${token_id}`);
+  const pubkey = env.storage_read_string(`encryption_key:${token_id}`);
 
   if (!pubkey) {
     env.value_return("null");
@@ -182,11 +182,11 @@ export function get_encryption_pubkey() {
 export function get_encrypted_content_data() {
   const { token_id } = JSON.parse(env.input());
 
-  const encrypted_content = env.storage_read(`locked-content:${token_id}`);
-  const encrypted_scalar = env.storage_read(`encrypted-scalar:${token_id}`);
-  const c1 = env.storage_read(`elgamal-ciphertext-c1:${token_id}`);
-  const c2 = env.storage_read(`elgamal-ciphertext-c2:${token_id}`);
-  const owner_pubkey = env.storage_read(`owner-pubkey:${token_id}`);
+  const encrypted_content = env.storage_read_string(`locked-content:${token_id}`);
+  const encrypted_scalar = env.storage_read_string(`encrypted-scalar:${token_id}`);
+  const c1 = env.storage_read_string(`elgamal-ciphertext-c1:${token_id}`);
+  const c2 = env.storage_read_string(`elgamal-ciphertext-c2:${token_id}`);
+  const owner_pubkey = env.storage_read_string(`owner-pubkey:${token_id}`);
 
   if (!encrypted_content) {
     env.value_return("null");
@@ -245,7 +245,7 @@ export function list_for_sale() {
  */
 export function get_listing() {
   const { token_id } = JSON.parse(env.input());
-  const listing = env.storage_read(`listing:${token_id}`);
+  const listing = env.storage_read_string(`listing:${token_id}`);
 
   if (!listing) {
     env.value_return("null");
@@ -263,7 +263,7 @@ export function cancel_listing() {
   const { token_id } = JSON.parse(env.input());
   const caller = env.signer_account_id();
 
-  const listing_data = env.storage_read(`listing:${token_id}`);
+  const listing_data = env.storage_read_string(`listing:${token_id}`);
   if (!listing_data) {
     env.panic("Token is not listed for sale");
   }
@@ -284,7 +284,7 @@ export function cancel_listing() {
  */
 export function get_escrow() {
   const { token_id } = JSON.parse(env.input());
-  const escrow = env.storage_read(`escrow:${token_id}`);
+  const escrow = env.storage_read_string(`escrow:${token_id}`);
 
   if (!escrow) {
     env.value_return("null");
@@ -302,7 +302,7 @@ export function cancel_purchase() {
   const { token_id } = JSON.parse(env.input());
   const caller = env.signer_account_id();
 
-  const escrow_data = env.storage_read(`escrow:${token_id}`);
+  const escrow_data = env.storage_read_string(`escrow:${token_id}`);
   if (!escrow_data) {
     env.panic("No pending purchase for this token");
   }
@@ -343,7 +343,7 @@ export function buy() {
   }
 
   // Check listing exists
-  const listing_data = env.storage_read(`listing:${token_id}`);
+  const listing_data = env.storage_read_string(`listing:${token_id}`);
   if (!listing_data) {
     env.panic("Token is not listed for sale");
   }
@@ -352,7 +352,7 @@ export function buy() {
 
   // Verify attached deposit matches price
   const attached = env.attached_deposit();
-  if (attached !== listing.price) {
+  if (attached !== BigInt(listing.price)) {
     env.panic(`Must attach exactly ${listing.price} yoctoNEAR`);
   }
 
@@ -402,7 +402,7 @@ export function complete_sale() {
   const caller = env.signer_account_id();
 
   // Get escrow record
-  const escrow_data = env.storage_read(`escrow:${token_id}`);
+  const escrow_data = env.storage_read_string(`escrow:${token_id}`);
   if (!escrow_data) {
     env.panic("No pending sale for this token");
   }
@@ -420,9 +420,9 @@ export function complete_sale() {
   }
 
   // Get old ciphertext from storage
-  const old_c1 = env.storage_read(`elgamal-ciphertext-c1:${token_id}`);
-  const old_c2 = env.storage_read(`elgamal-ciphertext-c2:${token_id}`);
-  const old_pk = env.storage_read(`owner-pubkey:${token_id}`);
+  const old_c1 = env.storage_read_string(`elgamal-ciphertext-c1:${token_id}`);
+  const old_c2 = env.storage_read_string(`elgamal-ciphertext-c2:${token_id}`);
+  const old_pk = env.storage_read_string(`owner-pubkey:${token_id}`);
 
   if (!old_c1 || !old_c2 || !old_pk) {
     env.panic("Original ciphertext not found");
@@ -1,6 +1,7 @@
 use near_sdk::borsh::{BorshDeserialize, BorshSerialize};
-use near_sdk::near_bindgen;
-use quickjs_rust_near::jslib::{js_call_function, load_js_bytecode};
+use near_sdk::{env, near_bindgen};
+use quickjs_rust_near::jslib::load_js_bytecode;
+use std::ffi::{c_char, CStr};
 
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, Default)]
@@ -11,8 +12,14 @@ pub struct Contract {}
 impl Contract {
     pub fn some_js_function(&self) {
         unsafe {
-            let jsmod = load_js_bytecode(123456789 as *const u8, 987654321);
-            js_call_function(jsmod, 456123987 as i32);
+            let jsmod = load_js_bytecode(123456789 as *const u8, 987654321)
+                .unwrap_or_else(|err| env::panic_str(&err.to_string()));
+            let function_name = CStr::from_ptr(456123987 as *const c_char)
+                .to_str()
+                .unwrap_or_else(|err| env::panic_str(&err.to_string()));
+            jsmod
+                .call_function(function_name)
+                .unwrap_or_else(|err| env::panic_str(&err.to_string()));
         }
     }
 }
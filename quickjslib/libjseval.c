@@ -50,7 +50,7 @@ void js_std_loop_no_os(JSContext *ctx)
         {
             if (err < 0)
             {
-                printf("%s\n", JS_ToCString(ctx, JS_GetException(ctx1)));
+                fprintf(stderr, "%s\n", JS_ToCString(ctx, JS_GetException(ctx1)));
             }
             break;
         }
@@ -69,7 +69,7 @@ int js_eval(const char *filename, const char *source, int module)
 
     if (JS_IsException(val) || JS_IsError(ctx, val))
     {
-        printf("%s\n", JS_ToCString(ctx, JS_GetException(ctx)));
+        fprintf(stderr, "%s\n", JS_ToCString(ctx, JS_GetException(ctx)));
     }
     js_std_loop_no_os(ctx);
     return JS_VALUE_GET_INT(val);
@@ -87,7 +87,7 @@ uint8_t *js_compile_to_bytecode(const char *filename, const char *source, size_t
 
     if (JS_IsException(obj))
     {
-        printf("%s\n", JS_ToCString(ctx, JS_GetException(ctx)));
+        fprintf(stderr, "%s\n", JS_ToCString(ctx, JS_GetException(ctx)));
     }
     return JS_WriteObject(ctx, out_buf_len, obj, JS_WRITE_OBJ_BYTECODE);
 }
@@ -100,51 +100,94 @@ JSValue js_eval_bytecode(const uint8_t *buf, size_t buf_len)
     val = JS_EvalFunction(ctx, obj);
     if (JS_IsException(val))
     {
-        printf("%s\n", JS_ToCString(ctx, JS_GetException(ctx)));
+        fprintf(stderr, "%s\n", JS_ToCString(ctx, JS_GetException(ctx)));
     }
     js_std_loop_no_os(ctx);
     return val;
 }
 
 
-JSValue js_load_bytecode(const uint8_t *buf, size_t buf_len)
+/* Loads module bytecode, leaving errors to the caller. Returns the promise of the module
+   namespace, or JS_EXCEPTION if the bytecode can not be read. */
+JSValue js_load_module_bytecode(const uint8_t *buf, size_t buf_len)
 {
-    JSValue module_obj;
+    JSValue module_obj, eval_result, load_module_promise;
     JSAtom module_name;
-    JSValue load_module_promise;
     const char *module_name_str;
 
     module_obj = JS_ReadObject(ctx, buf, buf_len, JS_READ_OBJ_BYTECODE);
-    JS_EvalFunction(ctx, module_obj);
+    if (JS_IsException(module_obj))
+        return module_obj;
+    if (JS_VALUE_GET_TAG(module_obj) != JS_TAG_MODULE)
+    {
+        JS_FreeValue(ctx, module_obj);
+        return JS_ThrowTypeError(ctx, "bytecode is not a module");
+    }
     module_name = JS_GetModuleName(ctx, JS_VALUE_GET_PTR(module_obj));
-    module_name_str = JS_AtomToCString(ctx, module_name);
+    eval_result = JS_EvalFunction(ctx, module_obj);
+    if (JS_IsException(eval_result))
+    {
+        JS_FreeAtom(ctx, module_name);
+        return eval_result;
+    }
+    JS_FreeValue(ctx, eval_result);
 
+    module_name_str = JS_AtomToCString(ctx, module_name);
+    JS_FreeAtom(ctx, module_name);
     load_module_promise = JS_LoadModule(ctx, "", module_name_str);
-    js_std_loop_no_os(ctx);
     JS_FreeCString(ctx, module_name_str);
-
-    return JS_PromiseResult(ctx, load_module_promise);
+    return load_module_promise;
 }
 
-JSValue js_get_promise_result(JSValue promise)
+/* Reads module bytecode for a module loader. Returns NULL with a pending exception if the
+   bytecode can not be read or is not a module. */
+JSModuleDef *js_read_module_bytecode(JSContext *ctx, const uint8_t *buf, size_t buf_len)
 {
-    JSValue promise_result = JS_PromiseResult(ctx, promise);
-    return promise_result;
+    JSValue module_obj;
+    JSModuleDef *m;
+
+    module_obj = JS_ReadObject(ctx, buf, buf_len, JS_READ_OBJ_BYTECODE);
+    if (JS_IsException(module_obj))
+        return NULL;
+    if (JS_VALUE_GET_TAG(module_obj) != JS_TAG_MODULE)
+    {
+        JS_FreeValue(ctx, module_obj);
+        JS_ThrowTypeError(ctx, "bytecode is not a module");
+        return NULL;
+    }
+    /* the module is referenced by the context, so the value can be freed */
+    m = JS_VALUE_GET_PTR(module_obj);
+    JS_FreeValue(ctx, module_obj);
+    return m;
 }
 
-JSValue js_call_function(JSValue mod_obj, const char *function_name)
+/* Checks that buf is module bytecode this version of QuickJS can read, whose header check rejects
+   bytecode of other versions. It is read in a runtime of its own that is freed afterwards, so the
+   global runtime and context are left as they are. Returns 1 if the bytecode is valid, or else 0
+   with the error written to error_buf. */
+int js_validate_module_bytecode(const uint8_t *buf, size_t buf_len, char *error_buf, size_t error_buf_len)
 {
-    JSValue fun_obj, val;
+    JSRuntime *validate_rt = JS_NewRuntime();
+    JSContext *validate_ctx = JS_NewContext(validate_rt);
+    JSModuleDef *m = js_read_module_bytecode(validate_ctx, buf, buf_len);
 
-    fun_obj = JS_GetPropertyStr(ctx, mod_obj, function_name);
-
-    val = JS_Call(ctx, fun_obj, mod_obj, 0, NULL);
-    if (JS_IsException(val))
+    if (m == NULL)
     {
-        printf("%s\n", JS_ToCString(ctx, JS_GetException(ctx)));
+        JSValue exception = JS_GetException(validate_ctx);
+        const char *message = JS_ToCString(validate_ctx, exception);
+        snprintf(error_buf, error_buf_len, "%s", message ? message : "invalid bytecode");
+        JS_FreeCString(validate_ctx, message);
+        JS_FreeValue(validate_ctx, exception);
     }
-    js_std_loop_no_os(ctx);
-    return val;
+    JS_FreeContext(validate_ctx);
+    JS_FreeRuntime(validate_rt);
+    return m != NULL;
+}
+
+JSValue js_get_promise_result(JSValue promise)
+{
+    JSValue promise_result = JS_PromiseResult(ctx, promise);
+    return promise_result;
 }
 
 void create_env()
@@ -159,6 +202,25 @@ void js_add_host_function(const char *name, JSCFunction *func, int length)
     JS_SetPropertyStr(ctx, env, name, JS_NewCFunction(ctx, func, name, length));
 }
 
+void js_add_host_function_magic(const char *name, JSCFunctionMagic *func, int length, int magic)
+{
+    JS_SetPropertyStr(ctx, env, name, JS_NewCFunctionMagic(ctx, func, name, length, JS_CFUNC_generic_magic, magic));
+}
+
+/* Adds `func` as the method `name` of the global object `object_name`, such as `console`,
+   creating the object if it does not exist yet. */
+void js_add_global_method_magic(const char *object_name, const char *name, JSCFunctionMagic *func, int length, int magic)
+{
+    JSValue obj = JS_GetPropertyStr(ctx, global_obj, object_name);
+    if (JS_IsUndefined(obj))
+    {
+        obj = JS_NewObject(ctx);
+        JS_SetPropertyStr(ctx, global_obj, object_name, JS_DupValue(ctx, obj));
+    }
+    JS_SetPropertyStr(ctx, obj, name, JS_NewCFunctionMagic(ctx, func, name, length, JS_CFUNC_generic_magic, magic));
+    JS_FreeValue(ctx, obj);
+}
+
 JSValue js_get_property(JSValue obj, const char *name)
 {
     return JS_GetPropertyStr(ctx, obj, name);
@@ -172,4 +234,40 @@ const char *js_get_string(JSValue val)
 JSContext *get_js_context()
 {
     return ctx;
-}
\ No newline at end of file
+}
+
+void js_free_value(JSContext *ctx, JSValue val)
+{
+    JS_FreeValue(ctx, val);
+}
+
+JSValue js_dup_value(JSContext *ctx, JSValue val)
+{
+    return JS_DupValue(ctx, val);
+}
+
+/* error_kind: 0 = Error, 1 = TypeError, 2 = RangeError */
+JSValue js_throw_error(JSContext *ctx, int error_kind, const char *message)
+{
+    switch (error_kind)
+    {
+    case 1:
+        return JS_ThrowTypeError(ctx, "%s", message);
+    case 2:
+        return JS_ThrowRangeError(ctx, "%s", message);
+    default:
+        return JS_ThrowPlainError(ctx, "%s", message);
+    }
+}
+
+JSValue js_new_uint8_array(JSContext *ctx, const uint8_t *buf, size_t len)
+{
+    JSValue array_buffer, uint8_array_ctor, uint8_array;
+
+    array_buffer = JS_NewArrayBufferCopy(ctx, buf, len);
+    uint8_array_ctor = JS_GetPropertyStr(ctx, global_obj, "Uint8Array");
+    uint8_array = JS_CallConstructor(ctx, uint8_array_ctor, 1, &array_buffer);
+    JS_FreeValue(ctx, uint8_array_ctor);
+    JS_FreeValue(ctx, array_buffer);
+    return uint8_array;
+}
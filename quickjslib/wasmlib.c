@@ -12,14 +12,51 @@ extern JSContext * get_js_context();
 extern uint64_t js_get_property(uint64_t obj, const char *name);
 extern const char *js_get_string(uint64_t val);
 extern uint64_t js_eval_bytecode(const char *buf, unsigned long buf_len);
-extern uint64_t js_load_bytecode(const char *buf, unsigned long buf_len);
-extern uint64_t js_call_function(uint64_t mod_obj, const char * function_name);
 extern uint64_t js_get_promise_result(uint64_t promise);
 extern unsigned long js_compile_to_bytecode(char *filename, char *source, unsigned long *buf_len, int module);
 extern void js_std_loop_no_os(JSContext *ctx);
 extern void js_add_host_function(const char *name, JSCFunction *func, int length);
 extern void js_call_host_async(JSValue params, JSValue *resolving_functions);
 
+/* The module loading and function calls of this library, which print exceptions rather than
+   returning them. The contract library only loads modules with js_load_module_bytecode, which
+   leaves errors to the caller. */
+static JSValue js_load_bytecode(const uint8_t *buf, size_t buf_len)
+{
+    JSContext *ctx = get_js_context();
+    JSValue module_obj;
+    JSAtom module_name;
+    JSValue load_module_promise;
+    const char *module_name_str;
+
+    module_obj = JS_ReadObject(ctx, buf, buf_len, JS_READ_OBJ_BYTECODE);
+    JS_EvalFunction(ctx, module_obj);
+    module_name = JS_GetModuleName(ctx, JS_VALUE_GET_PTR(module_obj));
+    module_name_str = JS_AtomToCString(ctx, module_name);
+
+    load_module_promise = JS_LoadModule(ctx, "", module_name_str);
+    js_std_loop_no_os(ctx);
+    JS_FreeCString(ctx, module_name_str);
+
+    return JS_PromiseResult(ctx, load_module_promise);
+}
+
+static JSValue js_call_function(JSValue mod_obj, const char *function_name)
+{
+    JSContext *ctx = get_js_context();
+    JSValue fun_obj, val;
+
+    fun_obj = JS_GetPropertyStr(ctx, mod_obj, function_name);
+
+    val = JS_Call(ctx, fun_obj, mod_obj, 0, NULL);
+    if (JS_IsException(val))
+    {
+        fprintf(stderr, "%s\n", JS_ToCString(ctx, JS_GetException(ctx)));
+    }
+    js_std_loop_no_os(ctx);
+    return val;
+}
+
 /* Host-provided wall clock in milliseconds (imported from the wasm host).
    The host JS thread is blocked while wasm runs, so a wall-clock deadline
    checked from the interrupt handler is the way to bound runaway guest code
@@ -133,7 +170,7 @@ uint64_t EMSCRIPTEN_KEEPALIVE eval_js_bytecode(const char *buf, unsigned long bu
 
 uint64_t EMSCRIPTEN_KEEPALIVE load_js_bytecode(const char *buf, unsigned long buf_len)
 {
-    return js_load_bytecode(buf, buf_len);
+    return js_load_bytecode((const uint8_t *)buf, buf_len);
 }
 
 uint64_t EMSCRIPTEN_KEEPALIVE call_js_function(uint64_t modobj, const char *name)
@@ -1,20 +1,53 @@
 use crate::viewaccesscontrol::{store_signing_key_for_account, verify_message_signed_by_account};
-use near_sdk::{base64, env};
-use std::ffi::{CStr, CString};
+use near_sdk::base64::{engine::general_purpose, Engine as _};
+use near_sdk::{base64, env, AccountId};
+use std::ffi::CString;
 use std::slice;
 
+use host::Bytes;
+
+pub mod borsh;
+pub mod budget;
+pub mod bytecode;
+pub mod console;
+pub mod error;
+pub mod events;
+pub mod host;
+pub mod jsvalue;
+pub mod modules;
+pub mod promises;
+pub mod random;
+pub mod runtime;
+pub mod sourcemap;
+pub mod storage;
+pub mod upgrade;
+
+pub use budget::ExecutionBudget;
+pub use bytecode::{bytecode_hash, validate_module_bytecode};
+pub use console::LogLevel;
+pub use error::{JsError, JsErrorKind};
+pub use events::add_event_functions;
+pub use host::{with_host_functions, HostError, HostErrorKind, HostFunctions, HostResult, Json};
+pub use jsvalue::JsValue;
+pub use modules::ModuleBundle;
+pub use promises::{add_promise_functions, add_promise_result_functions, js_callback};
+pub use runtime::RuntimeConfig;
+pub use sourcemap::SourceMap;
+pub use storage::add_storage_functions;
+pub use upgrade::CodeStorage;
+
 extern "C" {
     pub fn create_runtime();
-    fn js_eval(filename: i32, script: i32, is_module: i32) -> i32;
-    fn js_eval_bytecode(buf: *const u8, buf_len: usize) -> i64;
     fn js_compile_to_bytecode(filename: i32, source: i32, out_buf_len: i32, module: i32) -> i32;
-    pub fn js_load_bytecode(buf: *const u8, buf_len: usize) -> i64;
-    pub fn js_call_function(mod_obj: i64, function_name: i32) -> i64;
+    fn js_load_module_bytecode(buf: *const u8, buf_len: usize) -> i64;
+    fn js_std_loop_no_os(ctx: i32);
     pub fn js_get_property(val: i64, propertyname: i32) -> i64;
     pub fn js_get_string(val: i64) -> i32;
+    fn get_js_context() -> i32;
     fn create_env();
     fn js_add_host_function(name: i32, func: i32, length: i32);
     fn JS_ToCStringLen2(ctx: i32, value_len_ptr: i32, val: i64, b: i32) -> i32;
+    fn JS_FreeCString(ctx: i32, ptr: i32);
     fn JS_NewStringLen(ctx: i32, buf: i32, buf_len: usize) -> i64;
     fn JS_GetTypedArrayBuffer(
         ctx: i32,                       // QuickJS context (JSContext*)
@@ -33,25 +66,45 @@ extern "C" {
         data: *const u8,
         size: usize
     ) -> i64;
+    fn JS_Eval(ctx: i32, input: i32, input_len: usize, filename: i32, eval_flags: i32) -> i64;
+    fn JS_ReadObject(ctx: i32, buf: *const u8, buf_len: usize, flags: i32) -> i64;
+    fn JS_EvalFunction(ctx: i32, fun_obj: i64) -> i64;
+    fn JS_PromiseState(ctx: i32, promise: i64) -> i32;
+    fn JS_PromiseResult(ctx: i32, promise: i64) -> i64;
 }
 
+const JS_EVAL_TYPE_GLOBAL: i32 = 0;
+const JS_READ_OBJ_BYTECODE: i32 = 1;
+const JS_PROMISE_PENDING: i32 = 0;
+const JS_PROMISE_REJECTED: i32 = 2;
+
 pub const JS_UNDEFINED: i64 = 0x0000000300000000;
+pub const JS_NULL: i64 = 0x0000000200000000;
 pub const JS_FALSE: i64 = 0x0000000100000000;
 pub const JS_TRUE: i64 = 0x0000000100000001;
 
-pub fn arg_to_str(ctx: i32, arg_no: i32, argv: i32) -> String {
+/// The argument `arg_no` converted to a string. Fails with a `TypeError` if it can not be
+/// converted, such as a Symbol, or is not valid UTF-8.
+pub fn arg_to_str(ctx: i32, arg_no: i32, argv: i32) -> HostResult<String> {
     let mut value_len: usize = 0;
     let value_len_ptr: *mut usize = &mut value_len as *mut usize;
     let argv_ptr = (argv + (arg_no * 8)) as *const i64;
 
-    let value_string: String;
     let value_bytes: Vec<u8>;
     unsafe {
-        let value_ptr = JS_ToCStringLen2(ctx, value_len_ptr as i32, *argv_ptr, 0) as *const u8;
-        value_bytes = std::slice::from_raw_parts(value_ptr, value_len).to_vec();
+        let value_ptr = JS_ToCStringLen2(ctx, value_len_ptr as i32, *argv_ptr, 0);
+        if value_ptr == 0 {
+            return Err(HostError::type_error(format!(
+                "argument {}: can not be converted to a string",
+                arg_no
+            )));
+        }
+        value_bytes = std::slice::from_raw_parts(value_ptr as *const u8, value_len).to_vec();
+        JS_FreeCString(ctx, value_ptr);
     }
-    value_string = String::from_utf8(value_bytes).unwrap();
-    return value_string;
+    String::from_utf8(value_bytes).map_err(|_| {
+        HostError::type_error(format!("argument {}: expected a valid UTF-8 string", arg_no))
+    })
 }
 
 pub fn arg_to_number(_ctx: i32, arg_no: i32, argv: i32) -> i64 {
@@ -61,49 +114,9 @@ pub fn arg_to_number(_ctx: i32, arg_no: i32, argv: i32) -> i64 {
     }
 }
 
-unsafe fn JS_GetArrayBufferRawData(ctx: i32, jsvalue: i64) -> *mut u8 {
-    let mut size: usize = 0;
-    let data_ptr = JS_GetArrayBuffer(ctx, &mut size as *mut usize, jsvalue);
-    return data_ptr;
-}
-
 pub fn arg_to_u8_array(ctx: i32, arg_no: i32, argv: i32) -> Vec<u8> {
-    let mut byte_offset: usize = 0;
-    let mut byte_length: usize = 0;
-    let mut bytes_per_element: usize = 0;
-    let argv_ptr = (argv + (arg_no * 8)) as *const i64; // Access the argument as JSValue (i64)
-
-    let array_data: Vec<u8>;
-    unsafe {
-        // Retrieve the typed array object (Uint8Array)
-        let typed_array: i64 = *argv_ptr; // JSValue as a 64-bit integer
-
-        // Call JS_GetTypedArrayBuffer, which returns another JSValue representing the buffer
-        let buffer_jsvalue = JS_GetTypedArrayBuffer(
-            ctx,
-            typed_array,                          // Pass the JSValue (i64)
-            &mut byte_offset as *mut usize,       // Offset pointer
-            &mut byte_length as *mut usize,       // Length pointer
-            &mut bytes_per_element as *mut usize, // Bytes per element pointer
-        );
-
-        // Now we need to extract the raw buffer from this new JSValue (buffer_jsvalue)
-        if buffer_jsvalue != 0 && byte_length > 0 {
-            // Assume we have a function to get the raw buffer from JSValue (this needs to be implemented)
-            let data_ptr = JS_GetArrayBufferRawData(ctx, buffer_jsvalue);
-
-            // Convert the raw pointer into a Vec<u8>
-            if !data_ptr.is_null() {
-                array_data = std::slice::from_raw_parts(data_ptr, byte_length).to_vec();
-            } else {
-                array_data = Vec::new(); // Handle null case gracefully
-            }
-        } else {
-            array_data = Vec::new(); // If the returned value is invalid or length is zero
-        }
-    }
-
-    return array_data;
+    let argv_ptr = (argv + (arg_no * 8)) as *const i64;
+    unsafe { JsValue::from_borrowed(ctx, *argv_ptr).to_bytes() }
 }
 
 /**
@@ -122,21 +135,24 @@ static JSValue near_value_return(JSContext *ctx, JSValueConst this_val, int argc
 }
 */
 
+/// Returns an `ArrayBuffer` or typed array as it is, and any other value as a string.
 fn value_return_func(ctx: i32, _this_val: i64, _argc: i32, argv: i32) -> i64 {
-    let mut value_len = 0;
-    let value_len_ptr: *mut usize = &mut value_len as *mut usize;
     let argv_ptr = argv as *const i64;
+    let value = unsafe { JsValue::from_borrowed(ctx, *argv_ptr) };
 
-    unsafe {
-        let value_ptr = JS_ToCStringLen2(ctx, value_len_ptr as i32, *argv_ptr, 0) as *const u8;
-        near_sdk::env::value_return(std::slice::from_raw_parts(value_ptr, value_len));
+    match value.try_to_bytes() {
+        Some(bytes) => near_sdk::env::value_return(&bytes),
+        None => near_sdk::env::value_return(value.to_string().as_bytes()),
     }
 
     return JS_UNDEFINED;
 }
 
+/// The input decoded as UTF-8, or `null` if the contract was called without input.
 fn input_func(ctx: i32, _this_val: i64, _argc: i32, _argv: i32) -> i64 {
-    let inputbytes = near_sdk::env::input().unwrap();
+    let Some(inputbytes) = near_sdk::env::input() else {
+        return JS_NULL;
+    };
     let inputbytes_ptr = inputbytes.as_ptr();
 
     unsafe {
@@ -144,6 +160,14 @@ fn input_func(ctx: i32, _this_val: i64, _argc: i32, _argv: i32) -> i64 {
     }
 }
 
+/// The input as a `Uint8Array`, or `null` if the contract was called without input.
+fn input_bytes_func(_ctx: i32, _this_val: i64, _argc: i32, _argv: i32) -> i64 {
+    match near_sdk::env::input() {
+        Some(inputbytes) => JsValue::from_bytes(&inputbytes).into_raw(),
+        None => JS_NULL,
+    }
+}
+
 fn signer_account_id_func(ctx: i32, _this_val: i64, _argc: i32, _argv: i32) -> i64 {
     unsafe {
         let signer_account_id = near_sdk::env::signer_account_id().to_string();
@@ -164,18 +188,27 @@ fn predecessor_account_id_func(ctx: i32, _this_val: i64, _argc: i32, _argv: i32)
     }
 }
 
-fn attached_deposit_func(ctx: i32, _this_val: i64, _argc: i32, _argv: i32) -> i64 {
-    unsafe {
-        let attached_deposit = near_sdk::env::attached_deposit().as_yoctonear().to_string();
-        let attached_deposit_ptr = attached_deposit.as_ptr();
-        return JS_NewStringLen(ctx, attached_deposit_ptr as i32, attached_deposit.len());
-    }
+/// The attached deposit in yoctoNEAR, as a BigInt.
+fn attached_deposit_func(_ctx: i32, _this_val: i64, _argc: i32, _argv: i32) -> i64 {
+    JsValue::from_u128(near_sdk::env::attached_deposit().as_yoctonear()).into_raw()
 }
 
 fn verify_signed_message_func(ctx: i32, _this_val: i64, _argc: i32, argv: i32) -> i64 {
-    let message = arg_to_str(ctx, 0, argv);
-    let signature = arg_to_str(ctx, 1, argv);
-    let account = arg_to_str(ctx, 2, argv);
+    let (message, signature, account) = match (
+        arg_to_str(ctx, 0, argv),
+        arg_to_str(ctx, 1, argv),
+        arg_to_str(ctx, 2, argv),
+    ) {
+        (Ok(message), Ok(signature), Ok(account)) => (message, signature, account),
+        (Err(err), _, _) | (_, Err(err), _) | (_, _, Err(err)) => return err.throw(ctx),
+    };
+    let signature_bytes = general_purpose::STANDARD.decode(&signature);
+    if !signature_bytes.is_ok_and(|signature_bytes| signature_bytes.len() == 64) {
+        return HostError::type_error(
+            "verify_signed_message: expected a base64 encoded 64 byte signature",
+        )
+        .throw(ctx);
+    }
     if verify_message_signed_by_account(message, signature, account) {
         return JS_TRUE;
     } else {
@@ -202,6 +235,39 @@ pub unsafe fn add_function_to_js(
     );
 }
 
+/// Adds a closure capturing nothing to the `env` object of the runtime being set up. Unlike
+/// functions added through [`HostFunctions`], these stay available for the whole runtime.
+macro_rules! add_env_function {
+    ($name:literal, $func:expr) => {{
+        fn raw(ctx: i32, _this_val: i64, argc: i32, argv: i32) -> i64 {
+            host::call_host_fn($name, &$func, ctx, argc, argv)
+        }
+        add_function_to_js($name, raw, host::arity(&$func));
+    }};
+}
+
+/// The 64 byte secp256k1 public key that signed `hash`, or `None` if the signature is invalid.
+fn ecrecover(
+    hash: Vec<u8>,
+    signature: Vec<u8>,
+    v: u32,
+    malleability_flag: Option<bool>,
+) -> HostResult<Option<Vec<u8>>> {
+    if hash.len() != 32 {
+        return Err(HostError::range_error("expected a 32 byte hash"));
+    }
+    if signature.len() != 64 {
+        return Err(HostError::range_error("expected a 64 byte signature"));
+    }
+    if v > 3 {
+        return Err(HostError::range_error("expected a recovery id from 0 to 3"));
+    }
+    Ok(
+        env::ecrecover(&hash, &signature, v as u8, malleability_flag.unwrap_or(false))
+            .map(|public_key| public_key.to_vec()),
+    )
+}
+
 pub fn to_js_string(ctx: i32, str: String) -> i64 {
     let str_ptr = str.as_ptr();
 
@@ -210,39 +276,35 @@ pub fn to_js_string(ctx: i32, str: String) -> i64 {
     }
 }
 
-unsafe fn setup_quickjs() {
+unsafe fn setup_quickjs(config: &RuntimeConfig) {
     create_runtime();
+    config.apply(get_js_context());
     create_env();
+    console::install_console();
+    random::install_random();
 
     add_function_to_js(
         "panic",
         |ctx: i32, _this_val: i64, _argc: i32, argv: i32| -> i64 {
-            let mut value_len = 0;
-            let value_len_ptr: *mut usize = &mut value_len;
-            let argv_ptr = argv as *const i64;
-
-            let ptr = JS_ToCStringLen2(ctx, value_len_ptr as i32, *argv_ptr, 0) as *const i8;
-            let str = CStr::from_ptr(ptr).to_str().unwrap();
-            near_sdk::env::panic_str(str);
+            match arg_to_str(ctx, 0, argv) {
+                Ok(message) => near_sdk::env::panic_str(&message),
+                Err(err) => err.throw(ctx),
+            }
         },
         1,
     );
     add_function_to_js("value_return", value_return_func, 1);
     add_function_to_js("input", input_func, 1);
+    add_function_to_js("input_bytes", input_bytes_func, 0);
     add_function_to_js("attached_deposit", attached_deposit_func, 1);
     add_function_to_js(
         "block_timestamp_ms",
         |_ctx: i32, _this_val: i64, _argc: i32, _argv: i32| -> i64 {
             // QuickJS in WASM uses NaN-boxed JSValues (uint64 with tag in the
             // upper 32 bits). A raw i64 returned here would be interpreted as
-            // a malformed float64 (NaN-ish), so we must encode the timestamp
-            // as a properly tagged float64 per quickjs.h __JS_NewFloat64:
-            //   JSValue = double_bits - (JS_FLOAT64_TAG_ADDEND << 32)
-            //   JS_FLOAT64_TAG_ADDEND = 0x7ff80000 - JS_TAG_FIRST + 1
-            //                         = 0x7ff80000 - (-9) + 1 = 0x7ff8000a
-            const FLOAT64_TAG_ADDEND_SHIFTED: u64 = 0x7ff8000a_00000000;
-            let bits = (env::block_timestamp_ms() as f64).to_bits();
-            bits.wrapping_sub(FLOAT64_TAG_ADDEND_SHIFTED) as i64
+            // a malformed float64 (NaN-ish), so the timestamp must be encoded
+            // as a properly tagged float64.
+            JsValue::from_f64(env::block_timestamp_ms() as f64).into_raw()
         },
         0,
     );
@@ -259,27 +321,75 @@ unsafe fn setup_quickjs() {
     add_function_to_js("signer_account_id", signer_account_id_func, 1);
     add_function_to_js("verify_signed_message", verify_signed_message_func, 2);
     add_function_to_js("store_signing_key", store_signing_key_func, 1);
+    add_env_function!("signer_account_pk", || -> HostResult<String> {
+        Ok(String::from(&env::signer_account_pk()))
+    });
+    add_env_function!("block_height", || -> HostResult<u64> { Ok(env::block_height()) });
+    add_env_function!("epoch_height", || -> HostResult<u64> { Ok(env::epoch_height()) });
+    add_env_function!("account_balance", || -> HostResult<u128> {
+        Ok(env::account_balance().as_yoctonear())
+    });
+    add_env_function!("account_locked_balance", || -> HostResult<u128> {
+        Ok(env::account_locked_balance().as_yoctonear())
+    });
+    add_env_function!("storage_usage", || -> HostResult<u64> { Ok(env::storage_usage()) });
+    add_env_function!("prepaid_gas", || -> HostResult<u64> {
+        Ok(env::prepaid_gas().as_gas())
+    });
+    add_env_function!("used_gas", || -> HostResult<u64> { Ok(env::used_gas().as_gas()) });
+    add_env_function!("validator_stake", |account_id: AccountId| -> HostResult<u128> {
+        Ok(env::validator_stake(&account_id).as_yoctonear())
+    });
+    add_env_function!("validator_total_stake", || -> HostResult<u128> {
+        Ok(env::validator_total_stake().as_yoctonear())
+    });
+    add_env_function!("random_seed", || -> HostResult<Vec<u8>> { Ok(env::random_seed()) });
+    add_env_function!("keccak256", |value: Bytes| -> HostResult<Vec<u8>> {
+        Ok(env::keccak256(&value.0))
+    });
+    add_env_function!("keccak512", |value: Bytes| -> HostResult<Vec<u8>> {
+        Ok(env::keccak512(&value.0))
+    });
+    add_env_function!("ripemd160", |value: Bytes| -> HostResult<Vec<u8>> {
+        Ok(env::ripemd160_array(&value.0).to_vec())
+    });
+    add_env_function!("ecrecover", ecrecover);
+    add_env_function!("log_utf8", |message: String| -> HostResult<()> {
+        env::log_str(&message);
+        Ok(())
+    });
+    add_function_to_js("borsh_serialize", borsh::borsh_serialize_func, 2);
+    add_function_to_js("borsh_deserialize", borsh::borsh_deserialize_func, 2);
     add_function_to_js(
         "base64_encode",
         |ctx: i32, _this_val: i64, _argc: i32, argv: i32| -> i64 {
-            return to_js_string(ctx, base64::encode(arg_to_str(ctx, 0, argv)));
+            match arg_to_str(ctx, 0, argv) {
+                Ok(value) => to_js_string(ctx, base64::encode(value)),
+                Err(err) => err.throw(ctx),
+            }
         },
         1,
     );
     add_function_to_js(
         "sha256_utf8_to_base64",
         |ctx: i32, _this_val: i64, _argc: i32, argv: i32| -> i64 {
-            return to_js_string(
-                ctx,
-                base64::encode(near_sdk::env::sha256(arg_to_str(ctx, 0, argv).as_bytes())),
-            );
+            match arg_to_str(ctx, 0, argv) {
+                Ok(value) => {
+                    to_js_string(ctx, base64::encode(near_sdk::env::sha256(value.as_bytes())))
+                }
+                Err(err) => err.throw(ctx),
+            }
         },
         1,
     );
     add_function_to_js(
         "sha256_utf8",
         |ctx: i32, _this_val: i64, _argc: i32, argv: i32| -> i64 {
-            let message_sha256 = near_sdk::env::sha256(arg_to_str(ctx, 0, argv).as_bytes());
+            let message = match arg_to_str(ctx, 0, argv) {
+                Ok(message) => message,
+                Err(err) => return err.throw(ctx),
+            };
+            let message_sha256 = near_sdk::env::sha256(message.as_bytes());
             JS_NewArrayBufferCopy(
                 ctx,
                 message_sha256.as_ptr(),  // Pass the pointer to the data (const u8)
@@ -292,12 +402,18 @@ unsafe fn setup_quickjs() {
         "ed25519_verify",
         |ctx: i32, _this_val: i64, _argc: i32, argv: i32| -> i64 {
             let signature_vec = arg_to_u8_array(ctx, 0, argv);
-            let signature: &[u8; 64] = signature_vec[..].try_into().unwrap();
+            let Ok(signature) = <&[u8; 64]>::try_from(&signature_vec[..]) else {
+                return HostError::range_error("ed25519_verify: expected a 64 byte signature")
+                    .throw(ctx);
+            };
             let message_vec = arg_to_u8_array(ctx, 1, argv);
             let message: &[u8] = message_vec.as_slice();
 
             let public_key_vec = arg_to_u8_array(ctx, 2, argv);
-            let public_key: &[u8; 32] = public_key_vec[..].try_into().unwrap();
+            let Ok(public_key) = <&[u8; 32]>::try_from(&public_key_vec[..]) else {
+                return HostError::range_error("ed25519_verify: expected a 32 byte public key")
+                    .throw(ctx);
+            };
 
             let result = near_sdk::env::ed25519_verify(signature, message, public_key);
             if result {
@@ -310,42 +426,147 @@ unsafe fn setup_quickjs() {
     );
 }
 
-pub fn run_js(script: String) -> i32 {
-    let result: i32;
+/// Runs the jobs left pending by a successful evaluation, or takes the exception it threw.
+unsafe fn finish_evaluation(ctx: i32, result: JsValue) -> Result<JsValue, JsError> {
+    if result.is_exception() {
+        return Err(JsError::from_exception(ctx));
+    }
+    js_std_loop_no_os(ctx);
+    Ok(result)
+}
+
+pub fn run_js(script: String) -> Result<JsValue, JsError> {
+    run_js_with_config(script, &RuntimeConfig::default())
+}
+
+pub fn run_js_with_config(script: String, config: &RuntimeConfig) -> Result<JsValue, JsError> {
     let filename = CString::new("main.js").unwrap();
     let scriptstring = CString::new(script).unwrap();
 
     unsafe {
-        setup_quickjs();
-        result = js_eval(filename.as_ptr() as i32, scriptstring.as_ptr() as i32, 0);
+        setup_quickjs(config);
+        let ctx = get_js_context();
+        let result = JS_Eval(
+            ctx,
+            scriptstring.as_ptr() as i32,
+            scriptstring.as_bytes().len(),
+            filename.as_ptr() as i32,
+            JS_EVAL_TYPE_GLOBAL,
+        );
+        finish_evaluation(ctx, JsValue::from_raw(ctx, result))
     }
-    return result;
 }
 
-pub fn run_js_bytecode(bytecode: Vec<u8>) -> i64 {
-    let result: i64;
+pub fn run_js_bytecode(bytecode: Vec<u8>) -> Result<JsValue, JsError> {
+    run_js_bytecode_with_config(bytecode, &RuntimeConfig::default())
+}
 
+pub fn run_js_bytecode_with_config(
+    bytecode: Vec<u8>,
+    config: &RuntimeConfig,
+) -> Result<JsValue, JsError> {
     unsafe {
-        setup_quickjs();
-        result = js_eval_bytecode(bytecode.as_ptr(), bytecode.len());
+        setup_quickjs(config);
+        let ctx = get_js_context();
+        let function = JsValue::from_raw(
+            ctx,
+            JS_ReadObject(ctx, bytecode.as_ptr(), bytecode.len(), JS_READ_OBJ_BYTECODE),
+        );
+        let result = if function.is_exception() {
+            function
+        } else {
+            // JS_EvalFunction takes ownership of the function
+            JsValue::from_raw(ctx, JS_EvalFunction(ctx, function.into_raw()))
+        };
+        finish_evaluation(ctx, result)
+    }
+}
+
+/// Resolves `value` if it is a Promise, which must have settled once the pending jobs have run,
+/// as nothing else can settle it later. Any other value is returned as it is.
+pub(crate) fn await_promise(value: JsValue) -> Result<JsValue, JsError> {
+    let ctx = value.context();
+    unsafe {
+        let state = JS_PromiseState(ctx, value.as_raw());
+        if state < 0 {
+            return Ok(value);
+        }
+        if state == JS_PROMISE_PENDING {
+            return Err(JsError::new("Error", "promise was never settled"));
+        }
+        let result = JsValue::from_raw(ctx, JS_PromiseResult(ctx, value.as_raw()));
+        if state == JS_PROMISE_REJECTED {
+            return Err(JsError::from_thrown(&result));
+        }
+        Ok(result)
     }
-    return result;
 }
 
-pub fn load_js_bytecode(bytecode: *const u8, len: usize) -> i64 {
-    let result: i64;
+/// Loads the module in `bytecode` and returns its namespace object, from which
+/// exported functions can be called with [`JsValue::call_function`].
+pub fn load_js_bytecode(bytecode: *const u8, len: usize) -> Result<JsValue, JsError> {
+    load_js_bytecode_with_config(bytecode, len, &RuntimeConfig::default())
+}
 
+pub fn load_js_bytecode_with_config(
+    bytecode: *const u8,
+    len: usize,
+    config: &RuntimeConfig,
+) -> Result<JsValue, JsError> {
     unsafe {
-        setup_quickjs();
-        result = js_load_bytecode(bytecode, len);
+        setup_quickjs(config);
+        let ctx = get_js_context();
+        let promise = finish_evaluation(
+            ctx,
+            JsValue::from_raw(ctx, js_load_module_bytecode(bytecode, len)),
+        )?;
+        await_promise(promise)
+    }
+}
+
+/// Loads the entry module of `bundle`, resolving its imports from the other modules of the
+/// bundle, and returns its namespace object like [`load_js_bytecode`].
+pub fn load_js_bundle(bundle: &ModuleBundle) -> Result<JsValue, JsError> {
+    load_js_bundle_with_config(bundle, &RuntimeConfig::default())
+}
+
+pub fn load_js_bundle_with_config(
+    bundle: &ModuleBundle,
+    config: &RuntimeConfig,
+) -> Result<JsValue, JsError> {
+    let Some(bytecode) = bundle.entry_bytecode() else {
+        return Err(JsError::new(
+            "Error",
+            format!("could not load module '{}'", bundle.entry_module()),
+        ));
+    };
+    unsafe {
+        setup_quickjs(config);
+        let ctx = get_js_context();
+        modules::install_module_loader(ctx, bundle.clone());
+        let promise = finish_evaluation(
+            ctx,
+            JsValue::from_raw(ctx, js_load_module_bytecode(bytecode.as_ptr(), bytecode.len())),
+        )?;
+        await_promise(promise)
     }
-    return result;
 }
 
 pub fn compile_js(script: String, modulename: Option<String>) -> Vec<u8> {
+    compile_js_with_config(script, modulename, &RuntimeConfig::default())
+}
+
+/// Compiles `script` within the memory and stack limits of `config`, which large or deeply
+/// nested sources can exceed as well.
+pub fn compile_js_with_config(
+    script: String,
+    modulename: Option<String>,
+    config: &RuntimeConfig,
+) -> Vec<u8> {
     let result: Vec<u8>;
     unsafe {
         create_runtime();
+        config.apply(get_js_context());
         let mut out_buf_len: usize = 0;
         let out_buf_len_ptr: *mut usize = &mut out_buf_len;
         let is_module = if modulename.is_some() { 1 } else { 0 };
@@ -368,21 +589,33 @@ pub fn compile_js(script: String, modulename: Option<String>) -> Vec<u8> {
 
 #[cfg(test)]
 mod tests {
-    use super::{compile_js, js_get_property, js_get_string, run_js, run_js_bytecode};
+    use super::{
+        compile_js, load_js_bytecode, run_js, run_js_bytecode, run_js_with_config,
+        with_host_functions, HostResult, JsErrorKind, JsValue, RuntimeConfig,
+    };
     use crate::viewaccesscontrol::store_signing_key_for_account;
-    use near_sdk::{base64, env::sha256, NearToken};
+    use near_sdk::json_types::U128;
+    use near_sdk::{base64, env, env::sha256, AccountId, Gas, NearToken, PublicKey};
+    use std::cell::RefCell;
     use ed25519_dalek::{ed25519::signature::SignerMut, SigningKey};
 
     use quickjs_rust_near_testenv::testenv::{
-        alice, assert_latest_return_value_string_eq, set_attached_deposit, set_input, set_signer_account_id, setup_test_env
+        alice, assert_latest_return_value_eq, assert_latest_return_value_string_eq, bob,
+        clear_input, logs, set_account_balance, set_attached_deposit, set_block_height,
+        set_epoch_height, set_input, set_random_seed, set_signer_account_id,
+        set_signer_account_pk, set_used_gas, set_validator_stake, setup_test_env,
     };
-    use std::ffi::CStr;
+
+    const TO_HEX: &str = "const hex = (bytes) =>
+        Array.from(bytes).map((b) => b.toString(16).padStart(2, '0')).join('');";
 
     #[test]
     fn test_value_return_should_return_undefined() {
         setup_test_env();
         assert_eq!(
-            run_js("(env.value_return('hello') == undefined ? 1 : 0)".to_string()),
+            run_js("(env.value_return('hello') == undefined ? 1 : 0)".to_string())
+                .unwrap()
+                .to_i64(),
             1
         );
     }
@@ -391,15 +624,46 @@ mod tests {
     fn test_input_func() {
         setup_test_env();
         set_input("{\"a\":   \"b\"}".to_string().into_bytes());
-        run_js("env.value_return(JSON.stringify(JSON.parse(env.input())));".to_string());
+        run_js("env.value_return(JSON.stringify(JSON.parse(env.input())));".to_string()).unwrap();
         assert_latest_return_value_string_eq("{\"a\":\"b\"}".to_string());
     }
 
+    #[test]
+    fn test_input_bytes_func() {
+        setup_test_env();
+        set_input(vec![0, 159, 255]);
+        run_js("env.value_return(env.input_bytes())".to_string()).unwrap();
+        assert_latest_return_value_eq(&[0, 159, 255]);
+    }
+
+    #[test]
+    fn test_value_return_binary() {
+        setup_test_env();
+        run_js("env.value_return(new Uint8Array([1, 0, 255, 128]).subarray(1))".to_string())
+            .unwrap();
+        assert_latest_return_value_eq(&[0, 255, 128]);
+        run_js("env.value_return(new Uint8Array([200, 0]).buffer)".to_string()).unwrap();
+        assert_latest_return_value_eq(&[200, 0]);
+        run_js("env.value_return(42)".to_string()).unwrap();
+        assert_latest_return_value_string_eq("42".to_string());
+    }
+
+    #[test]
+    fn test_without_input() {
+        setup_test_env();
+        clear_input();
+        run_js(
+            "env.value_return(JSON.stringify([env.input(), env.input_bytes()]))".to_string(),
+        )
+        .unwrap();
+        assert_latest_return_value_string_eq("[null,null]".to_string());
+    }
+
     #[test]
     fn test_signer_account_id_func() {
         setup_test_env();
         set_signer_account_id(alice());
-        run_js("env.value_return(env.signer_account_id())".to_string());
+        run_js("env.value_return(env.signer_account_id())".to_string()).unwrap();
         assert_latest_return_value_string_eq(alice().to_string());
     }
 
@@ -408,8 +672,15 @@ mod tests {
         setup_test_env();
         set_attached_deposit(NearToken::from_near(20));
 
-        run_js("env.value_return(env.attached_deposit())".to_string());
-        assert_latest_return_value_string_eq(NearToken::from_near(20).as_yoctonear().to_string());
+        run_js(
+            "env.value_return(typeof env.attached_deposit() + ' ' + env.attached_deposit())"
+                .to_string(),
+        )
+        .unwrap();
+        assert_latest_return_value_string_eq(format!(
+            "bigint {}",
+            NearToken::from_near(20).as_yoctonear()
+        ));
     }
 
     #[test]
@@ -417,7 +688,7 @@ mod tests {
         setup_test_env();
         set_signer_account_id(alice());
         store_signing_key_for_account(24 * 60 * 60 * 1000);
-        run_js("env.value_return(env.verify_signed_message('invitation1','LtXiPcOxOC8n5/qiICscp3P5Ku8ymC3gj1eYJuq8GFR9co2pZYwbWLBiu5CrtVFtvmeWwMzOIkp4tJaosJ40Dg==', 'alice.near') ? 'valid' : 'invalid')".to_string());
+        run_js("env.value_return(env.verify_signed_message('invitation1','LtXiPcOxOC8n5/qiICscp3P5Ku8ymC3gj1eYJuq8GFR9co2pZYwbWLBiu5CrtVFtvmeWwMzOIkp4tJaosJ40Dg==', 'alice.near') ? 'valid' : 'invalid')".to_string()).unwrap();
         assert_latest_return_value_string_eq("valid".to_string());
     }
 
@@ -428,15 +699,197 @@ mod tests {
             "(function () { return {hello: 'world', thenumberis: 42}; })()".to_string(),
             None,
         );
-        let result = run_js_bytecode(bytecode);
-        unsafe {
-            assert_eq!(42, js_get_property(result, "thenumberis".as_ptr() as i32));
-            let stringjsval = js_get_property(result, "hello".as_ptr() as i32);
-            let str = CStr::from_ptr(js_get_string(stringjsval) as *const i8)
-                .to_str()
-                .unwrap();
-            assert_eq!("world", str);
-        }
+        let result = run_js_bytecode(bytecode).unwrap();
+        assert!(result.is_object());
+        let thenumberis = result.get_property("thenumberis");
+        assert!(thenumberis.is_number());
+        assert_eq!(42, thenumberis.to_i64());
+        let hello = result.get_property("hello");
+        assert!(hello.is_string());
+        assert_eq!("world", hello.to_string());
+    }
+
+    #[test]
+    fn test_jsvalue_conversions() {
+        setup_test_env();
+        let bytecode = compile_js(
+            "(function () { return {
+                list: [1, 'two', 3.5],
+                bytes: new Uint8Array([1, 2, 3, 4]).subarray(1, 3),
+                flag: true,
+                nested: { a: 'b' }
+            }; })()"
+                .to_string(),
+            None,
+        );
+        let result = run_js_bytecode(bytecode).unwrap();
+
+        let list = result.get_property("list");
+        assert!(list.is_array());
+        assert_eq!(3, list.len());
+        assert_eq!(1, list.get_index(0).to_i64());
+        assert_eq!("two", list.get_index(1).to_string());
+        assert_eq!(3.5, list.get_index(2).to_f64());
+        assert!(list.get_index(3).is_undefined());
+
+        assert_eq!(vec![2, 3], result.get_property("bytes").to_bytes());
+        assert!(result.get_property("flag").to_bool());
+        assert!(result.get_property("missing").is_undefined());
+        assert_eq!(
+            near_sdk::serde_json::json!({"a": "b"}),
+            result.get_property("nested").to_json()
+        );
+    }
+
+    #[test]
+    fn test_host_functions() {
+        setup_test_env();
+        let bytecode = compile_js(
+            "export function run() {
+                const total = env.add_to_total(2, '30') + env.add_to_total(0, 10n) - 32n;
+                env.value_return(env.greet('alice.near', { greeting: 'hello' }) + ' ' + total);
+            }"
+            .to_string(),
+            Some("main.js".to_string()),
+        );
+        let jsmod = load_js_bytecode(bytecode.as_ptr(), bytecode.len()).unwrap();
+
+        let total = RefCell::new(0u128);
+        with_host_functions(|host| {
+            host.add("add_to_total", |a: u128, b: u128| -> HostResult<u128> {
+                *total.borrow_mut() += a + b;
+                Ok(*total.borrow())
+            });
+            host.add(
+                "greet",
+                |account_id: AccountId, options: near_sdk::serde_json::Value| -> HostResult<String> {
+                    Ok(format!("{} {}", options["greeting"].as_str().unwrap(), account_id))
+                },
+            );
+            jsmod.call_function("run").unwrap();
+        });
+        assert_eq!(42, *total.borrow());
+        assert_latest_return_value_string_eq("hello alice.near 42".to_string());
+    }
+
+    #[test]
+    fn test_host_function_errors_are_thrown() {
+        setup_test_env();
+        let bytecode = compile_js(
+            "export function run() {
+                const messages = [];
+                for (const call of [
+                    () => env.withdraw(),
+                    () => env.withdraw('ten'),
+                    () => env.withdraw(-1),
+                    () => env.withdraw(10)
+                ]) {
+                    try {
+                        call();
+                    } catch (e) {
+                        messages.push(e.name + ': ' + e.message);
+                    }
+                }
+                env.value_return(messages.join('|'));
+            }"
+            .to_string(),
+            Some("main.js".to_string()),
+        );
+        let jsmod = load_js_bytecode(bytecode.as_ptr(), bytecode.len()).unwrap();
+
+        with_host_functions(|host| {
+            host.add("withdraw", |_amount: u32| -> HostResult<()> {
+                Err("insufficient balance".into())
+            });
+            jsmod.call_function("run").unwrap();
+        });
+        assert_latest_return_value_string_eq(
+            [
+                "TypeError: withdraw: expected at least 1 argument, got 0",
+                "TypeError: withdraw: argument 0: expected a number",
+                "RangeError: withdraw: argument 0: expected an integer in the range of u32",
+                "Error: withdraw: insufficient balance",
+            ]
+            .join("|"),
+        );
+    }
+
+    #[test]
+    fn test_host_function_bigint_amounts() {
+        setup_test_env();
+        let bytecode = compile_js(
+            "export function run() {
+                const results = [typeof env.double(1n), env.double(2n ** 127n - 1n) === 2n ** 128n - 2n];
+                for (const amount of [-1n, 2n ** 128n]) {
+                    try {
+                        env.double(amount);
+                    } catch (e) {
+                        results.push(e.name + ': ' + e.message);
+                    }
+                }
+                env.value_return(results.join('|'));
+            }"
+            .to_string(),
+            Some("main.js".to_string()),
+        );
+        let jsmod = load_js_bytecode(bytecode.as_ptr(), bytecode.len()).unwrap();
+
+        with_host_functions(|host| {
+            host.add("double", |amount: U128| -> HostResult<u128> { Ok(amount.0 * 2) });
+            jsmod.call_function("run").unwrap();
+        });
+        assert_latest_return_value_string_eq(
+            [
+                "bigint",
+                "true",
+                "RangeError: double: argument 0: expected a BigInt in the range of u128",
+                "RangeError: double: argument 0: expected a BigInt in the range of u128",
+            ]
+            .join("|"),
+        );
+    }
+
+    #[test]
+    fn test_host_function_integer_bounds() {
+        setup_test_env();
+        let bytecode = compile_js(
+            "export function run() {
+                const results = [env.to_i64(2 ** 62) === 2 ** 62, env.to_u64('42')];
+                for (const call of [
+                    () => env.to_i64(2 ** 63),
+                    () => env.to_u64('ten'),
+                    () => env.to_u64('18446744073709551616'),
+                    () => env.to_u64(2 ** 64)
+                ]) {
+                    try {
+                        call();
+                    } catch (e) {
+                        results.push(e.name + ': ' + e.message);
+                    }
+                }
+                env.value_return(results.join('|'));
+            }"
+            .to_string(),
+            Some("main.js".to_string()),
+        );
+        let jsmod = load_js_bytecode(bytecode.as_ptr(), bytecode.len()).unwrap();
+
+        with_host_functions(|host| {
+            host.add("to_i64", |n: i64| -> HostResult<i64> { Ok(n) });
+            host.add("to_u64", |n: u64| -> HostResult<String> { Ok(n.to_string()) });
+            jsmod.call_function("run").unwrap();
+        });
+        assert_latest_return_value_string_eq(
+            [
+                "true",
+                "42",
+                "RangeError: to_i64: argument 0: expected an integer in the range of i64",
+                "TypeError: to_u64: argument 0: expected a number or a decimal string",
+                "RangeError: to_u64: argument 0: expected a decimal string in the range of u64",
+                "RangeError: to_u64: argument 0: expected an integer in the range of u64",
+            ]
+            .join("|"),
+        );
     }
 
     #[test]
@@ -446,14 +899,36 @@ mod tests {
             "(function () { return { val: env.base64_encode('hello')}; })()".to_string(),
             None,
         );
-        let result = run_js_bytecode(bytecode);
-        unsafe {
-            let stringjsval = js_get_property(result, "val".as_ptr() as i32);
-            let str = CStr::from_ptr(js_get_string(stringjsval) as *const i8)
-                .to_str()
-                .unwrap();
-            assert_eq!("aGVsbG8=", str);
-        }
+        let result = run_js_bytecode(bytecode).unwrap();
+        assert_eq!("aGVsbG8=", result.get_property("val").to_string());
+    }
+
+    #[test]
+    fn test_string_argument_errors_are_thrown() {
+        setup_test_env();
+        let bytecode = compile_js(
+            "(function () {
+                const errors = [];
+                for (const f of [env.base64_encode, env.panic]) {
+                    try {
+                        f(Symbol('not a string'));
+                        errors.push('no exception');
+                    } catch (e) {
+                        errors.push(e instanceof TypeError ? e.message : 'wrong error type');
+                    }
+                }
+                return errors.join('; ');
+            })()"
+                .to_string(),
+            None,
+        );
+        let result = run_js_bytecode(bytecode).unwrap();
+
+        assert_eq!(
+            "argument 0: can not be converted to a string; \
+             argument 0: can not be converted to a string",
+            result.to_string()
+        );
     }
 
     #[test]
@@ -463,20 +938,14 @@ mod tests {
             "(function () { return { val: env.sha256_utf8_to_base64('hello\\n')}; })()".to_string(),
             None,
         );
-        let result = run_js_bytecode(bytecode);
-        unsafe {
-            let stringjsval = js_get_property(result, "val".as_ptr() as i32);
-            let str = CStr::from_ptr(js_get_string(stringjsval) as *const i8)
-                .to_str()
-                .unwrap();
-            assert_eq!(
-                base64::encode(
-                    hex::decode("5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03")
-                        .unwrap()
-                ),
-                str
-            );
-        }
+        let result = run_js_bytecode(bytecode).unwrap();
+        assert_eq!(
+            base64::encode(
+                hex::decode("5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03")
+                    .unwrap()
+            ),
+            result.get_property("val").to_string()
+        );
     }
 
     #[test]
@@ -494,9 +963,9 @@ mod tests {
         ", signature.to_bytes(), signing_key.verifying_key().as_bytes());
 
         let bytecode = compile_js(script, None);
-        let result = run_js_bytecode(bytecode);
+        let result = run_js_bytecode(bytecode).unwrap();
 
-        assert_eq!(1, result);
+        assert_eq!(1, result.to_i64());
     }
 
     #[test]
@@ -528,8 +997,351 @@ mod tests {
                 .to_string(),
             None,
         );
-        let result = run_js_bytecode(bytecode);
+        let result = run_js_bytecode(bytecode).unwrap();
+
+        assert_eq!(1, result.to_i64());
+    }
+
+    #[test]
+    fn test_ed25519_verify_wrong_length_throws_range_error() {
+        setup_test_env();
+        let bytecode = compile_js(
+            "(function () {
+                try {
+                    env.ed25519_verify(new Uint8Array(10), new Uint8Array(1), new Uint8Array(32));
+                    return 'no exception';
+                } catch (e) {
+                    return e instanceof RangeError ? e.message : 'wrong error type';
+                }
+            })()"
+                .to_string(),
+            None,
+        );
+        let result = run_js_bytecode(bytecode).unwrap();
+
+        assert_eq!(
+            "ed25519_verify: expected a 64 byte signature",
+            result.to_string()
+        );
+    }
+
+    #[test]
+    fn test_run_js_returns_error() {
+        setup_test_env();
+        let err = run_js("const a = 1;\nnull.foo;".to_string()).unwrap_err();
+
+        assert_eq!("TypeError", err.name);
+        assert_eq!(Some("main.js".to_string()), err.file_name);
+        assert_eq!(Some(2), err.line_number);
+        assert!(err.stack.is_some());
+    }
+
+    #[test]
+    fn test_call_function_returns_error() {
+        setup_test_env();
+        let bytecode = compile_js(
+            "export function fail_with_error() {
+                throw new RangeError('amount too large');
+            }
+            export function fail_with_string() {
+                throw 'plain string';
+            }"
+            .to_string(),
+            Some("main.js".to_string()),
+        );
+        let jsmod = load_js_bytecode(bytecode.as_ptr(), bytecode.len()).unwrap();
+
+        let err = jsmod.call_function("fail_with_error").unwrap_err();
+        assert_eq!("RangeError", err.name);
+        assert_eq!("amount too large", err.message);
+        assert_eq!(Some(2), err.line_number);
+        assert!(err
+            .to_string()
+            .starts_with("RangeError: amount too large at main.js:2"));
+
+        let err = jsmod.call_function("fail_with_string").unwrap_err();
+        assert_eq!("Uncaught plain string", err.to_string());
+
+        let err = jsmod.call_function("missing").unwrap_err();
+        assert_eq!("TypeError", err.name);
+    }
+
+    #[test]
+    fn test_load_js_bytecode_returns_error() {
+        setup_test_env();
+        let bytecode = compile_js(
+            "throw new Error('failed to initialize');".to_string(),
+            Some("main.js".to_string()),
+        );
+
+        let err = load_js_bytecode(bytecode.as_ptr(), bytecode.len()).unwrap_err();
+        assert_eq!("failed to initialize", err.message);
+    }
+
+    #[test]
+    fn test_call_function_with_args() {
+        setup_test_env();
+        let bytecode = compile_js(
+            "export function describe(name, amount, options, bytes, missing) {
+                return [name, amount + 1, options.tags.join(','), bytes.length, typeof missing].join(' ');
+            }"
+            .to_string(),
+            Some("main.js".to_string()),
+        );
+        let jsmod = load_js_bytecode(bytecode.as_ptr(), bytecode.len()).unwrap();
+
+        let result = jsmod
+            .call_function_with_args(
+                "describe",
+                (
+                    "alice.near",
+                    41,
+                    near_sdk::serde_json::json!({"tags": ["a", "b"]}),
+                    vec![1u8, 2, 3],
+                    None::<String>,
+                ),
+            )
+            .unwrap();
+        assert_eq!("alice.near 42 a,b 3 object", result.to_string());
+
+        let result = jsmod
+            .call_function_with_args("describe", &[JsValue::from_string("bob.near")][..])
+            .unwrap_err();
+        assert_eq!("TypeError", result.name);
+    }
+
+    #[test]
+    fn test_call_async_function() {
+        setup_test_env();
+        let bytecode = compile_js(
+            "export async function add_later(a, b) {
+                const sum = await new Promise(resolve => resolve(a + b));
+                return env.double(sum);
+            }
+            export async function reject_later() {
+                await null;
+                throw new Error('rejected');
+            }
+            export function never_settles() {
+                return new Promise(() => {});
+            }"
+            .to_string(),
+            Some("main.js".to_string()),
+        );
+        let jsmod = load_js_bytecode(bytecode.as_ptr(), bytecode.len()).unwrap();
+
+        with_host_functions(|host| {
+            host.add("double", |value: i32| -> HostResult<i32> { Ok(value * 2) });
+
+            let result = jsmod.call_function_with_args("add_later", (20, 1)).unwrap();
+            assert_eq!(42, result.to_i64());
+
+            let err = jsmod.call_function("reject_later").unwrap_err();
+            assert_eq!("rejected", err.message);
+
+            let err = jsmod.call_function("never_settles").unwrap_err();
+            assert_eq!("promise was never settled", err.message);
+        });
+    }
+
+    #[test]
+    fn test_runaway_script_is_interrupted() {
+        setup_test_env();
+        set_used_gas(Gas::from_tgas(296));
+        let err = run_js(
+            "while (true) {
+                try {
+                    for (;;) {}
+                } catch (e) {
+                    // the interrupt can not be caught
+                }
+            }"
+            .to_string(),
+        )
+        .unwrap_err();
+
+        assert_eq!(JsErrorKind::OutOfBudget, err.kind);
+    }
+
+    #[test]
+    fn test_script_within_budget_is_not_interrupted() {
+        setup_test_env();
+        set_used_gas(Gas::from_tgas(294));
+        let result = run_js("let i = 0; while (i < 100000) { i++; } i".to_string()).unwrap();
+
+        assert_eq!(100000, result.to_i64());
+    }
+
+    #[test]
+    fn test_runtime_config_limits_stack() {
+        setup_test_env();
+        let config = RuntimeConfig::default().with_max_stack_size(64 * 1024);
+        let result = run_js_with_config(
+            "function depth(n) {
+                try {
+                    return depth(n + 1);
+                } catch (e) {
+                    return n;
+                }
+            }
+            depth(0)"
+                .to_string(),
+            &config,
+        )
+        .unwrap();
+        let unlimited =
+            run_js("function depth(n) { return n < 1000 ? depth(n + 1) : n; } depth(0)".to_string())
+                .unwrap();
+
+        assert!(result.to_i64() < 1000);
+        assert_eq!(1000, unlimited.to_i64());
+    }
+
+    #[test]
+    fn test_runtime_config_limits_memory() {
+        setup_test_env();
+        let config = RuntimeConfig::default().with_memory_limit(2 * 1024 * 1024);
+        let err = run_js_with_config("new Array(1024 * 1024).fill(0).length".to_string(), &config)
+            .unwrap_err();
+
+        assert_eq!("out of memory", err.message);
+    }
+
+    #[test]
+    fn test_block_and_account_functions() {
+        setup_test_env();
+        set_block_height(123_456);
+        set_epoch_height(42);
+        set_account_balance(NearToken::from_near(10), NearToken::from_yoctonear(1));
+        set_used_gas(Gas::from_tgas(3));
+        env::storage_write(b"key", b"value");
+
+        let result = run_js(
+            "JSON.stringify([
+                env.block_height(),
+                env.epoch_height(),
+                env.account_balance().toString(),
+                env.account_locked_balance().toString(),
+                env.storage_usage(),
+                env.prepaid_gas(),
+                env.used_gas(),
+            ])"
+            .to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            "[123456,42,\"10000000000000000000000000\",\"1\",5,300000000000000,3000000000000]",
+            result.to_string()
+        );
+    }
+
+    #[test]
+    fn test_signer_account_pk_func() {
+        setup_test_env();
+        let pk: PublicKey = "ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp"
+            .parse()
+            .unwrap();
+        set_signer_account_pk(pk);
+
+        let result = run_js("env.signer_account_pk()".to_string()).unwrap();
+        assert_eq!(
+            "ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp",
+            result.to_string()
+        );
+    }
+
+    #[test]
+    fn test_validator_stake_func() {
+        setup_test_env();
+        set_validator_stake(alice(), NearToken::from_near(1000));
+        set_validator_stake(bob(), NearToken::from_near(500));
+
+        let result = run_js(
+            "JSON.stringify([
+                env.validator_stake('alice.near').toString(),
+                env.validator_stake('carol.near').toString(),
+                env.validator_total_stake().toString(),
+            ])"
+            .to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            "[\"1000000000000000000000000000\",\"0\",\"1500000000000000000000000000\"]",
+            result.to_string()
+        );
+    }
+
+    #[test]
+    fn test_random_seed_func() {
+        setup_test_env();
+        set_random_seed([7; 32]);
+
+        let result = run_js(format!("{} hex(env.random_seed())", TO_HEX)).unwrap();
+        assert_eq!("07".repeat(32), result.to_string());
+    }
+
+    #[test]
+    fn test_hash_functions() {
+        setup_test_env();
+        let result = run_js(format!(
+            "{} JSON.stringify([
+                hex(env.keccak256('hello')),
+                hex(env.keccak256(new Uint8Array([104, 101, 108, 108, 111]))),
+                hex(env.keccak512('hello')),
+                hex(env.ripemd160('hello')),
+            ])",
+            TO_HEX
+        ))
+        .unwrap();
+
+        assert_eq!(
+            format!(
+                "[\"{}\",\"{}\",\"{}\",\"{}\"]",
+                "1c8aff950685c2ed4bc3174f3472287b56d9517b9c948127319a09a7a36deac8",
+                "1c8aff950685c2ed4bc3174f3472287b56d9517b9c948127319a09a7a36deac8",
+                concat!(
+                    "52fa80662e64c128f8389c9ea6c73d4c02368004bf4463491900d11aaadca39d",
+                    "47de1b01361f207c512cfa79f0f92c3395c67ff7928e3f5ce3e3c852b392f976"
+                ),
+                "108f07b8382412612c048d07d13f814118445acd",
+            ),
+            result.to_string()
+        );
+    }
+
+    #[test]
+    fn test_ecrecover_func() {
+        setup_test_env();
+        let result = run_js(format!(
+            "{}
+            const signature = new Uint8Array('{}'.match(/../g).map((b) => parseInt(b, 16)));
+            const hash = env.keccak256('hello');
+            JSON.stringify([
+                hex(env.ecrecover(hash, signature, 0)),
+                env.ecrecover(hash, new Uint8Array(64), 0),
+            ])",
+            TO_HEX,
+            concat!(
+                "00ca2f2cde0f0048da11b41f14640814204ade2520f0ffb7a8fb5cec147b7afa",
+                "675a4b2d9b4fd234387d900910ba026008e03bc73bfd3288835fa7d5a971420b"
+            )
+        ))
+        .unwrap();
+
+        let public_key = concat!(
+            "989c0b76cb563971fdc9bef31ec06c3560f3249d6ee9e5d83c57625596e05f6f",
+            "631f4d05b3ae518776ee08755a7703e64b2ebc32547504de0b55a142d4ecdf80"
+        );
+        assert_eq!(format!("[\"{}\",null]", public_key), result.to_string());
+    }
 
-        assert_eq!(1, result);
+    #[test]
+    fn test_log_utf8_func() {
+        setup_test_env();
+        let result = run_js("env.log_utf8('hello from JavaScript')".to_string()).unwrap();
+        assert!(result.is_undefined());
+        assert_eq!(vec!["hello from JavaScript"], logs());
     }
 }
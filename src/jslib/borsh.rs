@@ -0,0 +1,660 @@
+//! Borsh serialization for scripts, driven by a schema in the format of the `borsh` npm package.
+//!
+//! `env.borsh_serialize(schema, value)` returns the encoding of `value` as a `Uint8Array`, and
+//! `env.borsh_deserialize(schema, bytes)` decodes it again. Reading the state of a Rust struct
+//! looks like this:
+//!
+//! ```js
+//! const schema = {
+//!     struct: {
+//!         owner_id: 'string',
+//!         balance: 'u128',
+//!         memo: { option: 'string' },
+//!         history: { array: { type: 'u32' } },
+//!     },
+//! };
+//! const token = env.borsh_deserialize(schema, env.storage_read('token'));
+//! ```
+//!
+//! | schema | Rust type | JavaScript value |
+//! |---|---|---|
+//! | `'bool'` | `bool` | a boolean |
+//! | `'u8'`, `'u16'`, `'u32'`, `'i8'`, `'i16'`, `'i32'` | the integer | a number |
+//! | `'u64'`, `'u128'`, `'i64'`, `'i128'` | the integer | a BigInt |
+//! | `'f32'`, `'f64'` | the float | a number |
+//! | `'string'` | `String` | a string |
+//! | `{ option: T }` | `Option<T>` | `null` for `None` |
+//! | `{ array: { type: T } }` | `Vec<T>` | an array |
+//! | `{ array: { type: T, len: n } }` | `[T; n]` | an array of `n` elements |
+//! | `{ map: { key: K, value: V } }` | `HashMap<K, V>` or `BTreeMap<K, V>` | an object |
+//! | `{ struct: { a: T, ... } }` | a struct, with the fields in order | an object |
+//! | `{ enum: [{ struct: { A: T } }, ...] }` | an enum, with the variants in order | `{ A: value }` |
+//!
+//! Arrays of `u8` are decoded as a `Uint8Array`. Integers are accepted as numbers, BigInts or
+//! decimal strings, but the 64 and 128 bit ones are decoded as BigInts, since a number loses
+//! precision beyond 2^53. Map keys must be strings or integers, and are decoded as strings.
+
+use near_sdk::borsh::{BorshDeserialize, BorshSerialize};
+use std::str::FromStr;
+
+use super::host::{call_host_fn, FromJs};
+use super::{HostError, HostResult, JsValue};
+
+enum Schema {
+    Bool,
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    I8,
+    I16,
+    I32,
+    I64,
+    I128,
+    F32,
+    F64,
+    String,
+    Option(Box<Schema>),
+    Array(Box<Schema>, Option<u32>),
+    Map(Box<Schema>, Box<Schema>),
+    Struct(Vec<(String, Schema)>),
+    Enum(Vec<(String, Schema)>),
+}
+
+fn invalid_schema(message: impl Into<String>) -> HostError {
+    HostError::type_error(format!("invalid schema: {}", message.into()))
+}
+
+impl Schema {
+    fn parse(schema: &JsValue) -> HostResult<Schema> {
+        if schema.is_string() {
+            return Ok(match schema.to_string().as_str() {
+                "bool" => Schema::Bool,
+                "u8" => Schema::U8,
+                "u16" => Schema::U16,
+                "u32" => Schema::U32,
+                "u64" => Schema::U64,
+                "u128" => Schema::U128,
+                "i8" => Schema::I8,
+                "i16" => Schema::I16,
+                "i32" => Schema::I32,
+                "i64" => Schema::I64,
+                "i128" => Schema::I128,
+                "f32" => Schema::F32,
+                "f64" => Schema::F64,
+                "string" => Schema::String,
+                other => return Err(invalid_schema(format!("unknown type '{}'", other))),
+            });
+        }
+        let keys = schema.keys();
+        let [kind] = keys.as_slice() else {
+            return Err(invalid_schema(
+                "expected a type name or an object with a single key",
+            ));
+        };
+        let inner = schema.get_property(kind);
+        match kind.as_str() {
+            "option" => Ok(Schema::Option(Box::new(Schema::parse(&inner)?))),
+            "array" => {
+                let element = Schema::parse(&inner.get_property("type"))?;
+                let len = inner.get_property("len");
+                let len = if len.is_undefined() {
+                    None
+                } else {
+                    Some(u32::from_js(&len).map_err(|_| {
+                        invalid_schema("the array len must be a non-negative integer")
+                    })?)
+                };
+                Ok(Schema::Array(Box::new(element), len))
+            }
+            "map" => {
+                let key = Schema::parse(&inner.get_property("key"))?;
+                if key.integer_type().is_none() && !matches!(key, Schema::String) {
+                    return Err(invalid_schema("map keys must be strings or integers"));
+                }
+                let value = Schema::parse(&inner.get_property("value"))?;
+                Ok(Schema::Map(Box::new(key), Box::new(value)))
+            }
+            "struct" => {
+                if !inner.is_object() {
+                    return Err(invalid_schema("expected an object of struct fields"));
+                }
+                let fields = inner
+                    .keys()
+                    .into_iter()
+                    .map(|name| {
+                        let field = Schema::parse(&inner.get_property(&name))?;
+                        Ok((name, field))
+                    })
+                    .collect::<HostResult<_>>()?;
+                Ok(Schema::Struct(fields))
+            }
+            "enum" => {
+                if !inner.is_array() {
+                    return Err(invalid_schema("expected an array of enum variants"));
+                }
+                // Borsh writes the variant index as a single byte
+                if inner.len() > 256 {
+                    return Err(invalid_schema("an enum can have at most 256 variants"));
+                }
+                let variants = (0..inner.len() as u32)
+                    .map(|index| {
+                        let variant = inner.get_index(index).get_property("struct");
+                        let keys = variant.keys();
+                        let [name] = keys.as_slice() else {
+                            return Err(invalid_schema(
+                                "expected enum variants of the form { struct: { Name: type } }",
+                            ));
+                        };
+                        let value = Schema::parse(&variant.get_property(name))?;
+                        Ok((name.clone(), value))
+                    })
+                    .collect::<HostResult<_>>()?;
+                Ok(Schema::Enum(variants))
+            }
+            other => Err(invalid_schema(format!("unknown type '{}'", other))),
+        }
+    }
+
+    /// The name of an integer type, used in error messages.
+    fn integer_type(&self) -> Option<&'static str> {
+        Some(match self {
+            Schema::U8 => "u8",
+            Schema::U16 => "u16",
+            Schema::U32 => "u32",
+            Schema::U64 => "u64",
+            Schema::U128 => "u128",
+            Schema::I8 => "i8",
+            Schema::I16 => "i16",
+            Schema::I32 => "i32",
+            Schema::I64 => "i64",
+            Schema::I128 => "i128",
+            _ => return None,
+        })
+    }
+}
+
+/// Map entries are serialized ordered by key, as Borsh does for `HashMap` and `BTreeMap`.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum MapKey {
+    String(String),
+    Integer(i128),
+    Unsigned(u128),
+}
+
+struct Encoder {
+    output: Vec<u8>,
+    // the fields and indices leading to the value being encoded, for error messages
+    path: Vec<String>,
+}
+
+impl Encoder {
+    fn error(&self, err: HostError) -> HostError {
+        if self.path.is_empty() {
+            return err;
+        }
+        HostError::new(
+            err.kind,
+            format!("{}: {}", self.path.join("."), err.message),
+        )
+    }
+
+    fn write<T: BorshSerialize>(&mut self, value: &T) -> HostResult<()> {
+        value
+            .serialize(&mut self.output)
+            .map_err(|err| self.error(HostError::type_error(err.to_string())))
+    }
+
+    fn integer<T>(&self, value: &JsValue, type_name: &str) -> HostResult<T>
+    where
+        T: FromStr,
+    {
+        let digits = if value.is_number() {
+            let number = value.to_f64();
+            if !number.is_finite() || number.fract() != 0.0 {
+                return Err(self.error(HostError::range_error(format!(
+                    "expected an integer in the range of {}",
+                    type_name
+                ))));
+            }
+            // adding zero turns -0 into 0
+            (number + 0.0).to_string()
+        } else if value.is_string() || value.is_bigint() {
+            value.to_string()
+        } else {
+            return Err(self.error(HostError::type_error(
+                "expected a number, a BigInt or a decimal string",
+            )));
+        };
+        digits.parse::<T>().map_err(|_| {
+            self.error(HostError::range_error(format!(
+                "expected an integer in the range of {}",
+                type_name
+            )))
+        })
+    }
+
+    fn write_integer<T>(&mut self, value: &JsValue, type_name: &str) -> HostResult<()>
+    where
+        T: FromStr + BorshSerialize,
+    {
+        let integer = self.integer::<T>(value, type_name)?;
+        self.write(&integer)
+    }
+
+    fn decode<T: FromJs>(&self, value: &JsValue) -> HostResult<T> {
+        T::from_js(value).map_err(|err| self.error(err))
+    }
+
+    fn encode_at(&mut self, segment: String, schema: &Schema, value: &JsValue) -> HostResult<()> {
+        self.path.push(segment);
+        self.encode(schema, value)?;
+        self.path.pop();
+        Ok(())
+    }
+
+    fn encode(&mut self, schema: &Schema, value: &JsValue) -> HostResult<()> {
+        match schema {
+            Schema::Bool => {
+                let value = self.decode::<bool>(value)?;
+                self.write(&value)
+            }
+            Schema::U8 => self.write_integer::<u8>(value, "u8"),
+            Schema::U16 => self.write_integer::<u16>(value, "u16"),
+            Schema::U32 => self.write_integer::<u32>(value, "u32"),
+            Schema::U64 => self.write_integer::<u64>(value, "u64"),
+            Schema::U128 => self.write_integer::<u128>(value, "u128"),
+            Schema::I8 => self.write_integer::<i8>(value, "i8"),
+            Schema::I16 => self.write_integer::<i16>(value, "i16"),
+            Schema::I32 => self.write_integer::<i32>(value, "i32"),
+            Schema::I64 => self.write_integer::<i64>(value, "i64"),
+            Schema::I128 => self.write_integer::<i128>(value, "i128"),
+            Schema::F32 => {
+                let value = self.decode::<f64>(value)? as f32;
+                self.write(&value)
+            }
+            Schema::F64 => {
+                let value = self.decode::<f64>(value)?;
+                self.write(&value)
+            }
+            Schema::String => {
+                let value = self.decode::<String>(value)?;
+                self.write(&value)
+            }
+            Schema::Option(inner) => {
+                if value.is_undefined() || value.is_null() {
+                    return self.write(&0u8);
+                }
+                self.write(&1u8)?;
+                self.encode(inner, value)
+            }
+            Schema::Array(element, len) => {
+                if matches!(**element, Schema::U8) {
+                    if let Some(bytes) = value.try_to_bytes() {
+                        self.check_len(bytes.len(), *len)?;
+                        self.output.extend_from_slice(&bytes);
+                        return Ok(());
+                    }
+                }
+                if !value.is_array() {
+                    return Err(self.error(HostError::type_error("expected an array")));
+                }
+                let count = value.len();
+                self.check_len(count, *len)?;
+                for index in 0..count as u32 {
+                    self.encode_at(index.to_string(), element, &value.get_index(index))?;
+                }
+                Ok(())
+            }
+            Schema::Map(key, value_schema) => {
+                if !value.is_object() {
+                    return Err(self.error(HostError::type_error("expected an object")));
+                }
+                let mut entries = value
+                    .keys()
+                    .into_iter()
+                    .map(|name| {
+                        let sort_key = match key.integer_type() {
+                            Some(type_name) if type_name.starts_with('u') => MapKey::Unsigned(
+                                self.integer(&JsValue::from_string(&name), type_name)?,
+                            ),
+                            Some(type_name) => MapKey::Integer(
+                                self.integer(&JsValue::from_string(&name), type_name)?,
+                            ),
+                            None => MapKey::String(name.clone()),
+                        };
+                        Ok((sort_key, name))
+                    })
+                    .collect::<HostResult<Vec<_>>>()?;
+                entries.sort();
+                self.write(&(entries.len() as u32))?;
+                for (_, name) in entries {
+                    self.encode_at(name.clone(), key, &JsValue::from_string(&name))?;
+                    self.encode_at(name.clone(), value_schema, &value.get_property(&name))?;
+                }
+                Ok(())
+            }
+            Schema::Struct(fields) => {
+                if !value.is_object() {
+                    return Err(self.error(HostError::type_error("expected an object")));
+                }
+                for (name, field) in fields {
+                    self.encode_at(name.clone(), field, &value.get_property(name))?;
+                }
+                Ok(())
+            }
+            Schema::Enum(variants) => {
+                let keys = value.keys();
+                let found = match keys.as_slice() {
+                    [name] => variants
+                        .iter()
+                        .position(|(variant, _)| variant == name)
+                        .map(|index| (index, name)),
+                    _ => None,
+                };
+                let Some((index, name)) = found else {
+                    return Err(self.error(HostError::type_error(
+                        "expected an object with the name of an enum variant as its single key",
+                    )));
+                };
+                self.write(&(index as u8))?;
+                self.encode_at(name.clone(), &variants[index].1, &value.get_property(name))
+            }
+        }
+    }
+
+    fn check_len(&mut self, count: usize, len: Option<u32>) -> HostResult<()> {
+        match len {
+            Some(len) if count != len as usize => Err(self.error(HostError::range_error(format!(
+                "expected an array of {} elements, but got {}",
+                len, count
+            )))),
+            Some(_) => Ok(()),
+            None => self.write(&(count as u32)),
+        }
+    }
+}
+
+fn invalid_data(message: impl std::fmt::Display) -> HostError {
+    HostError::from(format!("invalid borsh data: {}", message))
+}
+
+struct Decoder<'a> {
+    input: &'a [u8],
+}
+
+impl Decoder<'_> {
+    fn read<T: BorshDeserialize>(&mut self) -> HostResult<T> {
+        T::deserialize(&mut self.input).map_err(invalid_data)
+    }
+
+    fn read_len(&mut self, len: Option<u32>) -> HostResult<u32> {
+        match len {
+            Some(len) => Ok(len),
+            None => self.read::<u32>(),
+        }
+    }
+
+    fn decode(&mut self, schema: &Schema) -> HostResult<JsValue> {
+        Ok(match schema {
+            Schema::Bool => JsValue::from_bool(self.read()?),
+            Schema::U8 => JsValue::from_i32(self.read::<u8>()? as i32),
+            Schema::U16 => JsValue::from_i32(self.read::<u16>()? as i32),
+            Schema::U32 => JsValue::from_f64(self.read::<u32>()? as f64),
+            Schema::U64 => JsValue::from_u128(self.read::<u64>()? as u128),
+            Schema::U128 => JsValue::from_u128(self.read()?),
+            Schema::I8 => JsValue::from_i32(self.read::<i8>()? as i32),
+            Schema::I16 => JsValue::from_i32(self.read::<i16>()? as i32),
+            Schema::I32 => JsValue::from_i32(self.read()?),
+            Schema::I64 => JsValue::from_i128(self.read::<i64>()? as i128),
+            Schema::I128 => JsValue::from_i128(self.read()?),
+            Schema::F32 => JsValue::from_f64(self.read::<f32>()? as f64),
+            Schema::F64 => JsValue::from_f64(self.read()?),
+            Schema::String => JsValue::from_string(&self.read::<String>()?),
+            Schema::Option(inner) => match self.read::<u8>()? {
+                0 => JsValue::null(),
+                1 => self.decode(inner)?,
+                tag => return Err(invalid_data(format!("invalid option tag {}", tag))),
+            },
+            Schema::Array(element, len) => {
+                let count = self.read_len(*len)?;
+                if matches!(**element, Schema::U8) {
+                    if self.input.len() < count as usize {
+                        return Err(invalid_data("unexpected end of input"));
+                    }
+                    let (bytes, rest) = self.input.split_at(count as usize);
+                    self.input = rest;
+                    return Ok(JsValue::from_bytes(bytes));
+                }
+                let array = JsValue::new_array();
+                for index in 0..count {
+                    array.set_index(index, self.decode(element)?);
+                }
+                array
+            }
+            Schema::Map(key, value) => {
+                let count = self.read::<u32>()?;
+                let object = JsValue::new_object();
+                for _ in 0..count {
+                    let name = self.decode(key)?.to_string();
+                    object.set_property(&name, self.decode(value)?);
+                }
+                object
+            }
+            Schema::Struct(fields) => {
+                let object = JsValue::new_object();
+                for (name, field) in fields {
+                    object.set_property(name, self.decode(field)?);
+                }
+                object
+            }
+            Schema::Enum(variants) => {
+                let index = self.read::<u8>()?;
+                let Some((name, variant)) = variants.get(index as usize) else {
+                    return Err(invalid_data(format!("invalid enum variant {}", index)));
+                };
+                let object = JsValue::new_object();
+                object.set_property(name, self.decode(variant)?);
+                object
+            }
+        })
+    }
+}
+
+fn borsh_serialize(schema: JsValue, value: JsValue) -> HostResult<Vec<u8>> {
+    let schema = Schema::parse(&schema)?;
+    let mut encoder = Encoder {
+        output: Vec::new(),
+        path: Vec::new(),
+    };
+    encoder.encode(&schema, &value)?;
+    Ok(encoder.output)
+}
+
+fn borsh_deserialize(schema: JsValue, bytes: Vec<u8>) -> HostResult<JsValue> {
+    let schema = Schema::parse(&schema)?;
+    let mut decoder = Decoder { input: &bytes };
+    let value = decoder.decode(&schema)?;
+    if !decoder.input.is_empty() {
+        return Err(invalid_data(format!(
+            "{} bytes left after the value",
+            decoder.input.len()
+        )));
+    }
+    Ok(value)
+}
+
+pub(crate) fn borsh_serialize_func(ctx: i32, _this_val: i64, argc: i32, argv: i32) -> i64 {
+    call_host_fn("borsh_serialize", &borsh_serialize, ctx, argc, argv)
+}
+
+pub(crate) fn borsh_deserialize_func(ctx: i32, _this_val: i64, argc: i32, argv: i32) -> i64 {
+    call_host_fn("borsh_deserialize", &borsh_deserialize, ctx, argc, argv)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::jslib::{compile_js, load_js_bytecode, JsValue};
+    use near_sdk::borsh::{self, BorshSerialize};
+    use quickjs_rust_near_testenv::testenv::setup_test_env;
+    use std::collections::BTreeMap;
+
+    #[derive(BorshSerialize)]
+    #[borsh(crate = "near_sdk::borsh")]
+    enum Status {
+        Active,
+        Locked { until: u64 },
+    }
+
+    #[derive(BorshSerialize)]
+    #[borsh(crate = "near_sdk::borsh")]
+    struct Account {
+        owner_id: String,
+        balance: u128,
+        memo: Option<String>,
+        history: Vec<i32>,
+        key: [u8; 4],
+        allowances: BTreeMap<String, u64>,
+        statuses: Vec<Status>,
+    }
+
+    const SCHEMA: &str = "{
+        struct: {
+            owner_id: 'string',
+            balance: 'u128',
+            memo: { option: 'string' },
+            history: { array: { type: 'i32' } },
+            key: { array: { type: 'u8', len: 4 } },
+            allowances: { map: { key: 'string', value: 'u64' } },
+            statuses: {
+                array: {
+                    type: {
+                        enum: [
+                            { struct: { Active: { struct: {} } } },
+                            { struct: { Locked: { struct: { until: 'u64' } } } },
+                        ],
+                    },
+                },
+            },
+        },
+    }";
+
+    fn call_with_bytes(body: &str, bytes: &[u8]) -> JsValue {
+        let bytecode = compile_js(
+            format!(
+                "const schema = {}; export function run(bytes) {{ {} }}",
+                SCHEMA, body
+            ),
+            Some("main.js".to_string()),
+        );
+        let jsmod = load_js_bytecode(bytecode.as_ptr(), bytecode.len()).unwrap();
+        jsmod
+            .call_function_with_args("run", (bytes.to_vec(),))
+            .unwrap()
+    }
+
+    fn account() -> Account {
+        Account {
+            owner_id: "alice.near".to_string(),
+            balance: 10u128.pow(30),
+            memo: None,
+            history: vec![-1, 2, 300_000],
+            key: [1, 2, 3, 4],
+            allowances: BTreeMap::from([
+                ("bob.near".to_string(), 5),
+                ("alice.near".to_string(), u64::MAX),
+            ]),
+            statuses: vec![Status::Active, Status::Locked { until: 42 }],
+        }
+    }
+
+    #[test]
+    fn test_borsh_deserialize_rust_struct() {
+        setup_test_env();
+        let bytes = borsh::to_vec(&account()).unwrap();
+
+        let result = call_with_bytes(
+            "
+            const account = env.borsh_deserialize(schema, bytes);
+            account.key = Array.from(account.key);
+            return JSON.stringify(account, (key, value) =>
+                typeof value === 'bigint' ? value + 'n' : value
+            );
+            ",
+            &bytes,
+        );
+
+        assert_eq!(
+            concat!(
+                r#"{"owner_id":"alice.near","balance":"1000000000000000000000000000000n","#,
+                r#""memo":null,"history":[-1,2,300000],"key":[1,2,3,4],"#,
+                r#""allowances":{"alice.near":"18446744073709551615n","bob.near":"5n"},"#,
+                r#""statuses":[{"Active":{}},{"Locked":{"until":"42n"}}]}"#
+            ),
+            result.to_string()
+        );
+    }
+
+    #[test]
+    fn test_borsh_serialize_matches_rust() {
+        setup_test_env();
+        let bytes = borsh::to_vec(&account()).unwrap();
+
+        let result = call_with_bytes(
+            "
+            return env.borsh_serialize(schema, {
+                owner_id: 'alice.near',
+                balance: 10n ** 30n,
+                history: [-1, 2, 300000],
+                key: new Uint8Array([1, 2, 3, 4]),
+                allowances: { 'bob.near': 5, 'alice.near': '18446744073709551615' },
+                statuses: [{ Active: {} }, { Locked: { until: 42 } }],
+            });
+            ",
+            &[],
+        );
+
+        assert_eq!(bytes, result.to_bytes());
+    }
+
+    #[test]
+    fn test_borsh_errors() {
+        setup_test_env();
+        let result = call_with_bytes(
+            "
+            const errors = [];
+            for (const f of [
+                () => env.borsh_serialize(schema, { owner_id: 'alice.near', balance: -1 }),
+                () => env.borsh_serialize({ struct: { a: 'u256' } }, { a: 1 }),
+                () => env.borsh_deserialize(schema, bytes),
+                () => env.borsh_deserialize('u8', new Uint8Array([1, 2])),
+                () => env.borsh_serialize(
+                    { enum: Array.from({ length: 257 }, (_, i) => ({ struct: { ['V' + i]: 'u8' } })) },
+                    { V0: 1 },
+                ),
+            ]) {
+                try {
+                    f();
+                } catch (e) {
+                    errors.push(e.name + ': ' + e.message);
+                }
+            }
+            return errors.join('\\n');
+            ",
+            &[10, 0, 0, 0],
+        );
+
+        assert_eq!(
+            [
+                "RangeError: borsh_serialize: balance: expected an integer in the range of u128",
+                "TypeError: borsh_serialize: invalid schema: unknown type 'u256'",
+                "Error: borsh_deserialize: invalid borsh data: Unexpected length of input",
+                "Error: borsh_deserialize: invalid borsh data: 1 bytes left after the value",
+                "TypeError: borsh_serialize: invalid schema: an enum can have at most 256 variants",
+            ]
+            .join("\n"),
+            result.to_string()
+        );
+    }
+}
@@ -0,0 +1,77 @@
+//! Bounding the gas that on-chain JavaScript may use.
+//!
+//! QuickJS calls an interrupt handler periodically while executing. The handler installed here
+//! compares the gas used so far with the limit of the [`ExecutionBudget`] in the
+//! [`RuntimeConfig`](super::RuntimeConfig), and interrupts the script once it is exceeded. The
+//! interrupt can not be caught by the script, and surfaces as a [`JsError`](super::JsError) of
+//! kind [`JsErrorKind::OutOfBudget`](super::JsErrorKind), with the reserved gas left for the
+//! contract to log or refund before it aborts.
+
+use near_sdk::{env, Gas};
+use std::cell::Cell;
+
+extern "C" {
+    fn JS_GetRuntime(ctx: i32) -> i32;
+    fn JS_SetInterruptHandler(rt: i32, cb: i32, opaque: i32);
+}
+
+pub const DEFAULT_GAS_RESERVE: Gas = Gas::from_tgas(5);
+
+/// How much of the prepaid gas JavaScript execution may use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExecutionBudget {
+    /// Gas kept back from the script, for the contract to use once it is interrupted.
+    pub gas_reserve: Gas,
+}
+
+impl ExecutionBudget {
+    pub fn with_gas_reserve(gas_reserve: Gas) -> Self {
+        ExecutionBudget { gas_reserve }
+    }
+
+    /// The total gas used, including by the contract before running the script, at which the
+    /// script is interrupted.
+    pub fn gas_limit(&self) -> Gas {
+        env::prepaid_gas().saturating_sub(self.gas_reserve)
+    }
+}
+
+impl Default for ExecutionBudget {
+    fn default() -> Self {
+        Self::with_gas_reserve(DEFAULT_GAS_RESERVE)
+    }
+}
+
+thread_local! {
+    static GAS_LIMIT: Cell<Option<Gas>> = const { Cell::new(None) };
+    static INTERRUPTED: Cell<bool> = const { Cell::new(false) };
+}
+
+fn interrupt_handler(_rt: i32, _opaque: i32) -> i32 {
+    let Some(gas_limit) = GAS_LIMIT.with(|gas_limit| gas_limit.get()) else {
+        return 0;
+    };
+    if env::used_gas() >= gas_limit {
+        INTERRUPTED.with(|interrupted| interrupted.set(true));
+        return 1;
+    }
+    0
+}
+
+/// Installs the interrupt handler on the runtime of `ctx`, with the limit of `budget`.
+pub(crate) fn install_interrupt_handler(ctx: i32, budget: Option<ExecutionBudget>) {
+    GAS_LIMIT.with(|gas_limit| gas_limit.set(budget.map(|budget| budget.gas_limit())));
+    INTERRUPTED.with(|interrupted| interrupted.set(false));
+    unsafe {
+        JS_SetInterruptHandler(
+            JS_GetRuntime(ctx),
+            interrupt_handler as fn(i32, i32) -> i32 as usize as i32,
+            0,
+        );
+    }
+}
+
+/// Whether the budget interrupted the script since the handler was installed.
+pub(crate) fn was_interrupted() -> bool {
+    INTERRUPTED.with(|interrupted| interrupted.get())
+}
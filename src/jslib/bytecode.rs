@@ -0,0 +1,103 @@
+//! Checks for uploaded module bytecode, before it is stored and loaded.
+//!
+//! QuickJS reads bytecode with `JS_ReadObject`, which trusts it to come from the same version of
+//! the engine. [`validate_module_bytecode`] reads it without running it, so that bytecode with
+//! another version header, or truncated or otherwise malformed bytecode, is rejected on upload
+//! rather than when a contract method loads it. It does not verify the instructions, so the
+//! bytecode must still come from a trusted compiler. [`bytecode_hash`] identifies the stored
+//! code, for deployments to check which script is live.
+
+use std::ffi::CStr;
+
+use near_sdk::env;
+
+use super::JsError;
+
+extern "C" {
+    fn js_validate_module_bytecode(
+        buf: *const u8,
+        buf_len: usize,
+        error_buf: *mut u8,
+        error_buf_len: usize,
+    ) -> i32;
+}
+
+/// Checks that `bytecode` is a module compiled by the QuickJS version of this contract, and that
+/// it can be read. It is read in a runtime of its own, which is freed afterwards.
+pub fn validate_module_bytecode(bytecode: &[u8]) -> Result<(), JsError> {
+    if bytecode.is_empty() {
+        return Err(JsError::new("Error", "the bytecode is empty"));
+    }
+    let mut error_buf = [0u8; 256];
+    let valid = unsafe {
+        js_validate_module_bytecode(
+            bytecode.as_ptr(),
+            bytecode.len(),
+            error_buf.as_mut_ptr(),
+            error_buf.len(),
+        )
+    };
+    if valid != 0 {
+        return Ok(());
+    }
+    let error = CStr::from_bytes_until_nul(&error_buf)
+        .map(|error| error.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    // the error is written as `name: message`, like the `toString` of an error object
+    Err(match error.split_once(": ") {
+        Some((name, message)) => JsError::new(name, message),
+        None => JsError::new("Error", error),
+    })
+}
+
+/// The SHA-256 of `bytecode`, in hex.
+pub fn bytecode_hash(bytecode: &[u8]) -> String {
+    env::sha256(bytecode)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bytecode_hash, validate_module_bytecode};
+    use crate::jslib::compile_js;
+    use quickjs_rust_near_testenv::testenv::setup_test_env;
+
+    #[test]
+    fn test_validate_module_bytecode() {
+        setup_test_env();
+        let bytecode = compile_js(
+            "export function hello() { return 'hello'; }".to_string(),
+            Some("main.js".to_string()),
+        );
+        assert!(validate_module_bytecode(&bytecode).is_ok());
+
+        let script_bytecode = compile_js("'not a module'".to_string(), None);
+        assert_eq!(
+            "bytecode is not a module",
+            validate_module_bytecode(&script_bytecode)
+                .unwrap_err()
+                .message
+        );
+
+        assert!(validate_module_bytecode(&bytecode[..bytecode.len() / 2]).is_err());
+        assert!(validate_module_bytecode(&[]).is_err());
+
+        let mut other_version = bytecode.clone();
+        other_version[0] = other_version[0].wrapping_add(1);
+        assert!(validate_module_bytecode(&other_version)
+            .unwrap_err()
+            .message
+            .starts_with("invalid version"));
+    }
+
+    #[test]
+    fn test_bytecode_hash() {
+        setup_test_env();
+        assert_eq!(
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            bytecode_hash(b"abc")
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! Console output of scripts, logged through `env::log_str` at a configurable level.
+//!
+//! Scripts get a `console` object with the levels below. Messages under the log level of the
+//! [`RuntimeConfig`](super::RuntimeConfig) are dropped, which by default are the `debug` ones in
+//! release builds:
+//!
+//! | function | level | logged as |
+//! |---|---|---|
+//! | `console.debug(...)` | [`LogLevel::Debug`] | `message` |
+//! | `console.log(...)`, `console.info(...)`, `print(...)` | [`LogLevel::Info`] | `message` |
+//! | `console.warn(...)` | [`LogLevel::Warn`] | `WARN: message` |
+//! | `console.error(...)` | [`LogLevel::Error`] | `ERROR: message` |
+//!
+//! The arguments are joined with spaces, with objects and arrays given as JSON. What QuickJS
+//! itself writes to stderr, such as uncaught exceptions, is logged at [`LogLevel::Error`].
+
+use near_sdk::env;
+use std::cell::Cell;
+use std::ffi::CString;
+
+use super::JsValue;
+
+extern "C" {
+    fn JS_IsError(ctx: i32, val: i64) -> i32;
+    fn js_add_global_method_magic(object_name: i32, name: i32, func: i32, length: i32, magic: i32);
+}
+
+/// The severity of a message, from the most verbose to [`LogLevel::Off`], which as the log level
+/// drops all messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Off,
+}
+
+impl LogLevel {
+    fn prefix(self) -> &'static str {
+        match self {
+            LogLevel::Warn => "WARN: ",
+            LogLevel::Error => "ERROR: ",
+            _ => "",
+        }
+    }
+}
+
+/// The log level of a [`RuntimeConfig`](super::RuntimeConfig) unless configured otherwise:
+/// everything in debug builds, and everything but debug messages in release builds.
+pub const DEFAULT_LOG_LEVEL: LogLevel = if cfg!(debug_assertions) {
+    LogLevel::Debug
+} else {
+    LogLevel::Info
+};
+
+thread_local! {
+    static LOG_LEVEL: Cell<LogLevel> = const { Cell::new(DEFAULT_LOG_LEVEL) };
+}
+
+/// Sets the level below which messages are dropped.
+pub(crate) fn set_log_level(level: LogLevel) {
+    LOG_LEVEL.with(|log_level| log_level.set(level));
+}
+
+/// Logs `message` with the prefix of `level`, unless it is below the current log level.
+pub fn log(level: LogLevel, message: &str) {
+    if level == LogLevel::Off || level < LOG_LEVEL.with(|log_level| log_level.get()) {
+        return;
+    }
+    env::log_str(&format!("{}{}", level.prefix(), message));
+}
+
+fn format_arg(value: &JsValue) -> String {
+    let is_error = unsafe { JS_IsError(value.context(), value.as_raw()) != 0 };
+    if value.is_object() && !value.is_function() && !is_error {
+        if let Some(json) = value.to_json_string() {
+            return json;
+        }
+    }
+    value.to_string()
+}
+
+const CONSOLE_METHODS: [(&str, LogLevel); 5] = [
+    ("debug", LogLevel::Debug),
+    ("log", LogLevel::Info),
+    ("info", LogLevel::Info),
+    ("warn", LogLevel::Warn),
+    ("error", LogLevel::Error),
+];
+
+fn console_func(ctx: i32, _this_val: i64, argc: i32, argv: i32, magic: i32) -> i64 {
+    let (_, level) = CONSOLE_METHODS[magic as usize];
+    let message = (0..argc as usize)
+        .map(|n| {
+            let arg = unsafe { JsValue::from_borrowed(ctx, *(argv as *const i64).add(n)) };
+            format_arg(&arg)
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    log(level, &message);
+    JsValue::undefined().into_raw()
+}
+
+/// Adds the `console` object to the global object of the runtime being set up.
+pub(crate) fn install_console() {
+    let object_name = CString::new("console").unwrap();
+    for (magic, (name, _)) in CONSOLE_METHODS.iter().enumerate() {
+        let name = CString::new(*name).unwrap();
+        unsafe {
+            js_add_global_method_magic(
+                object_name.as_ptr() as i32,
+                name.as_ptr() as i32,
+                console_func as fn(i32, i64, i32, i32, i32) -> i64 as usize as i32,
+                1,
+                magic as i32,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LogLevel;
+    use crate::jslib::{run_js_with_config, RuntimeConfig};
+    use quickjs_rust_near_testenv::testenv::{logs, setup_test_env};
+
+    const SCRIPT: &str = "
+        console.debug('debugging', 1);
+        console.log('hello', { greeting: 'hi', to: ['alice'] });
+        print('printed');
+        console.warn('careful');
+        console.error(new Error('failed'));
+    ";
+
+    #[test]
+    fn test_console_output() {
+        setup_test_env();
+        run_js_with_config(
+            SCRIPT.to_string(),
+            &RuntimeConfig::default().with_log_level(LogLevel::Debug),
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                "debugging 1",
+                r#"hello {"greeting":"hi","to":["alice"]}"#,
+                "printed",
+                "WARN: careful",
+                "ERROR: Error: failed",
+            ],
+            logs()
+        );
+    }
+
+    #[test]
+    fn test_console_output_below_log_level_is_dropped() {
+        setup_test_env();
+        run_js_with_config(
+            SCRIPT.to_string(),
+            &RuntimeConfig::default().with_log_level(LogLevel::Warn),
+        )
+        .unwrap();
+        assert_eq!(vec!["WARN: careful", "ERROR: Error: failed"], logs());
+
+        setup_test_env();
+        run_js_with_config(
+            SCRIPT.to_string(),
+            &RuntimeConfig::default().with_log_level(LogLevel::Off),
+        )
+        .unwrap();
+        assert!(logs().is_empty());
+    }
+}
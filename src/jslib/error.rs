@@ -0,0 +1,201 @@
+use std::fmt;
+
+use super::budget::was_interrupted;
+use super::jsvalue::take_exception;
+use super::sourcemap::SourceMap;
+use super::JsValue;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsErrorKind {
+    /// A value thrown by the script.
+    Exception,
+    /// The script was interrupted for exceeding its [`ExecutionBudget`](super::ExecutionBudget).
+    OutOfBudget,
+}
+
+/// An exception thrown by JavaScript code and not caught by it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsError {
+    pub kind: JsErrorKind,
+    /// The error name, such as `TypeError`. Empty if the thrown value is not an `Error`.
+    pub name: String,
+    pub message: String,
+    pub stack: Option<String>,
+    pub file_name: Option<String>,
+    pub line_number: Option<u32>,
+    pub column_number: Option<u32>,
+}
+
+impl JsError {
+    /// An error raised on the Rust side of a call, without a location in the script.
+    pub fn new(name: impl Into<String>, message: impl Into<String>) -> Self {
+        JsError {
+            kind: JsErrorKind::Exception,
+            name: name.into(),
+            message: message.into(),
+            stack: None,
+            file_name: None,
+            line_number: None,
+            column_number: None,
+        }
+    }
+
+    /// Builds the error from a thrown value, which is usually an `Error` object but may be
+    /// anything, e.g. with `throw 'failed'`.
+    pub fn from_value(value: &JsValue) -> Self {
+        if !value.is_object() {
+            return JsError::new("", value.to_string());
+        }
+        let stack = value.get_property("stack");
+        let stack = stack.is_string().then(|| stack.to_string());
+        let location = stack.as_deref().and_then(parse_stack_location);
+        let (file_name, line_number, column_number) = match location {
+            Some((file_name, line_number, column_number)) => {
+                (Some(file_name), Some(line_number), column_number)
+            }
+            None => (None, None, None),
+        };
+        let name = value.get_property("name");
+        JsError {
+            kind: JsErrorKind::Exception,
+            name: if name.is_string() {
+                name.to_string()
+            } else {
+                String::new()
+            },
+            message: value.get_property("message").to_string(),
+            stack,
+            file_name,
+            line_number,
+            column_number,
+        }
+    }
+
+    /// Builds the error from a value thrown during execution, which is the interrupt if the
+    /// budget was exceeded.
+    pub(crate) fn from_thrown(value: &JsValue) -> Self {
+        let mut error = JsError::from_value(value);
+        if was_interrupted() {
+            error.kind = JsErrorKind::OutOfBudget;
+            error.message = "JavaScript execution exceeded its gas budget".to_string();
+        }
+        error
+    }
+
+    /// Takes the pending exception of `ctx`.
+    pub(crate) fn from_exception(ctx: i32) -> Self {
+        JsError::from_thrown(&take_exception(ctx))
+    }
+
+    /// Translates the location and the stack frames in `file_name`, the generated code of
+    /// `source_map`, to their locations in the original sources. Frames in other files or
+    /// without a mapping are left as they are.
+    pub fn with_source_map(mut self, file_name: &str, source_map: &SourceMap) -> Self {
+        if self.file_name.as_deref() == Some(file_name) {
+            if let Some(original) = self
+                .line_number
+                .and_then(|line_number| source_map.lookup(line_number, self.column_number))
+            {
+                self.file_name = Some(original.source);
+                self.line_number = Some(original.line_number);
+                self.column_number = Some(original.column_number);
+            }
+        }
+        self.stack = self.stack.map(|stack| {
+            stack
+                .split_inclusive('\n')
+                .map(|frame| {
+                    let Some((location, (frame_file_name, line_number, column_number))) =
+                        parse_frame_location(frame)
+                    else {
+                        return frame.to_string();
+                    };
+                    let original = (frame_file_name == file_name)
+                        .then(|| source_map.lookup(line_number, column_number))
+                        .flatten();
+                    match original {
+                        Some(original) => frame.replacen(
+                            location,
+                            &format!(
+                                "{}:{}:{}",
+                                original.source, original.line_number, original.column_number
+                            ),
+                            1,
+                        ),
+                        None => frame.to_string(),
+                    }
+                })
+                .collect()
+        });
+        self
+    }
+}
+
+/// A file name, line number and column number, if any.
+type Location = (String, u32, Option<u32>);
+
+/// Finds the location of the innermost stack frame with one. QuickJS formats the frames as
+/// `    at name (file:line:column)`, with `(native)` for host functions.
+fn parse_stack_location(stack: &str) -> Option<Location> {
+    stack
+        .lines()
+        .find_map(|frame| parse_frame_location(frame).map(|(_, location)| location))
+}
+
+/// The location of a stack frame, both as it is written and parsed.
+fn parse_frame_location(frame: &str) -> Option<(&str, Location)> {
+    let frame = frame.trim().strip_prefix("at ")?;
+    let location = match frame.rfind('(') {
+        Some(start) => frame[start + 1..].strip_suffix(')')?,
+        None => frame,
+    };
+    let mut parts = location.rsplitn(3, ':');
+    let last: u32 = parts.next()?.parse().ok()?;
+    let second_last = parts.next()?;
+    let parsed = match second_last.parse::<u32>() {
+        Ok(line_number) => (parts.next()?.to_string(), line_number, Some(last)),
+        Err(_) => {
+            // no column, so the file name is everything before the line number
+            let file_name = location.rsplit_once(':')?.0;
+            (file_name.to_string(), last, None)
+        }
+    };
+    Some((location, parsed))
+}
+
+impl fmt::Display for JsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.name.is_empty() {
+            write!(f, "Uncaught {}", self.message)?;
+        } else {
+            write!(f, "{}: {}", self.name, self.message)?;
+        }
+        if let (Some(file_name), Some(line_number)) = (&self.file_name, self.line_number) {
+            write!(f, " at {}:{}", file_name, line_number)?;
+            if let Some(column_number) = self.column_number {
+                write!(f, ":{}", column_number)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for JsError {}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_stack_location;
+
+    #[test]
+    fn test_parse_stack_location() {
+        assert_eq!(
+            Some(("main.js".to_string(), 3, Some(23))),
+            parse_stack_location("    at withdraw (native)\n    at run (main.js:3:23)\n")
+        );
+        assert_eq!(
+            Some(("main.js".to_string(), 7, None)),
+            parse_stack_location("    at <eval> (main.js:7)\n")
+        );
+        assert_eq!(None, parse_stack_location("    at withdraw (native)\n"));
+    }
+}
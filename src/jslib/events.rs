@@ -0,0 +1,205 @@
+//! [NEP-297](https://nomicon.io/Standards/EventsFormat) events for scripts, logged as
+//! `EVENT_JSON:` so that explorers and indexers pick them up.
+//!
+//! Only contracts whose scripts are trusted to report their own token transfers should add them
+//! with [`add_event_functions`], since a script able to emit events can make up transfers that
+//! never happened.
+//!
+//! | function | event |
+//! |---|---|
+//! | `env.emit_event(standard, version, event, data)` | any event, with optional `data` |
+//! | `env.emit_ft_transfer(data)` | NEP-141 `ft_transfer` |
+//! | `env.emit_nft_mint(data)` | NEP-171 `nft_mint` |
+//! | `env.emit_nft_transfer(data)` | NEP-171 `nft_transfer` |
+//!
+//! The data of the token events has the fields of the `FtTransfer`, `NftMint` and `NftTransfer`
+//! events of `near_contract_standards`, and may be a single object or an array of them:
+//!
+//! ```js
+//! env.emit_ft_transfer({ old_owner_id: 'bob.near', new_owner_id: 'alice.near', amount: '100' });
+//! ```
+
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, serde_json, AccountId};
+
+use super::host::HostFunctions;
+use super::{HostResult, Json};
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct Event<'a, T> {
+    standard: &'a str,
+    version: &'a str,
+    event: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<T>,
+}
+
+fn emit<T: Serialize>(
+    standard: &str,
+    version: &str,
+    event: &str,
+    data: Option<T>,
+) -> HostResult<()> {
+    let event = Event {
+        standard,
+        version,
+        event,
+        data,
+    };
+    env::log_str(&format!("EVENT_JSON:{}", serde_json::to_string(&event)?));
+    Ok(())
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde", untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> From<OneOrMany<T>> for Vec<T> {
+    fn from(data: OneOrMany<T>) -> Self {
+        match data {
+            OneOrMany::One(data) => vec![data],
+            OneOrMany::Many(data) => data,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde", deny_unknown_fields)]
+struct FtTransfer {
+    old_owner_id: AccountId,
+    new_owner_id: AccountId,
+    amount: U128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memo: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde", deny_unknown_fields)]
+struct NftMint {
+    owner_id: AccountId,
+    token_ids: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memo: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde", deny_unknown_fields)]
+struct NftTransfer {
+    old_owner_id: AccountId,
+    new_owner_id: AccountId,
+    token_ids: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    authorized_id: Option<AccountId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memo: Option<String>,
+}
+
+/// Adds the event functions to `host`.
+pub fn add_event_functions(host: &mut HostFunctions<'_>) {
+    host.add(
+        "emit_event",
+        |standard: String,
+         version: String,
+         event: String,
+         data: Option<serde_json::Value>|
+         -> HostResult<()> { emit(&standard, &version, &event, data) },
+    );
+    host.add(
+        "emit_ft_transfer",
+        |data: Json<OneOrMany<FtTransfer>>| -> HostResult<()> {
+            emit("nep141", "1.0.0", "ft_transfer", Some(Vec::from(data.0)))
+        },
+    );
+    host.add(
+        "emit_nft_mint",
+        |data: Json<OneOrMany<NftMint>>| -> HostResult<()> {
+            emit("nep171", "1.0.0", "nft_mint", Some(Vec::from(data.0)))
+        },
+    );
+    host.add(
+        "emit_nft_transfer",
+        |data: Json<OneOrMany<NftTransfer>>| -> HostResult<()> {
+            emit("nep171", "1.0.0", "nft_transfer", Some(Vec::from(data.0)))
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::add_event_functions;
+    use crate::jslib::host::run_with_host_functions;
+    use quickjs_rust_near_testenv::testenv::{logs, setup_test_env};
+
+    #[test]
+    fn test_emit_event() {
+        setup_test_env();
+        run_with_host_functions(
+            "
+            env.emit_event('nep999', '2.0.0', 'game_over', [{ winner: 'alice.near' }]);
+            env.emit_event('nep999', '2.0.0', 'game_started');
+            ",
+            add_event_functions,
+        );
+
+        assert_eq!(
+            vec![
+                r#"EVENT_JSON:{"standard":"nep999","version":"2.0.0","event":"game_over","data":[{"winner":"alice.near"}]}"#,
+                r#"EVENT_JSON:{"standard":"nep999","version":"2.0.0","event":"game_started"}"#,
+            ],
+            logs()
+        );
+    }
+
+    #[test]
+    fn test_emit_token_events() {
+        setup_test_env();
+        run_with_host_functions(
+            "
+            env.emit_ft_transfer({ old_owner_id: 'bob.near', new_owner_id: 'alice.near', amount: '100' });
+            env.emit_nft_mint([
+                { owner_id: 'alice.near', token_ids: ['1', '2'] },
+                { owner_id: 'bob.near', token_ids: ['3'], memo: 'has memo' },
+            ]);
+            env.emit_nft_transfer({
+                old_owner_id: 'alice.near',
+                new_owner_id: 'bob.near',
+                token_ids: ['1'],
+                authorized_id: 'carol.near',
+            });
+            ",
+            add_event_functions,
+        );
+
+        assert_eq!(
+            vec![
+                r#"EVENT_JSON:{"standard":"nep141","version":"1.0.0","event":"ft_transfer","data":[{"old_owner_id":"bob.near","new_owner_id":"alice.near","amount":"100"}]}"#,
+                r#"EVENT_JSON:{"standard":"nep171","version":"1.0.0","event":"nft_mint","data":[{"owner_id":"alice.near","token_ids":["1","2"]},{"owner_id":"bob.near","token_ids":["3"],"memo":"has memo"}]}"#,
+                r#"EVENT_JSON:{"standard":"nep171","version":"1.0.0","event":"nft_transfer","data":[{"old_owner_id":"alice.near","new_owner_id":"bob.near","token_ids":["1"],"authorized_id":"carol.near"}]}"#,
+            ],
+            logs()
+        );
+    }
+
+    #[test]
+    fn test_emit_token_event_rejects_invalid_data() {
+        setup_test_env();
+        let result = run_with_host_functions(
+            "
+            try {
+                env.emit_ft_transfer({ old_owner_id: 'bob.near', new_owner_id: 'alice.near', amount: 100 });
+            } catch (e) {
+                return e.name;
+            }
+            ",
+            add_event_functions,
+        );
+
+        assert_eq!("TypeError", result.to_string());
+        assert!(logs().is_empty());
+    }
+}
@@ -0,0 +1,609 @@
+//! Registration of Rust closures as functions on the JavaScript `env` object.
+//!
+//! Unlike [`add_function_to_js`](super::add_function_to_js), which only takes a plain function
+//! pointer, the closures registered here may capture the contract state they operate on:
+//!
+//! ```ignore
+//! with_host_functions(|host| {
+//!     host.add("get_data", |key: String| -> HostResult<String> {
+//!         Ok(self.data.get(&key).unwrap_or_default())
+//!     });
+//!     jsmod.call_function("main");
+//! });
+//! ```
+//!
+//! Arguments are decoded with [`FromJs`] and the returned value converted with [`IntoJs`]. A
+//! missing or mistyped argument is thrown as a `TypeError` into the calling JavaScript code, and
+//! an `Err` returned from the closure as the kind of error given by its [`HostErrorKind`]. Scripts
+//! can catch these like any other exception.
+//!
+//! The optional groups of host functions, such as
+//! [`add_storage_functions`](super::add_storage_functions), are added to a [`HostFunctions`] the
+//! same way, so a contract only exposes the ones its scripts need:
+//!
+//! ```ignore
+//! with_host_functions(|host| {
+//!     add_storage_functions(host, "JSS_");
+//!     add_event_functions(host);
+//!     jsmod.call_function("main");
+//! });
+//! ```
+
+use near_sdk::json_types::U128;
+use near_sdk::serde::{de::DeserializeOwned, Serialize};
+use near_sdk::{serde_json, AccountId};
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::fmt;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+use super::JsValue;
+
+extern "C" {
+    fn js_add_host_function_magic(name: i32, func: i32, length: i32, magic: i32);
+    fn js_throw_error(ctx: i32, error_kind: i32, message: i32) -> i64;
+}
+
+/// The JavaScript error constructor a [`HostError`] is thrown as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostErrorKind {
+    Error = 0,
+    TypeError = 1,
+    RangeError = 2,
+}
+
+/// The error returned from a host function, thrown as an exception in JavaScript.
+///
+/// Anything implementing [`Display`](fmt::Display) converts into a `HostError` of kind
+/// [`HostErrorKind::Error`], so `?` can be used directly on most results inside a host function.
+#[derive(Debug)]
+pub struct HostError {
+    pub kind: HostErrorKind,
+    pub message: String,
+}
+
+impl HostError {
+    pub fn new(kind: HostErrorKind, message: impl Into<String>) -> Self {
+        HostError {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    pub fn type_error(message: impl Into<String>) -> Self {
+        Self::new(HostErrorKind::TypeError, message)
+    }
+
+    pub fn range_error(message: impl Into<String>) -> Self {
+        Self::new(HostErrorKind::RangeError, message)
+    }
+
+    /// Throws the error in `ctx`, returning the exception value a raw host function, as
+    /// registered with [`add_function_to_js`](super::add_function_to_js), should return.
+    pub fn throw(self, ctx: i32) -> i64 {
+        let message = CString::new(self.message.replace('\0', "")).unwrap();
+        unsafe { js_throw_error(ctx, self.kind as i32, message.as_ptr() as i32) }
+    }
+}
+
+impl<T: fmt::Display> From<T> for HostError {
+    fn from(error: T) -> Self {
+        HostError::new(HostErrorKind::Error, error.to_string())
+    }
+}
+
+pub type HostResult<T> = Result<T, HostError>;
+
+/// Wraps a value that is passed between Rust and JavaScript as JSON, using serde.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Json<T>(pub T);
+
+/// The arguments of a host function call.
+pub struct JsArgs {
+    ctx: i32,
+    argc: i32,
+    argv: i32,
+}
+
+impl JsArgs {
+    pub fn len(&self) -> usize {
+        self.argc.max(0) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<JsValue> {
+        if index >= self.len() {
+            return None;
+        }
+        let argv_ptr = (self.argv as usize + index * 8) as *const i64;
+        unsafe { Some(JsValue::from_borrowed(self.ctx, *argv_ptr)) }
+    }
+
+    /// Decodes the argument at `index`, failing if it is missing unless `T` is an `Option`.
+    pub fn decode<T: FromJs>(&self, index: usize) -> HostResult<T> {
+        match self.get(index) {
+            Some(value) => T::from_js(&value).map_err(|err| {
+                HostError::new(err.kind, format!("argument {}: {}", index, err.message))
+            }),
+            None => T::from_missing().ok_or_else(|| {
+                HostError::type_error(format!(
+                    "expected at least {} argument{}, got {}",
+                    index + 1,
+                    if index == 0 { "" } else { "s" },
+                    self.len()
+                ))
+            }),
+        }
+    }
+}
+
+/// Conversion of a JavaScript value into a host function argument.
+///
+/// Values of the wrong type should fail with a `TypeError`, and values out of range for `Self`
+/// with a `RangeError`.
+pub trait FromJs: Sized {
+    fn from_js(value: &JsValue) -> HostResult<Self>;
+
+    /// The value to use when the argument is not passed at all.
+    fn from_missing() -> Option<Self> {
+        None
+    }
+}
+
+fn expected<T>(what: &str) -> HostResult<T> {
+    Err(HostError::type_error(format!("expected {}", what)))
+}
+
+fn out_of_range<T>(what: &str) -> HostResult<T> {
+    Err(HostError::range_error(format!("expected {}", what)))
+}
+
+impl FromJs for JsValue {
+    fn from_js(value: &JsValue) -> HostResult<Self> {
+        Ok(value.clone())
+    }
+}
+
+impl FromJs for String {
+    fn from_js(value: &JsValue) -> HostResult<Self> {
+        if !value.is_string() {
+            return expected("a string");
+        }
+        Ok(value.to_string())
+    }
+}
+
+impl FromJs for bool {
+    fn from_js(value: &JsValue) -> HostResult<Self> {
+        if !value.is_bool() {
+            return expected("a boolean");
+        }
+        Ok(value.to_bool())
+    }
+}
+
+impl FromJs for f64 {
+    fn from_js(value: &JsValue) -> HostResult<Self> {
+        if !value.is_number() {
+            return expected("a number");
+        }
+        Ok(value.to_f64())
+    }
+}
+
+/// Whether `value` is an integer in decimal notation, as opposed to any other string.
+fn is_decimal(value: &str) -> bool {
+    let digits = value.strip_prefix(['-', '+']).unwrap_or(value);
+    !digits.is_empty() && digits.bytes().all(|byte| byte.is_ascii_digit())
+}
+
+macro_rules! impl_from_js_for_int {
+    ($($t:ty),*) => {
+        $(impl FromJs for $t {
+            fn from_js(value: &JsValue) -> HostResult<Self> {
+                let number = f64::from_js(value)?;
+                // MAX + 1 is a power of two, which MAX itself rounds up to as an f64 for i64
+                let upper_bound = <$t>::MAX as f64 + 1.0;
+                if number.fract() != 0.0 || number < <$t>::MIN as f64 || number >= upper_bound {
+                    return out_of_range(concat!("an integer in the range of ", stringify!($t)));
+                }
+                Ok(number as $t)
+            }
+        })*
+    };
+}
+
+impl_from_js_for_int!(i32, u32, i64);
+
+/// Numbers beyond 2^53 lose precision in JavaScript, so amounts such as yoctoNEAR are also
+/// accepted as BigInts and decimal strings.
+macro_rules! impl_from_js_for_amount {
+    ($($t:ty),*) => {
+        $(impl FromJs for $t {
+            fn from_js(value: &JsValue) -> HostResult<Self> {
+                if value.is_bigint() {
+                    return value.to_string().parse::<$t>().or_else(|_| {
+                        out_of_range(concat!("a BigInt in the range of ", stringify!($t)))
+                    });
+                }
+                if value.is_string() {
+                    let decimal = value.to_string();
+                    if !is_decimal(&decimal) {
+                        return expected("a number or a decimal string");
+                    }
+                    return decimal.parse::<$t>().or_else(|_| {
+                        out_of_range(concat!("a decimal string in the range of ", stringify!($t)))
+                    });
+                }
+                let number = f64::from_js(value).or_else(|_| expected("a number or a decimal string"))?;
+                let upper_bound = <$t>::MAX as f64 + 1.0;
+                if number.fract() != 0.0 || number < 0.0 || number >= upper_bound {
+                    return out_of_range(concat!("an integer in the range of ", stringify!($t)));
+                }
+                Ok(number as $t)
+            }
+        })*
+    };
+}
+
+impl_from_js_for_amount!(u64, u128);
+
+impl FromJs for U128 {
+    fn from_js(value: &JsValue) -> HostResult<Self> {
+        u128::from_js(value).map(U128)
+    }
+}
+
+impl FromJs for AccountId {
+    fn from_js(value: &JsValue) -> HostResult<Self> {
+        String::from_js(value)?
+            .parse::<AccountId>()
+            .map_err(|err| HostError::type_error(err.to_string()))
+    }
+}
+
+impl FromJs for Vec<u8> {
+    fn from_js(value: &JsValue) -> HostResult<Self> {
+        value
+            .try_to_bytes()
+            .map_or_else(|| expected("an ArrayBuffer or a typed array"), Ok)
+    }
+}
+
+/// Binary data, from an `ArrayBuffer` or a typed array, or from a string, which is encoded as
+/// UTF-8.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bytes(pub Vec<u8>);
+
+impl FromJs for Bytes {
+    fn from_js(value: &JsValue) -> HostResult<Self> {
+        if value.is_string() {
+            return Ok(Bytes(value.to_string().into_bytes()));
+        }
+        match value.try_to_bytes() {
+            Some(bytes) => Ok(Bytes(bytes)),
+            None => expected("a string, an ArrayBuffer or a typed array"),
+        }
+    }
+}
+
+impl FromJs for serde_json::Value {
+    fn from_js(value: &JsValue) -> HostResult<Self> {
+        Ok(value.to_json())
+    }
+}
+
+impl<T: DeserializeOwned> FromJs for Json<T> {
+    fn from_js(value: &JsValue) -> HostResult<Self> {
+        serde_json::from_value(value.to_json())
+            .map(Json)
+            .map_err(|err| HostError::type_error(err.to_string()))
+    }
+}
+
+impl<T: FromJs> FromJs for Option<T> {
+    fn from_js(value: &JsValue) -> HostResult<Self> {
+        if value.is_undefined() || value.is_null() {
+            return Ok(None);
+        }
+        T::from_js(value).map(Some)
+    }
+
+    fn from_missing() -> Option<Self> {
+        Some(None)
+    }
+}
+
+/// Conversion of a host function result into a JavaScript value.
+pub trait IntoJs {
+    fn into_js(self) -> HostResult<JsValue>;
+}
+
+impl IntoJs for () {
+    fn into_js(self) -> HostResult<JsValue> {
+        Ok(JsValue::undefined())
+    }
+}
+
+impl IntoJs for JsValue {
+    fn into_js(self) -> HostResult<JsValue> {
+        Ok(self)
+    }
+}
+
+impl IntoJs for String {
+    fn into_js(self) -> HostResult<JsValue> {
+        Ok(JsValue::from_string(&self))
+    }
+}
+
+impl IntoJs for &str {
+    fn into_js(self) -> HostResult<JsValue> {
+        Ok(JsValue::from_string(self))
+    }
+}
+
+impl IntoJs for bool {
+    fn into_js(self) -> HostResult<JsValue> {
+        Ok(JsValue::from_bool(self))
+    }
+}
+
+impl IntoJs for i32 {
+    fn into_js(self) -> HostResult<JsValue> {
+        Ok(JsValue::from_i32(self))
+    }
+}
+
+/// Converted to a JavaScript number, which is exact up to 2^53.
+macro_rules! impl_into_js_for_number {
+    ($($t:ty),*) => {
+        $(impl IntoJs for $t {
+            fn into_js(self) -> HostResult<JsValue> {
+                Ok(JsValue::from_f64(self as f64))
+            }
+        })*
+    };
+}
+
+impl_into_js_for_number!(u32, i64, u64, f64);
+
+/// Converted to a BigInt, so that scripts can do exact arithmetic on amounts.
+impl IntoJs for u128 {
+    fn into_js(self) -> HostResult<JsValue> {
+        Ok(JsValue::from_u128(self))
+    }
+}
+
+/// Converted to a BigInt, like `u128`.
+impl IntoJs for U128 {
+    fn into_js(self) -> HostResult<JsValue> {
+        self.0.into_js()
+    }
+}
+
+impl IntoJs for AccountId {
+    fn into_js(self) -> HostResult<JsValue> {
+        Ok(JsValue::from_string(self.as_str()))
+    }
+}
+
+/// Converted to a `Uint8Array`.
+impl IntoJs for Vec<u8> {
+    fn into_js(self) -> HostResult<JsValue> {
+        Ok(JsValue::from_bytes(&self))
+    }
+}
+
+impl IntoJs for serde_json::Value {
+    fn into_js(self) -> HostResult<JsValue> {
+        Ok(JsValue::from_json(&self))
+    }
+}
+
+impl<T: Serialize> IntoJs for Json<T> {
+    fn into_js(self) -> HostResult<JsValue> {
+        Ok(JsValue::from_json(&serde_json::to_value(self.0)?))
+    }
+}
+
+/// `None` is converted to `null`.
+impl<T: IntoJs> IntoJs for Option<T> {
+    fn into_js(self) -> HostResult<JsValue> {
+        match self {
+            Some(value) => value.into_js(),
+            None => Ok(JsValue::null()),
+        }
+    }
+}
+
+/// A closure that can be registered as a host function, taking arguments of types `Args`.
+///
+/// Implemented for closures of up to six [`FromJs`] arguments, or a single `&JsArgs` argument,
+/// returning a `Result` with an [`IntoJs`] value.
+pub trait HostFn<Args> {
+    /// The number of arguments, used as the `length` of the JavaScript function.
+    const ARITY: i32;
+
+    fn call(&self, args: &JsArgs) -> HostResult<JsValue>;
+}
+
+macro_rules! impl_host_fn {
+    ($arity:expr; $($arg:ident: $t:ident = $index:expr),*) => {
+        impl<F, R, E, $($t),*> HostFn<($($t,)*)> for F
+        where
+            F: Fn($($t),*) -> Result<R, E>,
+            R: IntoJs,
+            E: Into<HostError>,
+            $($t: FromJs,)*
+        {
+            const ARITY: i32 = $arity;
+
+            #[allow(unused_variables)]
+            fn call(&self, args: &JsArgs) -> HostResult<JsValue> {
+                $(let $arg = args.decode::<$t>($index)?;)*
+                self($($arg),*).map_err(Into::into)?.into_js()
+            }
+        }
+    };
+}
+
+/// Variadic host functions take the [`JsArgs`] as they are, decoding them with
+/// [`JsArgs::decode`].
+impl<F, R, E> HostFn<JsArgs> for F
+where
+    F: Fn(&JsArgs) -> Result<R, E>,
+    R: IntoJs,
+    E: Into<HostError>,
+{
+    const ARITY: i32 = 0;
+
+    fn call(&self, args: &JsArgs) -> HostResult<JsValue> {
+        self(args).map_err(Into::into)?.into_js()
+    }
+}
+
+impl_host_fn!(0;);
+impl_host_fn!(1; a: T1 = 0);
+impl_host_fn!(2; a: T1 = 0, b: T2 = 1);
+impl_host_fn!(3; a: T1 = 0, b: T2 = 1, c: T3 = 2);
+impl_host_fn!(4; a: T1 = 0, b: T2 = 1, c: T3 = 2, d: T4 = 3);
+impl_host_fn!(5; a: T1 = 0, b: T2 = 1, c: T3 = 2, d: T4 = 3, e: T5 = 4);
+impl_host_fn!(6; a: T1 = 0, b: T2 = 1, c: T3 = 2, d: T4 = 3, e: T5 = 4, f: T6 = 5);
+
+type BoxedHostFn<'env> = Rc<dyn Fn(&JsArgs) -> HostResult<JsValue> + 'env>;
+
+struct RegisteredHostFn {
+    name: String,
+    func: BoxedHostFn<'static>,
+}
+
+thread_local! {
+    // Indexed by the `magic` value the function is registered with in QuickJS. Slots are never
+    // reused, so a stale JavaScript reference can not end up calling another closure.
+    static HOST_FUNCTIONS: RefCell<Vec<Option<RegisteredHostFn>>> = const { RefCell::new(Vec::new()) };
+}
+
+fn host_function_trampoline(ctx: i32, _this_val: i64, argc: i32, argv: i32, magic: i32) -> i64 {
+    let registered = HOST_FUNCTIONS.with(|functions| {
+        functions
+            .borrow()
+            .get(magic as usize)
+            .and_then(|slot| slot.as_ref())
+            .map(|registered| (registered.name.clone(), registered.func.clone()))
+    });
+    let Some((name, func)) = registered else {
+        return HostError::from("host function is no longer available").throw(ctx);
+    };
+    call_host_fn(&name, &|args: &JsArgs| func(args), ctx, argc, argv)
+}
+
+/// The `length` the JavaScript function for `func` is created with.
+pub(crate) fn arity<Args, F: HostFn<Args>>(_func: &F) -> i32 {
+    F::ARITY
+}
+
+/// Calls `func` with the arguments of a raw host function, as registered with
+/// [`add_function_to_js`](super::add_function_to_js), throwing an error it returns with the
+/// function `name` prepended to the message.
+pub(crate) fn call_host_fn<Args>(
+    name: &str,
+    func: &impl HostFn<Args>,
+    ctx: i32,
+    argc: i32,
+    argv: i32,
+) -> i64 {
+    match func.call(&JsArgs { ctx, argc, argv }) {
+        Ok(value) => value.into_raw(),
+        Err(err) => HostError::new(err.kind, format!("{}: {}", name, err.message)).throw(ctx),
+    }
+}
+
+/// The host functions registered within [`with_host_functions`], which are only callable while
+/// it runs.
+pub struct HostFunctions<'env> {
+    indices: Vec<usize>,
+    // invariant, so that scopes of different lifetimes can not be swapped
+    _env: PhantomData<fn(&'env ()) -> &'env ()>,
+}
+
+impl<'env> HostFunctions<'env> {
+    /// Adds `func` to the JavaScript `env` object of the current runtime as `name`.
+    ///
+    /// The runtime must already be set up, e.g. by [`load_js_bytecode`](super::load_js_bytecode).
+    pub fn add<Args, F>(&mut self, name: &str, func: F) -> &mut Self
+    where
+        F: HostFn<Args> + 'env,
+    {
+        let boxed: BoxedHostFn<'env> = Rc::new(move |args: &JsArgs| func.call(args));
+        // SAFETY: the closure is removed from the registry when `self` is dropped, which
+        // `with_host_functions` guarantees to happen before 'env ends. The trampoline only
+        // keeps its own clone of the Rc for the duration of the call.
+        let boxed: BoxedHostFn<'static> = unsafe { std::mem::transmute(boxed) };
+        let index = HOST_FUNCTIONS.with(|functions| {
+            let mut functions = functions.borrow_mut();
+            functions.push(Some(RegisteredHostFn {
+                name: name.to_string(),
+                func: boxed,
+            }));
+            functions.len() - 1
+        });
+        self.indices.push(index);
+
+        let name_cstr = CString::new(name).unwrap();
+        unsafe {
+            js_add_host_function_magic(
+                name_cstr.as_ptr() as i32,
+                host_function_trampoline as fn(i32, i64, i32, i32, i32) -> i64 as usize as i32,
+                F::ARITY,
+                index as i32,
+            );
+        }
+        self
+    }
+}
+
+impl Drop for HostFunctions<'_> {
+    fn drop(&mut self) {
+        // dropped outside of the borrow, in case a captured value touches the registry itself
+        let _removed: Vec<_> = HOST_FUNCTIONS.with(|functions| {
+            let mut functions = functions.borrow_mut();
+            self.indices
+                .drain(..)
+                .map(|index| functions[index].take())
+                .collect()
+        });
+    }
+}
+
+/// Runs `f` with a [`HostFunctions`] to register closures borrowing from the environment. The
+/// closures are unregistered again when `f` returns.
+pub fn with_host_functions<'env, R>(f: impl FnOnce(&mut HostFunctions<'env>) -> R) -> R {
+    let mut host = HostFunctions {
+        indices: Vec::new(),
+        _env: PhantomData,
+    };
+    f(&mut host)
+}
+
+/// Compiles `body` as the body of an exported `run` function, and calls it with the host
+/// functions added by `add_functions`.
+#[cfg(test)]
+pub(crate) fn run_with_host_functions(
+    body: &str,
+    add_functions: impl FnOnce(&mut HostFunctions<'_>),
+) -> JsValue {
+    let bytecode = super::compile_js(
+        format!("export function run() {{ {} }}", body),
+        Some("main.js".to_string()),
+    );
+    let jsmod = super::load_js_bytecode(bytecode.as_ptr(), bytecode.len()).unwrap();
+    with_host_functions(|host| {
+        add_functions(host);
+        jsmod.call_function("run").unwrap()
+    })
+}
@@ -0,0 +1,562 @@
+use near_sdk::serde_json;
+use std::ffi::CString;
+use std::fmt;
+
+use super::host::{HostResult, IntoJs};
+use super::{
+    await_promise, get_js_context, js_std_loop_no_os, JsError, JS_GetArrayBuffer, JS_GetTypedArrayBuffer,
+    JS_NewStringLen, JS_ToCStringLen2,
+};
+
+extern "C" {
+    fn js_free_value(ctx: i32, val: i64);
+    fn js_dup_value(ctx: i32, val: i64) -> i64;
+    fn js_new_uint8_array(ctx: i32, buf: *const u8, len: usize) -> i64;
+    fn JS_ParseJSON(ctx: i32, buf: i32, buf_len: usize, filename: i32) -> i64;
+    fn JS_FreeCString(ctx: i32, ptr: i32);
+    fn JS_GetException(ctx: i32) -> i64;
+    fn JS_GetPropertyStr(ctx: i32, this_obj: i64, prop: i32) -> i64;
+    fn JS_SetPropertyStr(ctx: i32, this_obj: i64, prop: i32, val: i64) -> i32;
+    fn JS_NewObject(ctx: i32) -> i64;
+    fn JS_GetGlobalObject(ctx: i32) -> i64;
+    fn JS_NewArray(ctx: i32) -> i64;
+    fn JS_GetPropertyUint32(ctx: i32, this_obj: i64, idx: u32) -> i64;
+    fn JS_SetPropertyUint32(ctx: i32, this_obj: i64, idx: u32, val: i64) -> i32;
+    fn JS_GetOwnPropertyNames(
+        ctx: i32,
+        ptab: *mut i32,
+        plen: *mut u32,
+        obj: i64,
+        flags: i32,
+    ) -> i32;
+    fn JS_FreePropertyEnum(ctx: i32, tab: i32, len: u32);
+    fn JS_AtomToString(ctx: i32, atom: u32) -> i64;
+    fn JS_ToFloat64(ctx: i32, pres: *mut f64, val: i64) -> i32;
+    fn JS_ToInt64(ctx: i32, pres: *mut i64, val: i64) -> i32;
+    fn JS_ToBool(ctx: i32, val: i64) -> i32;
+    fn JS_IsArray(ctx: i32, val: i64) -> i32;
+    fn JS_IsFunction(ctx: i32, val: i64) -> i32;
+    fn JS_JSONStringify(ctx: i32, obj: i64, replacer: i64, space0: i64) -> i64;
+    fn JS_Call(ctx: i32, func_obj: i64, this_obj: i64, argc: i32, argv: *const i64) -> i64;
+}
+
+// Tags of the NaN-boxed JSValue representation QuickJS uses on wasm32, see
+// the JS_TAG_* enum in quickjs.h. The tag is the upper 32 bits of the value.
+const JS_TAG_FIRST: i32 = -9;
+const JS_TAG_BIG_INT: i32 = -9;
+const JS_TAG_STRING: i32 = -7;
+const JS_TAG_STRING_ROPE: i32 = -6;
+const JS_TAG_OBJECT: i32 = -1;
+const JS_TAG_INT: i32 = 0;
+const JS_TAG_BOOL: i32 = 1;
+const JS_TAG_NULL: i32 = 2;
+const JS_TAG_UNDEFINED: i32 = 3;
+const JS_TAG_EXCEPTION: i32 = 6;
+const JS_TAG_SHORT_BIG_INT: i32 = 7;
+const JS_TAG_FLOAT64: i32 = 8;
+
+// A float64 is stored with its bits offset so that NaN-boxed tags stay below
+// it, per quickjs.h __JS_NewFloat64:
+//   JSValue = double_bits - (JS_FLOAT64_TAG_ADDEND << 32)
+//   JS_FLOAT64_TAG_ADDEND = 0x7ff80000 - JS_TAG_FIRST + 1
+//                         = 0x7ff80000 - (-9) + 1 = 0x7ff8000a
+const JS_FLOAT64_TAG_ADDEND_SHIFTED: u64 = 0x7ff8000a_00000000;
+
+// Flags of JS_GetOwnPropertyNames, from quickjs.h
+const JS_GPN_STRING_MASK: i32 = 1 << 0;
+const JS_GPN_ENUM_ONLY: i32 = 1 << 4;
+
+/// The JSPropertyEnum entries listed by JS_GetOwnPropertyNames.
+#[repr(C)]
+struct JsPropertyEnum {
+    _is_enumerable: i32,
+    atom: u32,
+}
+
+const fn mkval(tag: i32, val: i32) -> i64 {
+    ((tag as i64) << 32) | (val as u32 as i64)
+}
+
+/// An owned reference to a value living in the QuickJS runtime.
+///
+/// The reference count is released when the `JsValue` is dropped, so values
+/// returned from JavaScript can be inspected and converted without touching
+/// raw `JSValue` handles.
+pub struct JsValue {
+    ctx: i32,
+    value: i64,
+}
+
+impl JsValue {
+    /// Takes ownership of a raw `JSValue`, e.g. one returned by a QuickJS API call.
+    ///
+    /// # Safety
+    ///
+    /// `value` must be a valid `JSValue` of `ctx` that the caller owns a reference to.
+    pub unsafe fn from_raw(ctx: i32, value: i64) -> Self {
+        Self { ctx, value }
+    }
+
+    /// Creates an owned reference from a borrowed `JSValue`, such as a host function argument.
+    ///
+    /// # Safety
+    ///
+    /// `value` must be a valid `JSValue` of `ctx`.
+    pub unsafe fn from_borrowed(ctx: i32, value: i64) -> Self {
+        Self {
+            ctx,
+            value: js_dup_value(ctx, value),
+        }
+    }
+
+    /// Takes ownership of a raw `JSValue` belonging to the current context.
+    pub(crate) fn from_current(value: i64) -> Self {
+        unsafe { Self::from_raw(get_js_context(), value) }
+    }
+
+    pub fn undefined() -> Self {
+        Self::from_current(mkval(JS_TAG_UNDEFINED, 0))
+    }
+
+    pub fn null() -> Self {
+        Self::from_current(mkval(JS_TAG_NULL, 0))
+    }
+
+    pub fn from_bool(value: bool) -> Self {
+        Self::from_current(mkval(JS_TAG_BOOL, value as i32))
+    }
+
+    pub fn from_i32(value: i32) -> Self {
+        Self::from_current(mkval(JS_TAG_INT, value))
+    }
+
+    pub fn from_f64(value: f64) -> Self {
+        // NaNs are canonicalized, since any other payload would collide with the tags
+        let bits = if value.is_nan() { f64::NAN } else { value }.to_bits();
+        Self::from_current(bits.wrapping_sub(JS_FLOAT64_TAG_ADDEND_SHIFTED) as i64)
+    }
+
+    pub fn from_string(value: &str) -> Self {
+        let ctx = unsafe { get_js_context() };
+        unsafe { Self::from_raw(ctx, JS_NewStringLen(ctx, value.as_ptr() as i32, value.len())) }
+    }
+
+    /// Creates a BigInt. QuickJS has no constructor for integers wider than 64 bits, so the value
+    /// is passed to `BigInt` as a decimal string.
+    pub fn from_u128(value: u128) -> Self {
+        Self::big_int_from_decimal(&value.to_string())
+    }
+
+    /// Creates a BigInt, like [`JsValue::from_u128`].
+    pub fn from_i128(value: i128) -> Self {
+        Self::big_int_from_decimal(&value.to_string())
+    }
+
+    fn big_int_from_decimal(decimal: &str) -> Self {
+        let ctx = unsafe { get_js_context() };
+        let global = unsafe { Self::from_raw(ctx, JS_GetGlobalObject(ctx)) };
+        let big_int = global.get_property("BigInt");
+        let digits = Self::from_string(decimal);
+        unsafe {
+            Self::from_raw(
+                ctx,
+                JS_Call(ctx, big_int.value, super::JS_UNDEFINED, 1, &digits.value as *const i64),
+            )
+        }
+    }
+
+    /// Creates a `Uint8Array` holding a copy of `value`.
+    pub fn from_bytes(value: &[u8]) -> Self {
+        let ctx = unsafe { get_js_context() };
+        unsafe { Self::from_raw(ctx, js_new_uint8_array(ctx, value.as_ptr(), value.len())) }
+    }
+
+    /// Creates an empty object, to be filled with [`JsValue::set_property`].
+    pub fn new_object() -> Self {
+        let ctx = unsafe { get_js_context() };
+        unsafe { Self::from_raw(ctx, JS_NewObject(ctx)) }
+    }
+
+    /// Creates an empty array, to be filled with [`JsValue::set_index`].
+    pub fn new_array() -> Self {
+        let ctx = unsafe { get_js_context() };
+        unsafe { Self::from_raw(ctx, JS_NewArray(ctx)) }
+    }
+
+    /// Creates the JavaScript counterpart of a JSON value through `JSON.parse`.
+    pub fn from_json(value: &serde_json::Value) -> Self {
+        let ctx = unsafe { get_js_context() };
+        let json = CString::new(value.to_string()).unwrap();
+        let filename = CString::new("<json>").unwrap();
+        unsafe {
+            Self::from_raw(
+                ctx,
+                JS_ParseJSON(
+                    ctx,
+                    json.as_ptr() as i32,
+                    json.as_bytes().len(),
+                    filename.as_ptr() as i32,
+                ),
+            )
+        }
+    }
+
+    pub fn as_raw(&self) -> i64 {
+        self.value
+    }
+
+    /// Releases ownership of the underlying `JSValue` without decrementing its reference count.
+    pub fn into_raw(self) -> i64 {
+        let value = self.value;
+        std::mem::forget(self);
+        value
+    }
+
+    pub fn context(&self) -> i32 {
+        self.ctx
+    }
+
+    fn tag(&self) -> i32 {
+        (self.value >> 32) as i32
+    }
+
+    pub fn is_undefined(&self) -> bool {
+        self.tag() == JS_TAG_UNDEFINED
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.tag() == JS_TAG_NULL
+    }
+
+    pub fn is_bool(&self) -> bool {
+        self.tag() == JS_TAG_BOOL
+    }
+
+    pub fn is_number(&self) -> bool {
+        let tag = self.tag();
+        // every tag from JS_TAG_FLOAT64 and up is the upper half of a float64
+        tag == JS_TAG_INT || (tag.wrapping_sub(JS_TAG_FIRST) as u32) >= (JS_TAG_FLOAT64 - JS_TAG_FIRST) as u32
+    }
+
+    pub fn is_bigint(&self) -> bool {
+        let tag = self.tag();
+        tag == JS_TAG_BIG_INT || tag == JS_TAG_SHORT_BIG_INT
+    }
+
+    pub fn is_string(&self) -> bool {
+        let tag = self.tag();
+        tag == JS_TAG_STRING || tag == JS_TAG_STRING_ROPE
+    }
+
+    pub fn is_object(&self) -> bool {
+        self.tag() == JS_TAG_OBJECT
+    }
+
+    pub fn is_exception(&self) -> bool {
+        self.tag() == JS_TAG_EXCEPTION
+    }
+
+    pub fn is_array(&self) -> bool {
+        self.is_object() && unsafe { JS_IsArray(self.ctx, self.value) } == 1
+    }
+
+    pub fn is_function(&self) -> bool {
+        self.is_object() && unsafe { JS_IsFunction(self.ctx, self.value) } != 0
+    }
+
+    /// Converts the value to a number, with the same semantics as `Number(value)` in JavaScript.
+    pub fn to_f64(&self) -> f64 {
+        let mut result: f64 = f64::NAN;
+        unsafe {
+            JS_ToFloat64(self.ctx, &mut result as *mut f64, self.value);
+        }
+        result
+    }
+
+    /// Converts the value to an integer, truncating fractions and saturating out of range numbers.
+    pub fn to_i64(&self) -> i64 {
+        let mut result: i64 = 0;
+        unsafe {
+            JS_ToInt64(self.ctx, &mut result as *mut i64, self.value);
+        }
+        result
+    }
+
+    /// Converts the value to a boolean using JavaScript truthiness.
+    pub fn to_bool(&self) -> bool {
+        unsafe { JS_ToBool(self.ctx, self.value) == 1 }
+    }
+
+    /// Copies the contents of an `ArrayBuffer` or a typed array such as `Uint8Array`.
+    ///
+    /// Returns an empty vector for any other kind of value.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.try_to_bytes().unwrap_or_default()
+    }
+
+    /// Like [`JsValue::to_bytes`], but returns `None` if the value is not binary data.
+    pub fn try_to_bytes(&self) -> Option<Vec<u8>> {
+        if !self.is_object() {
+            return None;
+        }
+        unsafe {
+            let mut size: usize = 0;
+            let data_ptr = JS_GetArrayBuffer(self.ctx, &mut size as *mut usize, self.value);
+            if !data_ptr.is_null() {
+                return Some(std::slice::from_raw_parts(data_ptr, size).to_vec());
+            }
+            // not an ArrayBuffer, so discard the TypeError and try as a typed array
+            clear_exception(self.ctx);
+
+            let mut byte_offset: usize = 0;
+            let mut byte_length: usize = 0;
+            let mut bytes_per_element: usize = 0;
+            let buffer = JS_GetTypedArrayBuffer(
+                self.ctx,
+                self.value,
+                &mut byte_offset as *mut usize,
+                &mut byte_length as *mut usize,
+                &mut bytes_per_element as *mut usize,
+            );
+            let buffer = JsValue::from_raw(self.ctx, buffer);
+            if buffer.is_exception() {
+                clear_exception(self.ctx);
+                return None;
+            }
+            let data_ptr = JS_GetArrayBuffer(self.ctx, &mut size as *mut usize, buffer.value);
+            if data_ptr.is_null() || byte_length == 0 {
+                return Some(Vec::new());
+            }
+            Some(std::slice::from_raw_parts(data_ptr.add(byte_offset), byte_length).to_vec())
+        }
+    }
+
+    /// Converts the value to JSON through `JSON.stringify`.
+    ///
+    /// Values that have no JSON representation, like `undefined` or functions, become `null`.
+    pub fn to_json(&self) -> serde_json::Value {
+        self.to_json_string()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or(serde_json::Value::Null)
+    }
+
+    /// The result of `JSON.stringify`, keeping the order of object keys, or `None` for values
+    /// that have no JSON representation.
+    pub fn to_json_string(&self) -> Option<String> {
+        let json = unsafe {
+            JsValue::from_raw(
+                self.ctx,
+                JS_JSONStringify(self.ctx, self.value, super::JS_UNDEFINED, super::JS_UNDEFINED),
+            )
+        };
+        if !json.is_string() {
+            if json.is_exception() {
+                clear_exception(self.ctx);
+            }
+            return None;
+        }
+        Some(json.to_string())
+    }
+
+    pub fn get_property(&self, name: &str) -> JsValue {
+        let name_cstr = CString::new(name).unwrap();
+        unsafe {
+            JsValue::from_raw(
+                self.ctx,
+                JS_GetPropertyStr(self.ctx, self.value, name_cstr.as_ptr() as i32),
+            )
+        }
+    }
+
+    pub fn set_property(&self, name: &str, value: JsValue) {
+        let name_cstr = CString::new(name).unwrap();
+        unsafe {
+            // JS_SetPropertyStr takes ownership of the value
+            let name_ptr = name_cstr.as_ptr() as i32;
+            if JS_SetPropertyStr(self.ctx, self.value, name_ptr, value.into_raw()) < 0 {
+                clear_exception(self.ctx);
+            }
+        }
+    }
+
+    pub fn get_index(&self, index: u32) -> JsValue {
+        unsafe { JsValue::from_raw(self.ctx, JS_GetPropertyUint32(self.ctx, self.value, index)) }
+    }
+
+    pub fn set_index(&self, index: u32, value: JsValue) {
+        unsafe {
+            // JS_SetPropertyUint32 takes ownership of the value
+            if JS_SetPropertyUint32(self.ctx, self.value, index, value.into_raw()) < 0 {
+                clear_exception(self.ctx);
+            }
+        }
+    }
+
+    /// The names of the own enumerable properties, in the order `Object.keys` lists them.
+    pub fn keys(&self) -> Vec<String> {
+        if !self.is_object() {
+            return Vec::new();
+        }
+        let mut tab: i32 = 0;
+        let mut len: u32 = 0;
+        let flags = JS_GPN_STRING_MASK | JS_GPN_ENUM_ONLY;
+        unsafe {
+            if JS_GetOwnPropertyNames(self.ctx, &mut tab, &mut len, self.value, flags) < 0 {
+                clear_exception(self.ctx);
+                return Vec::new();
+            }
+            let keys = if len == 0 {
+                Vec::new()
+            } else {
+                std::slice::from_raw_parts(tab as *const JsPropertyEnum, len as usize)
+                    .iter()
+                    .map(|entry| JS_AtomToString(self.ctx, entry.atom))
+                    .map(|name| JsValue::from_raw(self.ctx, name).to_string())
+                    .collect()
+            };
+            JS_FreePropertyEnum(self.ctx, tab, len);
+            keys
+        }
+    }
+
+    /// The `length` property, for arrays, typed arrays and strings.
+    pub fn len(&self) -> usize {
+        self.get_property("length").to_i64().max(0) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Calls the function exported as `function_name` from this module object, and runs the
+    /// jobs it leaves pending. If the function is `async`, the value its Promise resolves to is
+    /// returned, and a rejection is returned as a [`JsError`].
+    ///
+    /// Exceptions thrown by host functions can be caught by the script, but an exception escaping
+    /// the called function is returned as a [`JsError`].
+    pub fn call_function(&self, function_name: &str) -> Result<JsValue, JsError> {
+        self.call_function_with_args(function_name, ())
+    }
+
+    /// Like [`JsValue::call_function`], passing `args` to the function. These are either a tuple
+    /// of Rust values, converted with [`IntoJs`], or a slice of `JsValue`s:
+    ///
+    /// ```ignore
+    /// jsmod.call_function_with_args("nft_payout", (token_id, balance.0, max_len_payout))
+    /// ```
+    pub fn call_function_with_args(
+        &self,
+        function_name: &str,
+        args: impl IntoJsArgs,
+    ) -> Result<JsValue, JsError> {
+        let args = args
+            .into_js_args()
+            .map_err(|err| JsError::new("TypeError", format!("{}: {}", function_name, err.message)))?;
+        let argv: Vec<i64> = args.iter().map(JsValue::as_raw).collect();
+        let function = self.get_property(function_name);
+        let result = unsafe {
+            JsValue::from_raw(
+                self.ctx,
+                JS_Call(
+                    self.ctx,
+                    function.value,
+                    self.value,
+                    argv.len() as i32,
+                    argv.as_ptr(),
+                ),
+            )
+        };
+        if result.is_exception() {
+            return Err(JsError::from_exception(self.ctx));
+        }
+        unsafe { js_std_loop_no_os(self.ctx) };
+        await_promise(result)
+    }
+}
+
+impl Clone for JsValue {
+    fn clone(&self) -> Self {
+        unsafe { JsValue::from_borrowed(self.ctx, self.value) }
+    }
+}
+
+impl Drop for JsValue {
+    fn drop(&mut self) {
+        // js_free_value checks the tag, decoding NaN-boxed floats, and only frees reference
+        // counted values. Those all have negative tags, so this merely skips the call for ints,
+        // booleans and the like; a negative upper word may still be a float.
+        if self.tag() < 0 {
+            unsafe { js_free_value(self.ctx, self.value) };
+        }
+    }
+}
+
+/// Converts the value to a string, with the same semantics as `String(value)` in JavaScript.
+impl fmt::Display for JsValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut len: usize = 0;
+        unsafe {
+            let ptr = JS_ToCStringLen2(self.ctx, &mut len as *mut usize as i32, self.value, 0);
+            if ptr == 0 {
+                // e.g. a Symbol, which throws on string conversion
+                clear_exception(self.ctx);
+                return Ok(());
+            }
+            let bytes = std::slice::from_raw_parts(ptr as *const u8, len);
+            let result = f.write_str(&String::from_utf8_lossy(bytes));
+            JS_FreeCString(self.ctx, ptr);
+            result
+        }
+    }
+}
+
+impl fmt::Debug for JsValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "JsValue({:#018x})", self.value)
+    }
+}
+
+/// Takes the pending exception of `ctx`, leaving none pending.
+pub(crate) fn take_exception(ctx: i32) -> JsValue {
+    unsafe { JsValue::from_raw(ctx, JS_GetException(ctx)) }
+}
+
+/// Arguments for [`JsValue::call_function_with_args`].
+pub trait IntoJsArgs {
+    fn into_js_args(self) -> HostResult<Vec<JsValue>>;
+}
+
+impl IntoJsArgs for &[JsValue] {
+    fn into_js_args(self) -> HostResult<Vec<JsValue>> {
+        Ok(self.to_vec())
+    }
+}
+
+impl IntoJsArgs for Vec<JsValue> {
+    fn into_js_args(self) -> HostResult<Vec<JsValue>> {
+        Ok(self)
+    }
+}
+
+macro_rules! impl_into_js_args {
+    ($($arg:ident: $t:ident),*) => {
+        impl<$($t: IntoJs),*> IntoJsArgs for ($($t,)*) {
+            fn into_js_args(self) -> HostResult<Vec<JsValue>> {
+                let ($($arg,)*) = self;
+                Ok(vec![$($arg.into_js()?),*])
+            }
+        }
+    };
+}
+
+impl_into_js_args!();
+impl_into_js_args!(a: T1);
+impl_into_js_args!(a: T1, b: T2);
+impl_into_js_args!(a: T1, b: T2, c: T3);
+impl_into_js_args!(a: T1, b: T2, c: T3, d: T4);
+impl_into_js_args!(a: T1, b: T2, c: T3, d: T4, e: T5);
+impl_into_js_args!(a: T1, b: T2, c: T3, d: T4, e: T5, f: T6);
+
+pub(crate) fn clear_exception(ctx: i32) {
+    drop(take_exception(ctx));
+}
@@ -0,0 +1,219 @@
+//! Bundles of named modules, and the module loader that resolves `import` statements from them.
+//!
+//! Each module of a [`ModuleBundle`] is compiled separately, under the name other modules import
+//! it by. QuickJS normalizes relative specifiers against the importing module, so `./math.js`
+//! imported from `lib/index.js` is looked up as `lib/math.js`:
+//!
+//! ```ignore
+//! let bundle = ModuleBundle::compile(
+//!     "main.js",
+//!     [
+//!         ("main.js", "import { add } from './lib/math.js'; export const sum = () => add(1, 2);"),
+//!         ("lib/math.js", "export function add(a, b) { return a + b; }"),
+//!     ],
+//! );
+//! env::storage_write(b"JS", &bundle.to_bytes());
+//! let jsmod = load_js_bundle(&bundle)?;
+//! ```
+//!
+//! Modules missing from the bundle can be read from contract storage instead, with
+//! [`ModuleBundle::with_storage_fallback`].
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::env;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ffi::{c_char, CStr};
+
+use super::{compile_js, HostError};
+
+extern "C" {
+    fn JS_GetRuntime(ctx: i32) -> i32;
+    fn JS_SetModuleLoaderFunc(rt: i32, module_normalize: i32, module_loader: i32, opaque: i32);
+    fn js_read_module_bytecode(ctx: i32, buf: *const u8, buf_len: usize) -> i32;
+}
+
+/// Compiled modules by name, with the module to load first.
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq, Eq)]
+#[borsh(crate = "near_sdk::borsh")]
+pub struct ModuleBundle {
+    entry_module: String,
+    modules: BTreeMap<String, Vec<u8>>,
+    storage_prefix: Option<Vec<u8>>,
+}
+
+impl ModuleBundle {
+    pub fn new(entry_module: impl Into<String>) -> Self {
+        ModuleBundle {
+            entry_module: entry_module.into(),
+            modules: BTreeMap::new(),
+            storage_prefix: None,
+        }
+    }
+
+    /// Compiles each of `sources`, given as pairs of module name and source code.
+    pub fn compile<N, S>(
+        entry_module: impl Into<String>,
+        sources: impl IntoIterator<Item = (N, S)>,
+    ) -> Self
+    where
+        N: Into<String>,
+        S: Into<String>,
+    {
+        let mut bundle = ModuleBundle::new(entry_module);
+        for (name, source) in sources {
+            let name = name.into();
+            let bytecode = compile_js(source.into(), Some(name.clone()));
+            bundle.insert(name, bytecode);
+        }
+        bundle
+    }
+
+    /// Resolves modules missing from the bundle from the storage key of their name appended to
+    /// `prefix`, where they are stored as module bytecode.
+    pub fn with_storage_fallback(mut self, prefix: impl Into<Vec<u8>>) -> Self {
+        self.storage_prefix = Some(prefix.into());
+        self
+    }
+
+    pub fn insert(&mut self, name: impl Into<String>, bytecode: Vec<u8>) {
+        self.modules.insert(name.into(), bytecode);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.modules.get(name).map(Vec::as_slice)
+    }
+
+    pub fn entry_module(&self) -> &str {
+        &self.entry_module
+    }
+
+    /// The entry module, which [`load_js_bundle`](super::load_js_bundle) starts from.
+    pub fn entry_bytecode(&self) -> Option<Vec<u8>> {
+        self.resolve(&self.entry_module)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        borsh::to_vec(self).unwrap()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        ModuleBundle::try_from_slice(bytes).ok()
+    }
+
+    fn resolve(&self, name: &str) -> Option<Vec<u8>> {
+        if let Some(bytecode) = self.modules.get(name) {
+            return Some(bytecode.clone());
+        }
+        let prefix = self.storage_prefix.as_ref()?;
+        env::storage_read(&[prefix.as_slice(), name.as_bytes()].concat())
+    }
+}
+
+thread_local! {
+    static LOADER_BUNDLE: RefCell<Option<ModuleBundle>> = const { RefCell::new(None) };
+}
+
+fn module_loader(ctx: i32, module_name: i32, _opaque: i32) -> i32 {
+    let name = unsafe { CStr::from_ptr(module_name as *const c_char) }
+        .to_string_lossy()
+        .into_owned();
+    let bytecode = LOADER_BUNDLE.with(|bundle| bundle.borrow().as_ref()?.resolve(&name));
+    match bytecode {
+        Some(bytecode) => unsafe {
+            js_read_module_bytecode(ctx, bytecode.as_ptr(), bytecode.len())
+        },
+        None => {
+            HostError::from(format!("could not load module '{}'", name)).throw(ctx);
+            0
+        }
+    }
+}
+
+/// Resolves the imports of modules loaded in the runtime of `ctx` from `bundle`, including
+/// dynamic imports made after loading.
+pub(crate) fn install_module_loader(ctx: i32, bundle: ModuleBundle) {
+    LOADER_BUNDLE.with(|current| *current.borrow_mut() = Some(bundle));
+    unsafe {
+        JS_SetModuleLoaderFunc(
+            JS_GetRuntime(ctx),
+            0,
+            module_loader as fn(i32, i32, i32) -> i32 as usize as i32,
+            0,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ModuleBundle;
+    use crate::jslib::{compile_js, load_js_bundle};
+    use near_sdk::env;
+    use quickjs_rust_near_testenv::testenv::setup_test_env;
+
+    #[test]
+    fn test_load_js_bundle_resolves_imports() {
+        setup_test_env();
+        let bundle = ModuleBundle::compile(
+            "main.js",
+            [
+                (
+                    "main.js",
+                    "import { add } from './lib/math.js';
+                    export function sum() { return add(20, 22); }",
+                ),
+                (
+                    "lib/math.js",
+                    "import { identity } from './identity.js';
+                    export function add(a, b) { return identity(a) + b; }",
+                ),
+                ("lib/identity.js", "export const identity = (a) => a;"),
+            ],
+        );
+        let bundle = ModuleBundle::from_bytes(&bundle.to_bytes()).unwrap();
+
+        let jsmod = load_js_bundle(&bundle).unwrap();
+        assert_eq!(42, jsmod.call_function("sum").unwrap().to_i64());
+    }
+
+    #[test]
+    fn test_load_js_bundle_from_storage() {
+        setup_test_env();
+        env::storage_write(
+            b"modules/greeting.js",
+            &compile_js(
+                "export const greeting = 'hello';".to_string(),
+                Some("greeting.js".to_string()),
+            ),
+        );
+        let bundle = ModuleBundle::compile(
+            "main.js",
+            [(
+                "main.js",
+                "export async function greet() {
+                    const { greeting } = await import('./greeting.js');
+                    return greeting;
+                }",
+            )],
+        )
+        .with_storage_fallback(b"modules/".to_vec());
+
+        let jsmod = load_js_bundle(&bundle).unwrap();
+        assert_eq!("hello", jsmod.call_function("greet").unwrap().to_string());
+    }
+
+    #[test]
+    fn test_load_js_bundle_missing_module() {
+        setup_test_env();
+        let bundle = ModuleBundle::compile(
+            "main.js",
+            [(
+                "main.js",
+                "import { missing } from './missing.js'; export { missing };",
+            )],
+        );
+
+        let err = load_js_bundle(&bundle).unwrap_err();
+        assert_eq!("could not load module 'missing.js'", err.message);
+    }
+}
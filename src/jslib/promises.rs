@@ -0,0 +1,369 @@
+//! Cross-contract calls and other outgoing actions for scripts.
+//!
+//! The functions mirror the promise API of the NEAR runtime, with promises identified by the
+//! index returned when creating them:
+//!
+//! ```js
+//! const promise = env.promise_create('wrap.near', 'ft_balance_of',
+//!     JSON.stringify({ account_id: env.current_account_id() }), 0, 10_000_000_000_000);
+//! env.promise_return(env.promise_then(promise, env.current_account_id(), 'js_callback',
+//!     JSON.stringify({ function_name: 'on_balance' }), 0, 10_000_000_000_000));
+//! ```
+//!
+//! In the callback, `env.promise_result(index)` returns `{ status: 'successful', data }`, with
+//! the returned data as a `Uint8Array`, or `{ status: 'failed' }`. A contract method calling
+//! [`js_callback`] routes the callback to the function named in its arguments, which receives
+//! the same results as its arguments:
+//!
+//! ```ignore
+//! pub fn js_callback(&mut self, function_name: String) {
+//!     let jsmod = self.load_js_bytecode();
+//!     with_host_functions(|host| {
+//!         add_promise_functions(host);
+//!         js_callback(&jsmod, &function_name).unwrap_or_else(|err| env::panic_str(&err.to_string()));
+//!     });
+//! }
+//! ```
+//!
+//! Amounts are in yoctoNEAR, and like gas may be given as decimal strings. Function arguments and
+//! contract code may be strings or binary data.
+//!
+//! Scripts that may spend the balance of the contract should only be given these functions in
+//! methods that are meant to.
+
+use near_sdk::{env, sys, AccountId, PromiseResult};
+
+use super::host::{Bytes, HostFunctions, JsArgs};
+use super::{HostResult, JsError, JsValue};
+
+fn promise_create(
+    account_id: AccountId,
+    function_name: String,
+    arguments: Bytes,
+    amount: u128,
+    gas: u64,
+) -> HostResult<u64> {
+    unsafe {
+        Ok(sys::promise_create(
+            account_id.as_str().len() as _,
+            account_id.as_str().as_ptr() as _,
+            function_name.len() as _,
+            function_name.as_ptr() as _,
+            arguments.0.len() as _,
+            arguments.0.as_ptr() as _,
+            &amount as *const u128 as _,
+            gas,
+        ))
+    }
+}
+
+fn promise_then(
+    promise_index: u64,
+    account_id: AccountId,
+    function_name: String,
+    arguments: Bytes,
+    amount: u128,
+    gas: u64,
+) -> HostResult<u64> {
+    unsafe {
+        Ok(sys::promise_then(
+            promise_index,
+            account_id.as_str().len() as _,
+            account_id.as_str().as_ptr() as _,
+            function_name.len() as _,
+            function_name.as_ptr() as _,
+            arguments.0.len() as _,
+            arguments.0.as_ptr() as _,
+            &amount as *const u128 as _,
+            gas,
+        ))
+    }
+}
+
+fn promise_and(args: &JsArgs) -> HostResult<u64> {
+    let promise_indices = (0..args.len())
+        .map(|index| args.decode::<u64>(index))
+        .collect::<HostResult<Vec<u64>>>()?;
+    unsafe {
+        Ok(sys::promise_and(
+            promise_indices.as_ptr() as _,
+            promise_indices.len() as _,
+        ))
+    }
+}
+
+fn promise_batch_create(account_id: AccountId) -> HostResult<u64> {
+    unsafe {
+        Ok(sys::promise_batch_create(
+            account_id.as_str().len() as _,
+            account_id.as_str().as_ptr() as _,
+        ))
+    }
+}
+
+fn promise_batch_then(promise_index: u64, account_id: AccountId) -> HostResult<u64> {
+    unsafe {
+        Ok(sys::promise_batch_then(
+            promise_index,
+            account_id.as_str().len() as _,
+            account_id.as_str().as_ptr() as _,
+        ))
+    }
+}
+
+fn promise_batch_action_function_call(
+    promise_index: u64,
+    function_name: String,
+    arguments: Bytes,
+    amount: u128,
+    gas: u64,
+) -> HostResult<()> {
+    unsafe {
+        sys::promise_batch_action_function_call(
+            promise_index,
+            function_name.len() as _,
+            function_name.as_ptr() as _,
+            arguments.0.len() as _,
+            arguments.0.as_ptr() as _,
+            &amount as *const u128 as _,
+            gas,
+        );
+    }
+    Ok(())
+}
+
+fn promise_batch_action_transfer(promise_index: u64, amount: u128) -> HostResult<()> {
+    unsafe {
+        sys::promise_batch_action_transfer(promise_index, &amount as *const u128 as _);
+    }
+    Ok(())
+}
+
+fn promise_batch_action_deploy_contract(promise_index: u64, code: Bytes) -> HostResult<()> {
+    unsafe {
+        sys::promise_batch_action_deploy_contract(
+            promise_index,
+            code.0.len() as _,
+            code.0.as_ptr() as _,
+        );
+    }
+    Ok(())
+}
+
+fn promise_return(promise_index: u64) -> HostResult<()> {
+    unsafe {
+        sys::promise_return(promise_index);
+    }
+    Ok(())
+}
+
+fn promise_results_count() -> HostResult<u64> {
+    Ok(env::promise_results_count())
+}
+
+fn promise_result(result_index: u64) -> HostResult<JsValue> {
+    let count = env::promise_results_count();
+    if result_index >= count {
+        return Err(format!(
+            "promise result {} requested, but there are {} results",
+            result_index, count
+        )
+        .into());
+    }
+    Ok(promise_result_value(result_index))
+}
+
+fn promise_result_value(result_index: u64) -> JsValue {
+    let result = JsValue::new_object();
+    match env::promise_result(result_index) {
+        PromiseResult::Successful(data) => {
+            result.set_property("status", JsValue::from_string("successful"));
+            result.set_property("data", JsValue::from_bytes(&data));
+        }
+        PromiseResult::Failed => {
+            result.set_property("status", JsValue::from_string("failed"));
+        }
+    }
+    result
+}
+
+/// Calls `function_name` exported from `jsmod` with the results of the promises the current call
+/// is a callback of, one argument per promise. Fails unless the contract called itself, as a
+/// method with `#[private]` would.
+pub fn js_callback(jsmod: &JsValue, function_name: &str) -> Result<JsValue, JsError> {
+    if env::predecessor_account_id() != env::current_account_id() {
+        return Err(JsError::new(
+            "Error",
+            format!(
+                "{} can only be called back by the contract itself",
+                function_name
+            ),
+        ));
+    }
+    let results: Vec<JsValue> = (0..env::promise_results_count())
+        .map(promise_result_value)
+        .collect();
+    jsmod.call_function_with_args(function_name, results)
+}
+
+/// Adds the functions reading the results of the promises the current call is a callback of.
+pub fn add_promise_result_functions(host: &mut HostFunctions<'_>) {
+    host.add("promise_results_count", promise_results_count);
+    host.add("promise_result", promise_result);
+}
+
+/// Adds the promise functions to `host`, including those of [`add_promise_result_functions`].
+pub fn add_promise_functions(host: &mut HostFunctions<'_>) {
+    host.add("promise_create", promise_create);
+    host.add("promise_then", promise_then);
+    host.add("promise_and", promise_and);
+    host.add("promise_batch_create", promise_batch_create);
+    host.add("promise_batch_then", promise_batch_then);
+    host.add(
+        "promise_batch_action_function_call",
+        promise_batch_action_function_call,
+    );
+    host.add(
+        "promise_batch_action_transfer",
+        promise_batch_action_transfer,
+    );
+    host.add(
+        "promise_batch_action_deploy_contract",
+        promise_batch_action_deploy_contract,
+    );
+    host.add("promise_return", promise_return);
+    add_promise_result_functions(host);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{add_promise_functions, js_callback};
+    use crate::jslib::host::run_with_host_functions;
+    use crate::jslib::{compile_js, load_js_bytecode, with_host_functions};
+    use near_sdk::{Gas, NearToken};
+    use quickjs_rust_near_testenv::testenv::{
+        alice, bob, promises, returned_promise, set_current_account_id, set_predecessor_account_id,
+        set_promise_results, setup_test_env, PromiseAction, TestPromise,
+    };
+
+    #[test]
+    fn test_promise_functions() {
+        setup_test_env();
+
+        run_with_host_functions(
+            "
+            const call = env.promise_create('bob.near', 'ft_balance_of',
+                JSON.stringify({ account_id: 'alice.near' }), 0, 10_000_000_000_000);
+            const transfer = env.promise_batch_create('bob.near');
+            env.promise_batch_action_transfer(transfer, '1000000000000000000000000');
+            const both = env.promise_and(call, transfer);
+            const callback = env.promise_then(both, 'alice.near', 'js_callback',
+                new Uint8Array([1, 2]), 0, '20000000000000');
+            env.promise_return(callback);
+            ",
+            add_promise_functions,
+        );
+
+        assert_eq!(
+            vec![
+                TestPromise {
+                    receiver_id: Some(bob()),
+                    depends_on: vec![],
+                    actions: vec![PromiseAction::FunctionCall {
+                        function_name: "ft_balance_of".to_string(),
+                        arguments: b"{\"account_id\":\"alice.near\"}".to_vec(),
+                        amount: NearToken::from_yoctonear(0),
+                        gas: Gas::from_tgas(10),
+                    }],
+                },
+                TestPromise {
+                    receiver_id: Some(bob()),
+                    depends_on: vec![],
+                    actions: vec![PromiseAction::Transfer {
+                        amount: NearToken::from_near(1),
+                    }],
+                },
+                TestPromise {
+                    receiver_id: None,
+                    depends_on: vec![0, 1],
+                    actions: vec![],
+                },
+                TestPromise {
+                    receiver_id: Some(alice()),
+                    depends_on: vec![2],
+                    actions: vec![PromiseAction::FunctionCall {
+                        function_name: "js_callback".to_string(),
+                        arguments: vec![1, 2],
+                        amount: NearToken::from_yoctonear(0),
+                        gas: Gas::from_tgas(20),
+                    }],
+                },
+            ],
+            promises()
+        );
+        assert_eq!(Some(3), returned_promise());
+    }
+
+    #[test]
+    fn test_promise_results() {
+        setup_test_env();
+        set_promise_results(vec![Some(b"\"42\"".to_vec()), None]);
+
+        let result = run_with_host_functions(
+            "
+            const results = [];
+            for (let i = 0; i < env.promise_results_count(); i++) {
+                const { status, data } = env.promise_result(i);
+                results.push(status === 'successful' ? JSON.parse(String.fromCharCode(...data)) : status);
+            }
+            try {
+                env.promise_result(2);
+            } catch (e) {
+                results.push(e.message);
+            }
+            return JSON.stringify(results);
+            ",
+            add_promise_functions,
+        )
+        .to_string();
+
+        assert_eq!(
+            "[\"42\",\"failed\",\"promise_result: promise result 2 requested, but there are 2 results\"]",
+            result
+        );
+    }
+
+    #[test]
+    fn test_js_callback() {
+        setup_test_env();
+        set_current_account_id(alice());
+        set_promise_results(vec![Some(b"1000".to_vec()), None]);
+        let bytecode = compile_js(
+            "export function on_balances(balance, failed) {
+                return JSON.stringify([
+                    env.promise_results_count(),
+                    String.fromCharCode(...balance.data),
+                    failed.status,
+                ]);
+            }"
+            .to_string(),
+            Some("main.js".to_string()),
+        );
+        let jsmod = load_js_bytecode(bytecode.as_ptr(), bytecode.len()).unwrap();
+
+        let (rejected, result) = with_host_functions(|host| {
+            add_promise_functions(host);
+            set_predecessor_account_id(bob());
+            let rejected = js_callback(&jsmod, "on_balances").unwrap_err();
+            set_predecessor_account_id(alice());
+            let result = js_callback(&jsmod, "on_balances").unwrap().to_string();
+            (rejected, result)
+        });
+
+        assert_eq!(
+            "on_balances can only be called back by the contract itself",
+            rejected.message
+        );
+        assert_eq!("[2,\"1000\",\"failed\"]", result);
+    }
+}
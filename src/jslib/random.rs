@@ -0,0 +1,202 @@
+//! Randomness for scripts, derived from `env::random_seed`.
+//!
+//! `Math.random()` and the Web Crypto style `crypto.getRandomValues(array)` draw from a
+//! generator seeded with the random seed of the block. The values are the same for every
+//! runtime created in the same block, and known to the validators producing it, so they are fit
+//! for games and sampling but not for secrets.
+
+use near_sdk::env;
+use std::cell::Cell;
+use std::ffi::CString;
+
+use super::{HostError, JS_GetArrayBuffer, JS_GetTypedArrayBuffer, JsValue};
+
+extern "C" {
+    fn js_add_global_method_magic(object_name: i32, name: i32, func: i32, length: i32, magic: i32);
+}
+
+/// The most bytes `crypto.getRandomValues` fills in one call, as in Web Crypto.
+const MAX_RANDOM_VALUES_LENGTH: usize = 65536;
+
+thread_local! {
+    // xoshiro256** state, seeded on first use
+    static STATE: Cell<Option<[u64; 4]>> = const { Cell::new(None) };
+}
+
+fn seed() -> [u64; 4] {
+    // hashed, since xoshiro can not start from all zeros
+    let hash = env::sha256(&env::random_seed());
+    let mut state = [0; 4];
+    for (word, chunk) in state.iter_mut().zip(hash.chunks_exact(8)) {
+        *word = u64::from_le_bytes(chunk.try_into().unwrap());
+    }
+    state
+}
+
+fn next_u64() -> u64 {
+    STATE.with(|state| {
+        let mut s = state.get().unwrap_or_else(seed);
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        state.set(Some(s));
+        result
+    })
+}
+
+/// Fills `buf` with random bytes.
+pub fn fill_random(buf: &mut [u8]) {
+    for chunk in buf.chunks_mut(8) {
+        chunk.copy_from_slice(&next_u64().to_le_bytes()[..chunk.len()]);
+    }
+}
+
+fn math_random_func(_ctx: i32, _this_val: i64, _argc: i32, _argv: i32, _magic: i32) -> i64 {
+    // the upper 53 bits, as the uniformly distributed mantissa of a number in [0, 1)
+    JsValue::from_f64((next_u64() >> 11) as f64 / (1u64 << 53) as f64).into_raw()
+}
+
+fn get_random_values_func(ctx: i32, _this_val: i64, argc: i32, argv: i32, _magic: i32) -> i64 {
+    let array = if argc > 0 {
+        unsafe { JsValue::from_borrowed(ctx, *(argv as *const i64)) }
+    } else {
+        JsValue::undefined()
+    };
+    let is_float_array = array.is_object()
+        && array
+            .get_property("constructor")
+            .get_property("name")
+            .to_string()
+            .starts_with("Float");
+    if is_float_array {
+        return HostError::type_error("getRandomValues: expected an integer typed array")
+            .throw(ctx);
+    }
+
+    unsafe {
+        let mut byte_offset: usize = 0;
+        let mut byte_length: usize = 0;
+        let mut bytes_per_element: usize = 0;
+        let buffer = JsValue::from_raw(
+            ctx,
+            JS_GetTypedArrayBuffer(
+                ctx,
+                array.as_raw(),
+                &mut byte_offset as *mut usize,
+                &mut byte_length as *mut usize,
+                &mut bytes_per_element as *mut usize,
+            ),
+        );
+        if buffer.is_exception() {
+            super::jsvalue::clear_exception(ctx);
+            return HostError::type_error("getRandomValues: expected an integer typed array")
+                .throw(ctx);
+        }
+        if byte_length > MAX_RANDOM_VALUES_LENGTH {
+            return HostError::range_error(format!(
+                "getRandomValues: can not fill more than {} bytes",
+                MAX_RANDOM_VALUES_LENGTH
+            ))
+            .throw(ctx);
+        }
+        let mut size: usize = 0;
+        let data_ptr = JS_GetArrayBuffer(ctx, &mut size as *mut usize, buffer.as_raw());
+        if !data_ptr.is_null() && byte_length > 0 {
+            fill_random(std::slice::from_raw_parts_mut(
+                data_ptr.add(byte_offset),
+                byte_length,
+            ));
+        }
+    }
+    array.into_raw()
+}
+
+fn add_global_method(
+    object_name: &str,
+    name: &str,
+    func: fn(i32, i64, i32, i32, i32) -> i64,
+    length: i32,
+) {
+    let object_name = CString::new(object_name).unwrap();
+    let name = CString::new(name).unwrap();
+    unsafe {
+        js_add_global_method_magic(
+            object_name.as_ptr() as i32,
+            name.as_ptr() as i32,
+            func as usize as i32,
+            length,
+            0,
+        );
+    }
+}
+
+/// Replaces `Math.random` and adds the `crypto` object to the global object of the runtime being
+/// set up, restarting the generator from the seed.
+pub(crate) fn install_random() {
+    STATE.with(|state| state.set(None));
+    add_global_method("Math", "random", math_random_func, 0);
+    add_global_method("crypto", "getRandomValues", get_random_values_func, 1);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::jslib::run_js;
+    use quickjs_rust_near_testenv::testenv::{set_random_seed, setup_test_env};
+
+    const SCRIPT: &str = "
+        const numbers = [Math.random(), Math.random(), Math.random()];
+        const bytes = crypto.getRandomValues(new Uint8Array(12));
+        const words = crypto.getRandomValues(new Uint32Array(3));
+        JSON.stringify([numbers, Array.from(bytes), Array.from(words)]);
+    ";
+
+    fn run_with_seed(random_seed: [u8; 32]) -> Vec<Vec<f64>> {
+        setup_test_env();
+        set_random_seed(random_seed);
+        let result = run_js(SCRIPT.to_string()).unwrap().to_string();
+        near_sdk::serde_json::from_str(&result).unwrap()
+    }
+
+    #[test]
+    fn test_random_is_deterministic_per_seed() {
+        let values = run_with_seed([1; 32]);
+
+        assert_eq!(values, run_with_seed([1; 32]));
+        assert_ne!(values, run_with_seed([2; 32]));
+
+        let [numbers, bytes, words] = &values[..] else {
+            panic!("expected numbers, bytes and words");
+        };
+        assert!(numbers.iter().all(|n| (0.0..1.0).contains(n)));
+        assert_ne!(numbers[0], numbers[1]);
+        assert!(bytes.iter().any(|byte| *byte != 0.0));
+        assert!(words.iter().any(|word| *word > 255.0));
+    }
+
+    #[test]
+    fn test_get_random_values_rejects_invalid_arrays() {
+        setup_test_env();
+        let result = run_js(
+            "
+            const errors = [];
+            for (const array of [new Float64Array(1), [1, 2], new Uint8Array(65537)]) {
+                try {
+                    crypto.getRandomValues(array);
+                } catch (e) {
+                    errors.push(e.name);
+                }
+            }
+            errors.join(',');
+            "
+            .to_string(),
+        )
+        .unwrap();
+
+        assert_eq!("TypeError,TypeError,RangeError", result.to_string());
+    }
+}
@@ -0,0 +1,96 @@
+//! Limits of the QuickJS runtime that scripts are run in, and how much of their console output
+//! is logged.
+//!
+//! QuickJS allocates from the contract's own linear memory, and without limits a script can
+//! grow its heap or recurse until the contract traps. A [`RuntimeConfig`] bounds both, so that
+//! the script fails with a catchable exception instead:
+//!
+//! ```ignore
+//! let config = RuntimeConfig::default()
+//!     .with_memory_limit(8 * 1024 * 1024)
+//!     .with_max_stack_size(256 * 1024);
+//! let result = run_js_bytecode_with_config(bytecode, &config)?;
+//! ```
+
+use super::budget::{install_interrupt_handler, ExecutionBudget};
+use super::console::{set_log_level, LogLevel, DEFAULT_LOG_LEVEL};
+
+extern "C" {
+    fn JS_GetRuntime(ctx: i32) -> i32;
+    fn JS_SetMemoryLimit(rt: i32, limit: usize);
+    fn JS_SetMaxStackSize(rt: i32, stack_size: usize);
+    fn JS_SetGCThreshold(rt: i32, gc_threshold: usize);
+}
+
+/// The limits applied to the runtime created for each script. Limits left as `None` keep the
+/// QuickJS defaults.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RuntimeConfig {
+    /// Bytes the runtime may allocate, including what QuickJS itself allocates on creation.
+    pub memory_limit: Option<usize>,
+    /// Bytes of native stack that nested calls may use before throwing a stack overflow.
+    pub max_stack_size: Option<usize>,
+    /// Bytes allocated since the last collection at which the garbage collector runs.
+    pub gc_threshold: Option<usize>,
+    /// The gas the script may use, or `None` to let it run until the prepaid gas is exhausted.
+    pub budget: Option<ExecutionBudget>,
+    /// The level below which console output of the script is dropped.
+    pub log_level: LogLevel,
+}
+
+impl RuntimeConfig {
+    pub fn with_memory_limit(mut self, memory_limit: usize) -> Self {
+        self.memory_limit = Some(memory_limit);
+        self
+    }
+
+    pub fn with_max_stack_size(mut self, max_stack_size: usize) -> Self {
+        self.max_stack_size = Some(max_stack_size);
+        self
+    }
+
+    pub fn with_gc_threshold(mut self, gc_threshold: usize) -> Self {
+        self.gc_threshold = Some(gc_threshold);
+        self
+    }
+
+    pub fn with_budget(mut self, budget: Option<ExecutionBudget>) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    pub fn with_log_level(mut self, log_level: LogLevel) -> Self {
+        self.log_level = log_level;
+        self
+    }
+
+    /// Applies the limits to the runtime of `ctx`.
+    pub(crate) fn apply(&self, ctx: i32) {
+        unsafe {
+            let rt = JS_GetRuntime(ctx);
+            if let Some(memory_limit) = self.memory_limit {
+                JS_SetMemoryLimit(rt, memory_limit);
+            }
+            if let Some(max_stack_size) = self.max_stack_size {
+                JS_SetMaxStackSize(rt, max_stack_size);
+            }
+            if let Some(gc_threshold) = self.gc_threshold {
+                JS_SetGCThreshold(rt, gc_threshold);
+            }
+        }
+        install_interrupt_handler(ctx, self.budget);
+        set_log_level(self.log_level);
+    }
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        RuntimeConfig {
+            memory_limit: None,
+            max_stack_size: None,
+            gc_threshold: None,
+            budget: Some(ExecutionBudget::default()),
+            log_level: DEFAULT_LOG_LEVEL,
+        }
+    }
+}
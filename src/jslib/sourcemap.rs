@@ -0,0 +1,239 @@
+//! [Source maps](https://sourcemaps.info/spec.html), for reporting errors at their location in
+//! the original sources.
+//!
+//! Scripts are often bundled or compiled from TypeScript before they are compiled to bytecode, so
+//! the locations QuickJS reports are in the generated code, usually all on `main.js:1`. A version
+//! 3 source map stored next to the bytecode translates them back, for the locations in the file
+//! name the bytecode was compiled with:
+//!
+//! ```ignore
+//! let source_map = SourceMap::parse(&source_map_json)?;
+//! let result = run_js_bytecode(bytecode)
+//!     .map_err(|err| err.with_source_map("main.js", &source_map));
+//! ```
+
+use near_sdk::serde::Deserialize;
+use near_sdk::serde_json;
+
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde", rename_all = "camelCase")]
+struct RawSourceMap {
+    version: u32,
+    source_root: Option<String>,
+    sources: Vec<Option<String>>,
+    mappings: String,
+}
+
+/// A position in the generated code, mapped to a position in one of the sources.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Segment {
+    generated_column: u32,
+    source: usize,
+    line: u32,
+    column: u32,
+}
+
+/// A location in one of the original sources, with line and column numbers from 1 as QuickJS
+/// reports them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OriginalLocation {
+    pub source: String,
+    pub line_number: u32,
+    pub column_number: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceMap {
+    sources: Vec<String>,
+    /// The mapped segments of each generated line, ordered by column.
+    lines: Vec<Vec<Segment>>,
+}
+
+impl SourceMap {
+    /// Parses a version 3 source map in JSON.
+    pub fn parse(json: &str) -> Result<Self, String> {
+        let raw: RawSourceMap = serde_json::from_str(json).map_err(|err| err.to_string())?;
+        if raw.version != 3 {
+            return Err(format!("unsupported source map version {}", raw.version));
+        }
+        let mut source_root = raw.source_root.unwrap_or_default();
+        if !source_root.is_empty() && !source_root.ends_with('/') {
+            source_root.push('/');
+        }
+        let sources: Vec<String> = raw
+            .sources
+            .into_iter()
+            .map(|source| format!("{}{}", source_root, source.unwrap_or_default()))
+            .collect();
+        let lines = parse_mappings(&raw.mappings, sources.len())?;
+        Ok(SourceMap { sources, lines })
+    }
+
+    /// The original location of a generated line and column. Without a column, the first mapped
+    /// segment of the line is used.
+    pub fn lookup(&self, line_number: u32, column_number: Option<u32>) -> Option<OriginalLocation> {
+        let segments = self.lines.get(line_number.checked_sub(1)? as usize)?;
+        let segment = match column_number {
+            Some(column_number) => {
+                let column = column_number.saturating_sub(1);
+                let end = segments.partition_point(|segment| segment.generated_column <= column);
+                segments.get(end.checked_sub(1)?)?
+            }
+            None => segments.first()?,
+        };
+        Some(OriginalLocation {
+            source: self.sources[segment.source].clone(),
+            line_number: segment.line + 1,
+            column_number: segment.column + 1,
+        })
+    }
+}
+
+/// Decodes the base64 VLQ values of a segment.
+fn decode_vlq(segment: &str) -> Result<Vec<i64>, String> {
+    let mut values = Vec::new();
+    let mut value: i64 = 0;
+    let mut shift = 0;
+    for c in segment.bytes() {
+        let digit = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return Err(format!("invalid character '{}' in mappings", c as char)),
+        } as i64;
+        if shift > 60 {
+            return Err("mapping value out of range".to_string());
+        }
+        value += (digit & 31) << shift;
+        if digit & 32 != 0 {
+            shift += 5;
+            continue;
+        }
+        // the lowest bit is the sign
+        values.push(if value & 1 == 1 {
+            -(value >> 1)
+        } else {
+            value >> 1
+        });
+        value = 0;
+        shift = 0;
+    }
+    if shift != 0 {
+        return Err("truncated value in mappings".to_string());
+    }
+    Ok(values)
+}
+
+fn parse_mappings(mappings: &str, source_count: usize) -> Result<Vec<Vec<Segment>>, String> {
+    // all fields but the generated column are relative to the previous segment in any line
+    let (mut source, mut line, mut column) = (0i64, 0i64, 0i64);
+    let mut lines = Vec::new();
+    for generated_line in mappings.split(';') {
+        let mut generated_column = 0i64;
+        let mut segments = Vec::new();
+        for segment in generated_line
+            .split(',')
+            .filter(|segment| !segment.is_empty())
+        {
+            let values = decode_vlq(segment)?;
+            generated_column += values[0];
+            if values.len() < 4 {
+                // a generated position without an original one
+                continue;
+            }
+            source += values[1];
+            line += values[2];
+            column += values[3];
+            let in_range = |value: i64| u32::try_from(value).ok();
+            let (Some(generated_column), Some(line), Some(column)) =
+                (in_range(generated_column), in_range(line), in_range(column))
+            else {
+                return Err("negative position in mappings".to_string());
+            };
+            if source < 0 || source as usize >= source_count {
+                return Err(format!("mappings refer to missing source {}", source));
+            }
+            segments.push(Segment {
+                generated_column,
+                source: source as usize,
+                line,
+                column,
+            });
+        }
+        segments.sort_by_key(|segment| segment.generated_column);
+        lines.push(segments);
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OriginalLocation, SourceMap};
+    use crate::jslib::{compile_js, run_js_bytecode};
+    use quickjs_rust_near_testenv::testenv::setup_test_env;
+
+    // line 1 is mapped from src/math.ts:1:1, and line 2 from src/main.ts:10:5 up to column
+    // 10 and from src/main.ts:12:3 after it
+    const SOURCE_MAP: &str = r#"{
+        "version": 3,
+        "file": "main.js",
+        "sourceRoot": "src/",
+        "sources": ["math.ts", "main.ts"],
+        "names": [],
+        "mappings": "AAAA;ACSI,UAEF"
+    }"#;
+
+    #[test]
+    fn test_lookup() {
+        let source_map = SourceMap::parse(SOURCE_MAP).unwrap();
+        let location = |source: &str, line_number, column_number| {
+            Some(OriginalLocation {
+                source: source.to_string(),
+                line_number,
+                column_number,
+            })
+        };
+
+        assert_eq!(location("src/math.ts", 1, 1), source_map.lookup(1, Some(7)));
+        assert_eq!(
+            location("src/main.ts", 10, 5),
+            source_map.lookup(2, Some(10))
+        );
+        assert_eq!(
+            location("src/main.ts", 12, 3),
+            source_map.lookup(2, Some(11))
+        );
+        assert_eq!(location("src/main.ts", 10, 5), source_map.lookup(2, None));
+        assert_eq!(None, source_map.lookup(3, Some(1)));
+
+        let without_trailing_slash = SOURCE_MAP.replace(r#""src/""#, r#""src""#);
+        assert_eq!(
+            location("src/math.ts", 1, 1),
+            SourceMap::parse(&without_trailing_slash)
+                .unwrap()
+                .lookup(1, Some(7))
+        );
+
+        assert!(SourceMap::parse(r#"{"version": 2, "sources": [], "mappings": ""}"#).is_err());
+        assert!(SourceMap::parse(r#"{"version": 3, "sources": [], "mappings": "AAAA"}"#).is_err());
+    }
+
+    #[test]
+    fn test_error_with_source_map() {
+        setup_test_env();
+        let bytecode = compile_js("let x = 1;\nthrow new Error('failed');".to_string(), None);
+        // all of line 2 is mapped from src/main.ts:10:5
+        let source_map = r#"{"version":3,"sources":["src/main.ts"],"mappings":"AAAA;AASI"}"#;
+        let err = run_js_bytecode(bytecode)
+            .unwrap_err()
+            .with_source_map("main.js", &SourceMap::parse(source_map).unwrap());
+
+        assert_eq!(Some("src/main.ts".to_string()), err.file_name);
+        assert_eq!(Some(10), err.line_number);
+        assert_eq!(Some(5), err.column_number);
+        assert!(err.stack.unwrap().contains("(src/main.ts:10:5)"));
+        assert_eq!("failed", err.message);
+    }
+}
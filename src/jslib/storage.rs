@@ -0,0 +1,129 @@
+//! Contract storage for scripts, isolated under a key prefix.
+//!
+//! With [`add_storage_functions`], scripts see only the keys below the prefix:
+//!
+//! | function | returns |
+//! |---|---|
+//! | `env.storage_read(key)` | the value as a `Uint8Array`, or `null` if there is none |
+//! | `env.storage_read_string(key)` | the value decoded as UTF-8, or `null` if there is none |
+//! | `env.storage_write(key, value)` | whether a previous value was overwritten |
+//! | `env.storage_remove(key)` | whether there was a value to remove |
+//! | `env.storage_has_key(key)` | whether there is a value |
+//!
+//! Keys and values may be given as strings, which are stored as UTF-8, or as binary data in a
+//! `Uint8Array` or `ArrayBuffer`.
+
+use near_sdk::env;
+use std::rc::Rc;
+
+use super::host::{Bytes, HostFunctions};
+use super::{HostError, HostResult};
+
+/// Adds the storage functions to `host`, reading and writing the keys given by scripts appended
+/// to `prefix`.
+pub fn add_storage_functions(host: &mut HostFunctions<'_>, prefix: impl Into<Vec<u8>>) {
+    let prefix: Rc<[u8]> = prefix.into().into();
+    let prefixed = move |key: Bytes| [&prefix[..], &key.0].concat();
+
+    let prefixed_key = prefixed.clone();
+    host.add(
+        "storage_read",
+        move |key: Bytes| -> HostResult<Option<Vec<u8>>> {
+            Ok(env::storage_read(&prefixed_key(key)))
+        },
+    );
+    let prefixed_key = prefixed.clone();
+    host.add(
+        "storage_read_string",
+        move |key: Bytes| -> HostResult<Option<String>> {
+            env::storage_read(&prefixed_key(key))
+                .map(|value| {
+                    String::from_utf8(value)
+                        .map_err(|_| HostError::type_error("the stored value is not valid UTF-8"))
+                })
+                .transpose()
+        },
+    );
+    let prefixed_key = prefixed.clone();
+    host.add(
+        "storage_write",
+        move |key: Bytes, value: Bytes| -> HostResult<bool> {
+            Ok(env::storage_write(&prefixed_key(key), &value.0))
+        },
+    );
+    let prefixed_key = prefixed.clone();
+    host.add("storage_remove", move |key: Bytes| -> HostResult<bool> {
+        Ok(env::storage_remove(&prefixed_key(key)))
+    });
+    host.add("storage_has_key", move |key: Bytes| -> HostResult<bool> {
+        Ok(env::storage_has_key(&prefixed(key)))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::add_storage_functions;
+    use crate::jslib::host::run_with_host_functions;
+    use near_sdk::env;
+    use quickjs_rust_near_testenv::testenv::setup_test_env;
+
+    #[test]
+    fn test_storage_functions() {
+        setup_test_env();
+        env::storage_write(b"greeting", b"outside of the prefix");
+
+        let result = run_with_host_functions(
+            "
+            return JSON.stringify([
+                env.storage_has_key('greeting'),
+                env.storage_write('greeting', 'hello'),
+                env.storage_write('greeting', 'hello again'),
+                env.storage_read_string('greeting'),
+                env.storage_write(new Uint8Array([0, 1]), new Uint8Array([255, 0, 128])),
+                Array.from(env.storage_read(new Uint8Array([0, 1]))),
+                env.storage_remove('missing'),
+                env.storage_read('missing'),
+            ]);
+            ",
+            |host| add_storage_functions(host, "JSS_"),
+        )
+        .to_string();
+
+        assert_eq!(
+            "[false,false,true,\"hello again\",false,[255,0,128],false,null]",
+            result
+        );
+        assert_eq!(
+            Some(b"hello again".to_vec()),
+            env::storage_read(b"JSS_greeting")
+        );
+        assert_eq!(Some(vec![255, 0, 128]), env::storage_read(b"JSS_\0\x01"));
+        assert_eq!(
+            Some(b"outside of the prefix".to_vec()),
+            env::storage_read(b"greeting")
+        );
+    }
+
+    #[test]
+    fn test_storage_read_string_rejects_binary() {
+        setup_test_env();
+        env::storage_write(b"JSS_binary", &[0xff, 0xfe]);
+
+        let result = run_with_host_functions(
+            "
+            try {
+                env.storage_read_string('binary');
+            } catch (e) {
+                return e.name + ': ' + e.message;
+            }
+            ",
+            |host| add_storage_functions(host, "JSS_"),
+        )
+        .to_string();
+
+        assert_eq!(
+            "TypeError: storage_read_string: the stored value is not valid UTF-8",
+            result
+        );
+    }
+}
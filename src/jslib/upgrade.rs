@@ -0,0 +1,230 @@
+//! Staged upgrades of the module bytecode a contract keeps in storage.
+//!
+//! Replacing the bytecode makes the new code live at once, with no chance to migrate the data
+//! written by the previous code. A [`CodeStorage`] instead stages the new bytecode first, and
+//! activates it in a later call, after calling the `migrate(previous_code_hash)` function the new
+//! module may export:
+//!
+//! ```ignore
+//! let code = CodeStorage::new("JS");
+//! code.stage(&bytecode)?;
+//! // later, with the host functions the migration needs
+//! code.activate(|host| add_storage_functions(host, "JSS_"))?;
+//! ```
+//!
+//! ```js
+//! export function migrate(previous_code_hash) {
+//!     const settings = env.storage_read_string('settings');
+//!     env.storage_write('config', settings ?? '{}');
+//!     env.storage_remove('settings');
+//! }
+//! ```
+//!
+//! If `migrate` throws, the previous bytecode stays active and the staged bytecode is kept.
+//! Storage the migration wrote before throwing is not restored, so contracts should panic with
+//! the error to revert it.
+
+use near_sdk::env;
+
+use super::host::HostFunctions;
+use super::{
+    bytecode_hash, load_js_bytecode, validate_module_bytecode, with_host_functions, JsError,
+    JsValue,
+};
+
+/// The module bytecode under a storage key, with its hash under the key followed by `_HASH`, and
+/// the bytecode of a staged upgrade under the key followed by `_STAGED`.
+pub struct CodeStorage {
+    key: Vec<u8>,
+}
+
+impl CodeStorage {
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        CodeStorage { key: key.into() }
+    }
+
+    fn suffixed_key(&self, suffix: &str) -> Vec<u8> {
+        [self.key.as_slice(), suffix.as_bytes()].concat()
+    }
+
+    /// The active bytecode.
+    pub fn bytecode(&self) -> Option<Vec<u8>> {
+        env::storage_read(&self.key)
+    }
+
+    /// The SHA-256 in hex of the active bytecode.
+    pub fn code_hash(&self) -> Option<String> {
+        match env::storage_read(&self.suffixed_key("_HASH")) {
+            Some(hash) => Some(String::from_utf8_lossy(&hash).into_owned()),
+            // stored before its hash was recorded
+            None => self.bytecode().map(|bytecode| bytecode_hash(&bytecode)),
+        }
+    }
+
+    /// Replaces the active bytecode right away, without a migration.
+    pub fn store(&self, bytecode: &[u8]) {
+        env::storage_write(&self.key, bytecode);
+        env::storage_write(
+            &self.suffixed_key("_HASH"),
+            bytecode_hash(bytecode).as_bytes(),
+        );
+    }
+
+    /// Validates `bytecode` and replaces the active bytecode with it, like [`CodeStorage::store`],
+    /// returning its hash. If `expected_hash` is given, the hash must match it, ignoring case.
+    pub fn store_verified(
+        &self,
+        bytecode: &[u8],
+        expected_hash: Option<&str>,
+    ) -> Result<String, JsError> {
+        validate_module_bytecode(bytecode)
+            .map_err(|err| JsError::new(err.name, format!("invalid bytecode: {}", err.message)))?;
+        let hash = bytecode_hash(bytecode);
+        if let Some(expected_hash) = expected_hash {
+            if !expected_hash.eq_ignore_ascii_case(&hash) {
+                return Err(JsError::new(
+                    "Error",
+                    format!(
+                        "the bytecode has hash {}, but {} was expected",
+                        hash, expected_hash
+                    ),
+                ));
+            }
+        }
+        self.store(bytecode);
+        Ok(hash)
+    }
+
+    /// Stages `bytecode` to be activated with [`CodeStorage::activate`], replacing any bytecode
+    /// staged before, and returns its hash.
+    pub fn stage(&self, bytecode: &[u8]) -> Result<String, JsError> {
+        validate_module_bytecode(bytecode)?;
+        env::storage_write(&self.suffixed_key("_STAGED"), bytecode);
+        Ok(bytecode_hash(bytecode))
+    }
+
+    /// The SHA-256 in hex of the staged bytecode.
+    pub fn staged_hash(&self) -> Option<String> {
+        env::storage_read(&self.suffixed_key("_STAGED")).map(|bytecode| bytecode_hash(&bytecode))
+    }
+
+    /// Loads the staged bytecode and calls its `migrate` export, if any, with the hash of the
+    /// active bytecode, or `null` if there is none. The functions added by `add_functions` are
+    /// available to the migration. Once it has returned, the staged bytecode becomes the active
+    /// one, and its hash is returned.
+    pub fn activate<'env>(
+        &self,
+        add_functions: impl FnOnce(&mut HostFunctions<'env>),
+    ) -> Result<String, JsError> {
+        let staged_key = self.suffixed_key("_STAGED");
+        let Some(bytecode) = env::storage_read(&staged_key) else {
+            return Err(JsError::new(
+                "Error",
+                "there is no staged bytecode to activate",
+            ));
+        };
+        let previous_code_hash = self.code_hash();
+
+        let jsmod = load_js_bytecode(bytecode.as_ptr(), bytecode.len())?;
+        with_host_functions(|host| -> Result<JsValue, JsError> {
+            add_functions(host);
+            if !jsmod.get_property("migrate").is_function() {
+                return Ok(JsValue::undefined());
+            }
+            jsmod.call_function_with_args("migrate", (previous_code_hash,))
+        })?;
+
+        self.store(&bytecode);
+        env::storage_remove(&staged_key);
+        Ok(bytecode_hash(&bytecode))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CodeStorage;
+    use crate::jslib::{add_storage_functions, bytecode_hash, compile_js};
+    use near_sdk::env;
+    use quickjs_rust_near_testenv::testenv::setup_test_env;
+
+    fn compile_module(source: &str) -> Vec<u8> {
+        compile_js(source.to_string(), Some("main.js".to_string()))
+    }
+
+    #[test]
+    fn test_activate_runs_migration() {
+        setup_test_env();
+        let code = CodeStorage::new("JS");
+        let previous = compile_module("export function version() { return 1; }");
+        code.store(&previous);
+
+        let staged = compile_module(
+            "
+            export function version() { return 2; }
+            export function migrate(previous_code_hash) {
+                env.storage_write('migrated_from', previous_code_hash);
+            }
+            ",
+        );
+        let staged_hash = code.stage(&staged).unwrap();
+        assert_eq!(Some(previous.clone()), code.bytecode());
+        assert_eq!(Some(staged_hash.clone()), code.staged_hash());
+
+        assert_eq!(
+            staged_hash,
+            code.activate(|host| add_storage_functions(host, "JSS_"))
+                .unwrap()
+        );
+        assert_eq!(Some(staged), code.bytecode());
+        assert_eq!(Some(staged_hash), code.code_hash());
+        assert_eq!(None, code.staged_hash());
+        assert_eq!(
+            Some(bytecode_hash(&previous).into_bytes()),
+            env::storage_read(b"JSS_migrated_from")
+        );
+    }
+
+    #[test]
+    fn test_store_verified() {
+        setup_test_env();
+        let code = CodeStorage::new("JS");
+        let bytecode = compile_module("export function version() { return 1; }");
+        let hash = bytecode_hash(&bytecode);
+
+        let err = code
+            .store_verified(&bytecode, Some(&bytecode_hash(b"other")))
+            .unwrap_err();
+        assert!(err.message.starts_with("the bytecode has hash"));
+        let err = code
+            .store_verified(&bytecode[..bytecode.len() / 2], None)
+            .unwrap_err();
+        assert!(err.message.starts_with("invalid bytecode: "));
+        assert_eq!(None, code.bytecode());
+
+        assert_eq!(
+            hash,
+            code.store_verified(&bytecode, Some(&hash.to_uppercase()))
+                .unwrap()
+        );
+        assert_eq!(Some(bytecode), code.bytecode());
+        assert_eq!(Some(hash), code.code_hash());
+    }
+
+    #[test]
+    fn test_failed_migration_keeps_previous_bytecode() {
+        setup_test_env();
+        let code = CodeStorage::new("JS");
+        let previous = compile_module("export function version() { return 1; }");
+        code.store(&previous);
+
+        let staged =
+            compile_module("export function migrate() { throw new Error('can not migrate'); }");
+        let staged_hash = code.stage(&staged).unwrap();
+
+        let err = code.activate(|_| {}).unwrap_err();
+        assert_eq!("can not migrate", err.message);
+        assert_eq!(Some(previous.clone()), code.bytecode());
+        assert_eq!(Some(bytecode_hash(&previous)), code.code_hash());
+        assert_eq!(Some(staged_hash), code.staged_hash());
+    }
+}
@@ -5,55 +5,342 @@ use web4::webappbundle::WEB_APP_BUNDLE;
 #[cfg(not(feature = "library"))]
 use viewaccesscontrol::{store_signing_key_for_account};
 #[cfg(not(feature = "library"))]
+use near_contract_standards::storage_management::{
+    StorageBalance, StorageBalanceBounds, StorageManagement,
+};
+#[cfg(not(feature = "library"))]
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 #[cfg(not(feature = "library"))]
-use near_sdk::{base64, env, near_bindgen};
+use near_sdk::store::LookupMap;
+#[cfg(not(feature = "library"))]
+use near_sdk::serde::Serialize;
+#[cfg(not(feature = "library"))]
+use near_sdk::{
+    assert_one_yocto, base64, env, near_bindgen, AccountId, BorshStorageKey, NearToken, Promise,
+};
 #[cfg(not(feature = "library"))]
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 pub mod jslib;
 pub mod web4;
 pub mod viewaccesscontrol;
     
 mod wasimock;
+
+/// The limits for scripts submitted by any account, which must not be able to exhaust the
+/// memory of the contract.
+#[cfg(not(feature = "library"))]
+const SCRIPT_RUNTIME_CONFIG: jslib::RuntimeConfig = jslib::RuntimeConfig {
+    memory_limit: Some(16 * 1024 * 1024),
+    max_stack_size: Some(256 * 1024),
+    gc_threshold: None,
+    budget: Some(jslib::ExecutionBudget {
+        gas_reserve: jslib::budget::DEFAULT_GAS_RESERVE,
+    }),
+    log_level: jslib::console::DEFAULT_LOG_LEVEL,
+};
+
+/// The name of the script of an account when none is given.
+#[cfg(not(feature = "library"))]
+const DEFAULT_SCRIPT_NAME: &str = "default";
+
+#[derive(BorshStorageKey, BorshSerialize)]
+#[borsh(crate="near_sdk::borsh")]
+#[cfg(not(feature = "library"))]
+enum StorageKey {
+    Scripts,
+    ScriptVersions,
+    StorageAccounts,
+}
+
+/// The bytes charged for registering an account: the storage record overhead, and the key and
+/// value of its entry in `storage_accounts` for the longest account id.
+#[cfg(not(feature = "library"))]
+const ACCOUNT_STORAGE_USAGE: u64 = 40 + 1 + 4 + 64 + 16 + 8;
+
+/// The NEP-145 storage balance of an account, and the bytes of storage it is charged for.
+#[derive(BorshDeserialize, BorshSerialize)]
+#[borsh(crate="near_sdk::borsh")]
+#[cfg(not(feature = "library"))]
+struct StorageAccount {
+    deposit: NearToken,
+    storage_usage: u64,
+}
+
+/// A named script of an account, with the block timestamps of its versions, which are numbered
+/// from 1.
+#[derive(BorshDeserialize, BorshSerialize)]
+#[borsh(crate="near_sdk::borsh")]
+#[cfg(not(feature = "library"))]
+struct ScriptInfo {
+    submitted_at_ms: Vec<u64>,
+    active_version: u32,
+}
+
+#[derive(BorshSerialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[borsh(crate="near_sdk::borsh")]
+#[cfg(not(feature = "library"))]
+struct ScriptVersionKey {
+    account_id: AccountId,
+    name: String,
+    version: u32,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+#[borsh(crate="near_sdk::borsh")]
+#[cfg(not(feature = "library"))]
+struct ScriptVersion {
+    source: String,
+    bytecode: Vec<u8>,
+    source_map: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg(not(feature = "library"))]
+pub struct ScriptView {
+    pub name: String,
+    pub active_version: u32,
+    pub latest_version: u32,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg(not(feature = "library"))]
+pub struct ScriptVersionView {
+    pub version: u32,
+    pub submitted_at_ms: u64,
+    pub active: bool,
+}
+
+/// The state before scripts were named and versioned, with the bytecode of one script per
+/// account, which [`Scripts::migrate`] moves to the current state.
+#[derive(BorshDeserialize, BorshSerialize)]
+#[borsh(crate="near_sdk::borsh")]
+#[cfg(not(feature = "library"))]
+struct ScriptsV1 {
+    scripts: HashMap<String, Vec<u8>>,
+}
+
+/// Scripts belong to the account calling the contract, the predecessor, rather than the signer
+/// of the transaction, so that a contract called by a user can not change the scripts of the
+/// user.
 #[near_bindgen]
-#[derive(Default, BorshDeserialize, BorshSerialize)]
+#[derive(BorshDeserialize, BorshSerialize)]
 #[borsh(crate="near_sdk::borsh")]
 #[cfg(not(feature = "library"))]
 pub struct Scripts {
-    scripts: HashMap<String, Vec<u8>>,
+    /// The scripts of each account by name. The bytecode is kept separately, so that only the
+    /// version being run is loaded.
+    scripts: LookupMap<AccountId, BTreeMap<String, ScriptInfo>>,
+    versions: LookupMap<ScriptVersionKey, ScriptVersion>,
+    storage_accounts: LookupMap<AccountId, StorageAccount>,
+}
+
+#[cfg(not(feature = "library"))]
+impl Default for Scripts {
+    fn default() -> Self {
+        Self {
+            scripts: LookupMap::new(StorageKey::Scripts),
+            versions: LookupMap::new(StorageKey::ScriptVersions),
+            storage_accounts: LookupMap::new(StorageKey::StorageAccounts),
+        }
+    }
 }
 
 #[near_bindgen]
 #[cfg(not(feature = "library"))]
 impl Scripts {
+    /// Moves the script of each account in the state from before scripts were named and
+    /// versioned to the first version of its default script. Only the bytecode was kept, so the
+    /// source of the migrated versions is empty. Their storage stays paid by the contract, and
+    /// is not charged to the storage balance of the accounts.
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let Some(previous) = env::state_read::<ScriptsV1>() else {
+            env::panic_str("there is no state to migrate");
+        };
+        let mut contract = Self::default();
+        for (account_id, bytecode) in previous.scripts {
+            let Ok(account_id) = account_id.parse::<AccountId>() else {
+                continue;
+            };
+            let mut scripts = BTreeMap::new();
+            scripts.insert(
+                DEFAULT_SCRIPT_NAME.to_string(),
+                ScriptInfo {
+                    submitted_at_ms: vec![env::block_timestamp_ms()],
+                    active_version: 1,
+                },
+            );
+            contract.scripts.insert(account_id.clone(), scripts);
+            contract.versions.insert(
+                ScriptVersionKey {
+                    account_id,
+                    name: DEFAULT_SCRIPT_NAME.to_string(),
+                    version: 1,
+                },
+                ScriptVersion { source: String::new(), bytecode, source_map: None },
+            );
+        }
+        contract
+    }
+
     pub fn store_signing_key() {
         store_signing_key_for_account(env::block_timestamp_ms() + 24 * 60 * 60 * 1000);
     }
 
     pub fn run_script(&self, script: String) -> String {
-        return jslib::run_js(script).to_string();
+        return jslib::run_js_with_config(script, &SCRIPT_RUNTIME_CONFIG)
+            .unwrap_or_else(|err| env::panic_str(&err.to_string()))
+            .to_string();
     }
 
     pub fn run_bytecode(&self, bytecodebase64: String) -> String {
         let bytecode: Result<Vec<u8>, base64::DecodeError> = base64::decode(&bytecodebase64);
-        return jslib::run_js_bytecode(bytecode.unwrap()).to_string();
+        return jslib::run_js_bytecode_with_config(bytecode.unwrap(), &SCRIPT_RUNTIME_CONFIG)
+            .unwrap_or_else(|err| env::panic_str(&err.to_string()))
+            .to_string();
     }
 
-    pub fn submit_script(&mut self, script: String) {
-        let compiled = jslib::compile_js(script, None);
+    /// Compiles and stores `script` as a new version of the script `name` of the caller, which
+    /// becomes the active one. The storage is charged against the storage balance of the caller,
+    /// to which the attached deposit is added first. Errors of the script are reported at their
+    /// location in the original sources of `source_map`, if given.
+    #[payable]
+    pub fn submit_script(
+        &mut self,
+        script: String,
+        name: Option<String>,
+        source_map: Option<String>,
+    ) -> u32 {
+        let account_id = env::predecessor_account_id();
+        if !env::attached_deposit().is_zero() {
+            self.deposit_storage(&account_id, env::attached_deposit());
+        }
+        let initial_storage_usage = self.flushed_storage_usage();
+        let name = name.unwrap_or_else(|| DEFAULT_SCRIPT_NAME.to_string());
+        if name.is_empty() {
+            env::panic_str("the script name must not be empty");
+        }
+        if let Some(Err(err)) = source_map.as_deref().map(jslib::SourceMap::parse) {
+            env::panic_str(&format!("invalid source map: {}", err));
+        }
+
+        let compiled = jslib::compile_js_with_config(script.clone(), None, &SCRIPT_RUNTIME_CONFIG);
         env::log_str(&(compiled.len().to_string()));
-        let account_id = env::signer_account_id();
-        self.scripts.insert(account_id.to_string(), compiled);
+
+        let info = self
+            .scripts
+            .entry(account_id.clone())
+            .or_default()
+            .entry(name.clone())
+            .or_insert(ScriptInfo {
+                submitted_at_ms: Vec::new(),
+                active_version: 0,
+            });
+        info.submitted_at_ms.push(env::block_timestamp_ms());
+        let version = info.submitted_at_ms.len() as u32;
+        info.active_version = version;
+        self.versions.insert(
+            ScriptVersionKey { account_id: account_id.clone(), name, version },
+            ScriptVersion { source: script, bytecode: compiled, source_map },
+        );
+
+        self.charge_storage(&account_id, initial_storage_usage);
+        version
+    }
+
+    /// Deletes the script `name` of the caller with all of its versions, releasing their storage
+    /// from the storage balance of the caller.
+    pub fn delete_script(&mut self, name: String) {
+        let initial_storage_usage = self.flushed_storage_usage();
+        let account_id = env::predecessor_account_id();
+        let Some(scripts) = self.scripts.get_mut(&account_id) else {
+            env::panic_str(&format!("no script named {}", name));
+        };
+        let Some(info) = scripts.remove(&name) else {
+            env::panic_str(&format!("no script named {}", name));
+        };
+        if scripts.is_empty() {
+            self.scripts.remove(&account_id);
+        }
+        self.remove_versions(&account_id, &name, &info);
+
+        self.charge_storage(&account_id, initial_storage_usage);
+    }
+
+    /// Makes an earlier `version` of the script `name` of the caller the active one again.
+    pub fn rollback_script(&mut self, name: String, version: u32) {
+        let account_id = env::predecessor_account_id();
+        let Some(info) = self
+            .scripts
+            .get_mut(&account_id)
+            .and_then(|scripts| scripts.get_mut(&name))
+        else {
+            env::panic_str(&format!("no script named {}", name));
+        };
+        if version == 0 || version as usize > info.submitted_at_ms.len() {
+            env::panic_str(&format!("script {} has no version {}", name, version));
+        }
+        info.active_version = version;
     }
 
-    pub fn run_script_for_account(&self, account_id: String) -> String {
-        let bytecode = self.scripts.get(&account_id).unwrap().to_vec();
-        return jslib::run_js_bytecode(bytecode).to_string();
+    pub fn list_scripts(&self, account_id: AccountId) -> Vec<ScriptView> {
+        let Some(scripts) = self.scripts.get(&account_id) else {
+            return Vec::new();
+        };
+        scripts
+            .iter()
+            .map(|(name, info)| ScriptView {
+                name: name.clone(),
+                active_version: info.active_version,
+                latest_version: info.submitted_at_ms.len() as u32,
+            })
+            .collect()
     }
 
-    pub fn run_script_for_account_no_return(&self, account_id: String) {
-        let bytecode = self.scripts.get(&account_id).unwrap().to_vec();
-        jslib::run_js_bytecode(bytecode);
+    pub fn list_script_versions(
+        &self,
+        account_id: AccountId,
+        name: String,
+    ) -> Vec<ScriptVersionView> {
+        let Some(info) = self.script_info(&account_id, &name) else {
+            return Vec::new();
+        };
+        (1..)
+            .zip(&info.submitted_at_ms)
+            .map(|(version, submitted_at_ms)| ScriptVersionView {
+                version,
+                submitted_at_ms: *submitted_at_ms,
+                active: version == info.active_version,
+            })
+            .collect()
+    }
+
+    /// The source of a `version` of the script `name`, by default the active one.
+    pub fn get_script_source(
+        &self,
+        account_id: AccountId,
+        name: Option<String>,
+        version: Option<u32>,
+    ) -> Option<String> {
+        let name = name.unwrap_or_else(|| DEFAULT_SCRIPT_NAME.to_string());
+        let version = match version {
+            Some(version) => version,
+            None => self.script_info(&account_id, &name)?.active_version,
+        };
+        self.versions
+            .get(&ScriptVersionKey { account_id, name, version })
+            .map(|script_version| script_version.source.clone())
+    }
+
+    pub fn run_script_for_account(&self, account_id: AccountId, name: Option<String>) -> String {
+        return self.run_active_script(account_id, name).to_string();
+    }
+
+    pub fn run_script_for_account_no_return(&self, account_id: AccountId, name: Option<String>) {
+        self.run_active_script(account_id, name);
     }
 
     pub fn web4_get(&self, #[allow(unused_variables)] request: Web4Request) -> Web4Response {
@@ -64,16 +351,215 @@ impl Scripts {
     }
 }
 
+#[cfg(not(feature = "library"))]
+impl Scripts {
+    fn script_info(&self, account_id: &AccountId, name: &str) -> Option<&ScriptInfo> {
+        self.scripts.get(account_id)?.get(name)
+    }
+
+    /// Runs the active version of the script `name` of `account_id`, panicking with the error it
+    /// throws, located through its source map if it has one.
+    fn run_active_script(&self, account_id: AccountId, name: Option<String>) -> jslib::JsValue {
+        let name = name.unwrap_or_else(|| DEFAULT_SCRIPT_NAME.to_string());
+        let Some(info) = self.script_info(&account_id, &name) else {
+            env::panic_str(&format!("{} has no script named {}", account_id, name));
+        };
+        let key = ScriptVersionKey { account_id, name, version: info.active_version };
+        let script_version = self.versions.get(&key).unwrap();
+        jslib::run_js_bytecode_with_config(script_version.bytecode.clone(), &SCRIPT_RUNTIME_CONFIG)
+            .unwrap_or_else(|err| {
+                let err = match &script_version.source_map {
+                    Some(source_map) => err
+                        .with_source_map("main.js", &jslib::SourceMap::parse(source_map).unwrap()),
+                    None => err,
+                };
+                env::panic_str(&err.to_string())
+            })
+    }
+
+    fn remove_versions(&mut self, account_id: &AccountId, name: &str, info: &ScriptInfo) {
+        for version in 1..=info.submitted_at_ms.len() as u32 {
+            self.versions.remove(&ScriptVersionKey {
+                account_id: account_id.clone(),
+                name: name.to_string(),
+                version,
+            });
+        }
+    }
+
+    /// The storage usage of the contract, with all changes to the collections written.
+    fn flushed_storage_usage(&mut self) -> u64 {
+        self.scripts.flush();
+        self.versions.flush();
+        self.storage_accounts.flush();
+        env::storage_usage()
+    }
+
+    /// Adds `amount` to the storage balance of `account_id`, registering the account if needed.
+    fn deposit_storage(&mut self, account_id: &AccountId, amount: NearToken) {
+        if let Some(account) = self.storage_accounts.get_mut(account_id) {
+            account.deposit = account.deposit.saturating_add(amount);
+            return;
+        }
+        let min = self.storage_balance_bounds().min;
+        if amount < min {
+            env::panic_str(&format!(
+                "the minimum storage deposit is {} yoctoNEAR",
+                min.as_yoctonear()
+            ));
+        }
+        self.storage_accounts.insert(
+            account_id.clone(),
+            StorageAccount {
+                deposit: amount,
+                storage_usage: ACCOUNT_STORAGE_USAGE,
+            },
+        );
+    }
+
+    /// Charges the storage added since `initial_storage_usage` to `account_id`, or releases the
+    /// storage removed, panicking if its storage balance does not cover it. Storage released by
+    /// an account without a storage balance, which only migrated scripts can take, is the
+    /// contract's.
+    fn charge_storage(&mut self, account_id: &AccountId, initial_storage_usage: u64) {
+        let storage_usage = self.flushed_storage_usage();
+        let Some(account) = self.storage_accounts.get_mut(account_id) else {
+            if storage_usage <= initial_storage_usage {
+                return;
+            }
+            env::panic_str(&format!(
+                "{} has no storage balance, register it with storage_deposit",
+                account_id
+            ));
+        };
+        account.storage_usage = (account.storage_usage + storage_usage)
+            .saturating_sub(initial_storage_usage);
+        let cost = env::storage_byte_cost().saturating_mul(account.storage_usage as u128);
+        if cost > account.deposit {
+            env::panic_str(&format!(
+                "insufficient storage balance: {} bytes cost {} yoctoNEAR, but {} has deposited {} yoctoNEAR",
+                account.storage_usage,
+                cost.as_yoctonear(),
+                account_id,
+                account.deposit.as_yoctonear()
+            ));
+        }
+    }
+
+    fn storage_balance(account: &StorageAccount) -> StorageBalance {
+        let cost = env::storage_byte_cost().saturating_mul(account.storage_usage as u128);
+        StorageBalance {
+            total: account.deposit,
+            available: account.deposit.saturating_sub(cost),
+        }
+    }
+}
+
+#[near_bindgen]
+#[cfg(not(feature = "library"))]
+impl StorageManagement for Scripts {
+    #[payable]
+    fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let amount = env::attached_deposit();
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        if registration_only == Some(true) {
+            // keeps only what registration takes, and nothing from registered accounts
+            let kept = if self.storage_accounts.contains_key(&account_id) {
+                NearToken::from_yoctonear(0)
+            } else {
+                let min = self.storage_balance_bounds().min;
+                self.deposit_storage(&account_id, amount.min(min));
+                min
+            };
+            let refund = amount.saturating_sub(kept);
+            if !refund.is_zero() {
+                Promise::new(env::predecessor_account_id()).transfer(refund);
+            }
+        } else {
+            self.deposit_storage(&account_id, amount);
+        }
+        self.storage_balance_of(account_id).unwrap()
+    }
+
+    #[payable]
+    fn storage_withdraw(&mut self, amount: Option<NearToken>) -> StorageBalance {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let Some(account) = self.storage_accounts.get_mut(&account_id) else {
+            env::panic_str(&format!("{} has no storage balance", account_id));
+        };
+        let balance = Self::storage_balance(account);
+        let amount = amount.unwrap_or(balance.available);
+        if amount > balance.available {
+            env::panic_str(&format!(
+                "only {} yoctoNEAR of the storage balance is available",
+                balance.available.as_yoctonear()
+            ));
+        }
+        account.deposit = account.deposit.saturating_sub(amount);
+        let balance = Self::storage_balance(account);
+        if !amount.is_zero() {
+            Promise::new(account_id).transfer(amount);
+        }
+        balance
+    }
+
+    /// Unregisters the caller and refunds its storage deposit. With `force`, the scripts of the
+    /// caller are deleted, otherwise it must have none left.
+    #[payable]
+    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let Some(account) = self.storage_accounts.remove(&account_id) else {
+            return false;
+        };
+        if let Some(scripts) = self.scripts.remove(&account_id) {
+            if force != Some(true) {
+                env::panic_str("delete the scripts first, or unregister with force");
+            }
+            for (name, info) in &scripts {
+                self.remove_versions(&account_id, name, info);
+            }
+        }
+        Promise::new(account_id).transfer(account.deposit);
+        true
+    }
+
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: env::storage_byte_cost().saturating_mul(ACCOUNT_STORAGE_USAGE as u128),
+            max: None,
+        }
+    }
+
+    fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        self.storage_accounts.get(&account_id).map(Self::storage_balance)
+    }
+}
 
 #[cfg(test)]
 pub mod tests {
     use super::*;
 
-    use near_sdk::base64;
-    use quickjs_rust_near_testenv::testenv::{alice, set_signer_account_id, setup_test_env, assert_latest_return_value_string_eq};
+    use near_sdk::{base64, NearToken};
+    use quickjs_rust_near_testenv::testenv::{
+        alice, assert_latest_return_value_string_eq, bob, promises, set_attached_deposit,
+        set_block_timestamp, set_predecessor_account_id, set_signer_account_id, setup_test_env,
+        PromiseAction,
+    };
     use quickjs_rust_near_testenv::musicscript::MUSIC_SCRIPT;
     use jslib::compile_js;
 
+    fn set_submitter(account_id: AccountId, deposit: NearToken) {
+        set_signer_account_id(account_id.clone());
+        set_predecessor_account_id(account_id);
+        set_attached_deposit(deposit);
+    }
+
     #[test]
     fn test_run_script() {
         setup_test_env();
@@ -83,17 +569,191 @@ pub mod tests {
         assert_eq!("6".to_string(), result);
     }
 
+    #[test]
+    fn test_submitted_script_memory_is_limited() {
+        setup_test_env();
+        set_submitter(alice(), NearToken::from_near(10));
+        let mut contract = Scripts::default();
+
+        contract.submit_script("(function () {
+            const chunks = [];
+            try {
+                while (true) {
+                    chunks.push(new Array(1024 * 1024).fill(1));
+                }
+            } catch (e) {
+                return chunks.length;
+            }
+        })()".to_string(), None, None);
+        let chunks: usize = contract.run_script_for_account(alice(), None).parse().unwrap();
+        assert!(chunks < 16);
+    }
+
     #[test]
     fn test_submit_and_run_stored_script() {
         setup_test_env();
-        set_signer_account_id(alice());
+        set_submitter(alice(), NearToken::from_near(10));
         let mut contract = Scripts::default();
 
-        contract.submit_script("(function () { return 15+4+3; })()".to_string());
-        let result = contract.run_script_for_account(alice().to_string());
+        contract.submit_script("(function () { return 15+4+3; })()".to_string(), None, None);
+        let result = contract.run_script_for_account(alice(), None);
         assert_eq!("22".to_string(), result);
     }
 
+    #[test]
+    fn test_named_script_versions() {
+        setup_test_env();
+        set_submitter(alice(), NearToken::from_near(10));
+        let mut contract = Scripts::default();
+
+        set_block_timestamp(1_000_000_000);
+        let greeting = Some("greeting".to_string());
+        assert_eq!(1, contract.submit_script("'first'".to_string(), greeting.clone(), None));
+        set_block_timestamp(2_000_000_000);
+        assert_eq!(2, contract.submit_script("'second'".to_string(), greeting, None));
+        contract.submit_script("'default'".to_string(), None, None);
+
+        let scripts = contract.list_scripts(alice());
+        assert_eq!(
+            vec![("default", 1, 1), ("greeting", 2, 2)],
+            scripts
+                .iter()
+                .map(|script| (script.name.as_str(), script.active_version, script.latest_version))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![(1, 1000, false), (2, 2000, true)],
+            contract
+                .list_script_versions(alice(), "greeting".to_string())
+                .iter()
+                .map(|version| (version.version, version.submitted_at_ms, version.active))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            "second",
+            contract.run_script_for_account(alice(), Some("greeting".to_string()))
+        );
+        assert_eq!("default", contract.run_script_for_account(alice(), None));
+
+        contract.rollback_script("greeting".to_string(), 1);
+        assert_eq!(
+            "first",
+            contract.run_script_for_account(alice(), Some("greeting".to_string()))
+        );
+        assert_eq!(
+            Some("'first'".to_string()),
+            contract.get_script_source(alice(), Some("greeting".to_string()), None)
+        );
+        assert_eq!(
+            Some("'second'".to_string()),
+            contract.get_script_source(alice(), Some("greeting".to_string()), Some(2))
+        );
+        assert!(contract.list_scripts(bob()).is_empty());
+    }
+
+    #[test]
+    fn test_scripts_belong_to_predecessor() {
+        setup_test_env();
+        set_submitter(bob(), NearToken::from_near(10));
+        set_signer_account_id(alice());
+        let mut contract = Scripts::default();
+
+        contract.submit_script("'from bob'".to_string(), None, None);
+        assert!(contract.list_scripts(alice()).is_empty());
+        assert_eq!("from bob", contract.run_script_for_account(bob(), None));
+    }
+
+    #[test]
+    fn test_migrate_single_script_state() {
+        setup_test_env();
+        set_block_timestamp(3_000_000_000);
+        env::state_write(&ScriptsV1 {
+            scripts: HashMap::from([(
+                alice().to_string(),
+                compile_js("'migrated'".to_string(), None),
+            )]),
+        });
+
+        let mut contract = Scripts::migrate();
+        assert_eq!("migrated", contract.run_script_for_account(alice(), None));
+        assert_eq!(
+            vec![(1, 3000, true)],
+            contract
+                .list_script_versions(alice(), DEFAULT_SCRIPT_NAME.to_string())
+                .iter()
+                .map(|version| (version.version, version.submitted_at_ms, version.active))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(Some(String::new()), contract.get_script_source(alice(), None, None));
+
+        set_submitter(alice(), NearToken::from_yoctonear(0));
+        contract.delete_script(DEFAULT_SCRIPT_NAME.to_string());
+        assert!(contract.list_scripts(alice()).is_empty());
+    }
+
+    fn transfer(index: usize) -> (AccountId, NearToken) {
+        let promise = &promises()[index];
+        match &promise.actions[..] {
+            [PromiseAction::Transfer { amount }] => (promise.receiver_id.clone().unwrap(), *amount),
+            _ => panic!("expected a transfer"),
+        }
+    }
+
+    #[test]
+    fn test_script_storage_is_paid_from_storage_balance() {
+        setup_test_env();
+        set_submitter(alice(), NearToken::from_near(1));
+        let mut contract = Scripts::default();
+
+        let min = contract.storage_balance_bounds().min;
+        let balance = contract.storage_deposit(None, None);
+        assert_eq!(NearToken::from_near(1), balance.total);
+        assert_eq!(NearToken::from_near(1).saturating_sub(min), balance.available);
+
+        set_attached_deposit(NearToken::from_yoctonear(0));
+        let storage_usage = contract.flushed_storage_usage();
+        contract.submit_script("'paid for'".to_string(), Some("paid".to_string()), None);
+        let storage_cost = env::storage_byte_cost()
+            .saturating_mul((contract.flushed_storage_usage() - storage_usage) as u128);
+        assert!(!storage_cost.is_zero());
+        assert_eq!(
+            balance.available.saturating_sub(storage_cost),
+            contract.storage_balance_of(alice()).unwrap().available
+        );
+
+        contract.delete_script("paid".to_string());
+        assert_eq!(storage_usage, contract.flushed_storage_usage());
+        assert_eq!(balance.available, contract.storage_balance_of(alice()).unwrap().available);
+        assert!(promises().is_empty());
+
+        set_attached_deposit(NearToken::from_yoctonear(1));
+        let balance = contract.storage_withdraw(None);
+        assert_eq!(min, balance.total);
+        assert!(balance.available.is_zero());
+        assert_eq!((alice(), NearToken::from_near(1).saturating_sub(min)), transfer(0));
+
+        assert!(contract.storage_unregister(None));
+        assert_eq!((alice(), min), transfer(1));
+        assert!(contract.storage_balance_of(alice()).is_none());
+    }
+
+    #[test]
+    fn test_storage_deposit_registration_only() {
+        setup_test_env();
+        set_submitter(bob(), NearToken::from_near(1));
+        let mut contract = Scripts::default();
+
+        let min = contract.storage_balance_bounds().min;
+        let balance = contract.storage_deposit(Some(alice()), Some(true));
+        assert_eq!(min, balance.total);
+        assert!(balance.available.is_zero());
+        assert_eq!((bob(), NearToken::from_near(1).saturating_sub(min)), transfer(0));
+
+        contract.storage_deposit(Some(alice()), Some(true));
+        assert_eq!((bob(), NearToken::from_near(1)), transfer(1));
+        assert_eq!(min, contract.storage_balance_of(alice()).unwrap().total);
+    }
+
     #[test]
     fn test_run_bytecode() {
         setup_test_env();
@@ -109,7 +769,7 @@ pub mod tests {
     fn test_run_async() {
         setup_test_env();
 
-        set_signer_account_id(alice());
+        set_submitter(alice(), NearToken::from_near(10));
         let mut contract = Scripts::default();
 
         contract.submit_script("(async () => {
@@ -119,9 +779,9 @@ pub mod tests {
             await new Promise(r => r());
             print('after await');
             env.value_return('return after async');
-        })()".to_string());
+        })()".to_string(), None, None);
 
-        contract.run_script_for_account(alice().to_string());
+        contract.run_script_for_account(alice(), None);
         assert_latest_return_value_string_eq(
             "return after async".to_string()
         );
@@ -141,12 +801,12 @@ pub mod tests {
     fn test_run_async_music_script() {
         setup_test_env();
 
-        set_signer_account_id(alice());
+        set_submitter(alice(), NearToken::from_near(10));
         let mut contract = Scripts::default();
 
-        contract.submit_script(MUSIC_SCRIPT.to_string());
+        contract.submit_script(MUSIC_SCRIPT.to_string(), None, None);
 
-        contract.run_script_for_account_no_return(alice().to_string());
+        contract.run_script_for_account_no_return(alice(), None);
         assert_latest_return_value_string_eq(
             "[{\"time\":0,\"message\":[148,26,100]},{\"time\":0,\"message\":[146,60,100]},{\"time\":166,\"message\":[146,66,10]},{\"time\":233,\"message\":[130,66,0]},{\"time\":267,\"message\":[132,26,0]},{\"time\":333,\"message\":[146,66,80]},{\"time\":500,\"message\":[148,26,100]},{\"time\":533,\"message\":[130,66,0]},{\"time\":567,\"message\":[132,26,0]},{\"time\":666,\"message\":[148,33,100]},{\"time\":666,\"message\":[146,62,100]},{\"time\":667,\"message\":[130,60,0]},{\"time\":799,\"message\":[132,33,0]},{\"time\":833,\"message\":[148,36,100]},{\"time\":966,\"message\":[132,36,0]},{\"time\":1000,\"message\":[146,66,70]},{\"time\":1166,\"message\":[148,38,100]},{\"time\":1166,\"message\":[146,60,100]},{\"time\":1200,\"message\":[130,66,0]},{\"time\":1299,\"message\":[132,38,0]},{\"time\":1333,\"message\":[130,62,0]},{\"time\":1500,\"message\":[148,36,100]},{\"time\":1500,\"message\":[146,66,10]},{\"time\":1567,\"message\":[130,66,0]},{\"time\":1666,\"message\":[146,66,80]},{\"time\":1666,\"message\":[146,60,100]},{\"time\":1700,\"message\":[132,36,0]},{\"time\":1833,\"message\":[148,33,100]},{\"time\":1833,\"message\":[130,60,0]},{\"time\":1866,\"message\":[130,66,0]},{\"time\":1966,\"message\":[132,33,0]},{\"time\":2000,\"message\":[146,62,100]},{\"time\":2166,\"message\":[148,33,100]},{\"time\":2299,\"message\":[132,33,0]},{\"time\":2333,\"message\":[148,36,100]},{\"time\":2333,\"message\":[146,66,70]},{\"time\":2333,\"message\":[130,60,0]},{\"time\":2400,\"message\":[132,36,0]},{\"time\":2500,\"message\":[148,38,100]},{\"time\":2500,\"message\":[146,62,20]},{\"time\":2533,\"message\":[130,66,0]},{\"time\":2567,\"message\":[130,62,0]},{\"time\":2633,\"message\":[132,38,0]},{\"time\":2666,\"message\":[-1]}]".to_string()
         );
@@ -1,21 +1,102 @@
 use near_sdk::env;
 
+use crate::jslib::console::{self, LogLevel};
+use crate::jslib::random;
+
+// The contract has no time zone, so the local time is UTC.
+static TZ_NAME: &[u8] = b"UTC\0";
+
+// `struct tm` of the wasm32 libc, with `tm_gmtoff` and `tm_zone` following the int fields
+#[repr(C)]
+struct Tm {
+    tm_sec: i32,
+    tm_min: i32,
+    tm_hour: i32,
+    tm_mday: i32,
+    tm_mon: i32,
+    tm_year: i32,
+    tm_wday: i32,
+    tm_yday: i32,
+    tm_isdst: i32,
+    tm_gmtoff: i32,
+    tm_zone: i32,
+}
+
+// Days since 1970-01-01 of a date in the proleptic Gregorian calendar, with `month` from 1 to 12.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// The year, month from 1 to 12 and day of `days` since 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn write_tm(t: i64, tm: &mut Tm) {
+    let days = t.div_euclid(86400);
+    let seconds = t.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+
+    tm.tm_sec = (seconds % 60) as i32;
+    tm.tm_min = (seconds / 60 % 60) as i32;
+    tm.tm_hour = (seconds / 3600) as i32;
+    tm.tm_mday = day as i32;
+    tm.tm_mon = (month - 1) as i32;
+    tm.tm_year = (year - 1900) as i32;
+    tm.tm_wday = (days + 4).rem_euclid(7) as i32;
+    tm.tm_yday = (days - days_from_civil(year, 1, 1)) as i32;
+    tm.tm_isdst = 0;
+    tm.tm_gmtoff = 0;
+    tm.tm_zone = TZ_NAME.as_ptr() as i32;
+}
+
+// The seconds since the epoch of the fields of `tm`, which may be out of their usual ranges,
+// normalizing the fields as `timegm` does.
+fn read_tm(tm: &mut Tm) -> i64 {
+    let month = tm.tm_mon as i64;
+    let year = tm.tm_year as i64 + 1900 + month.div_euclid(12);
+    let days = days_from_civil(year, month.rem_euclid(12) + 1, 1) + tm.tm_mday as i64 - 1;
+    let t = days * 86400 + tm.tm_hour as i64 * 3600 + tm.tm_min as i64 * 60 + tm.tm_sec as i64;
+    write_tm(t, tm);
+    t
+}
+
 #[no_mangle]
-//pub extern "C" fn _tzset_js(_timezone: *const libc::c_long, _daylight: *const libc::c_int, _tzname: *const libc::c_char) {
-pub extern "C" fn _tzset_js(_timezone: i32, _daylight: i32, _tzname: i32) {
-    env::log_str("_tzset_js");
+//pub extern "C" fn _tzset_js(_timezone: *mut libc::c_long, _daylight: *mut libc::c_int, _std_name: *mut libc::c_char, _dst_name: *mut libc::c_char) {
+pub extern "C" fn _tzset_js(_timezone: i32, _daylight: i32, _std_name: i32, _dst_name: i32) {
+    unsafe {
+        *(_timezone as *mut i32) = 0;
+        *(_daylight as *mut i32) = 0;
+        for name in [_std_name, _dst_name] {
+            std::ptr::copy_nonoverlapping(TZ_NAME.as_ptr(), name as *mut u8, TZ_NAME.len());
+        }
+    }
 }
 
 #[no_mangle]
-//pub extern "C" fn _localtime_js(_t: *const libc::time_t, _tm: *const libc::tm) {
-pub extern "C" fn _localtime_js(_t: i32, _tm: i32) {
-    env::log_str("_localtime_js");
+//pub extern "C" fn _localtime_js(_t: libc::time_t, _tm: *mut libc::tm) {
+pub extern "C" fn _localtime_js(_t: i64, _tm: i32) {
+    write_tm(_t, unsafe { &mut *(_tm as *mut Tm) });
 }
 
 #[no_mangle]
 pub extern "C" fn _emscripten_date_now() -> f64 {
-    //env::log_str("_emscripten_date_now");
-    return 0.0;
+    return env::block_timestamp_ms() as f64;
 }
 
 #[no_mangle]
@@ -42,7 +123,7 @@ pub extern "C" fn _emscripten_get_progname(_p1: i32, _p2: i32) {}
 #[no_mangle]
 //pub extern "C" fn __wasi_fd_write(_fd: i32, _iovs: i32, _iovs_len: usize, _result: *const libc::size_t) -> libc::size_t {
 pub extern "C" fn __wasi_fd_write(_fd: i32, _iovs: i32, _iovs_len: usize, _result: i32) -> i32 {
-    let mut written = 0;
+    let mut output: Vec<u8> = Vec::new();
     for n in 0.._iovs_len {
         unsafe {
             let bufptrptr: *const usize = (_iovs + n as i32 * 8) as *const usize;
@@ -50,16 +131,23 @@ pub extern "C" fn __wasi_fd_write(_fd: i32, _iovs: i32, _iovs_len: usize, _resul
 
             let bufptr: *const u8 = usize::from_le(*bufptrptr) as *const u8;
             let buflen = usize::from_le(*buflenptr);
-            env::log_str(std::str::from_utf8_unchecked(std::slice::from_raw_parts(
-                bufptr, buflen,
-            )));
-
-            written += buflen;
+            output.extend_from_slice(std::slice::from_raw_parts(bufptr, buflen));
         }
     }
     unsafe {
         let written_ptr: *mut usize = _result as *mut usize;
-        *written_ptr = written;
+        *written_ptr = output.len();
+    }
+
+    // stdout is line buffered, so a write is usually one line from `print`
+    let message = String::from_utf8_lossy(output.strip_suffix(b"\n").unwrap_or(&output));
+    if !message.is_empty() {
+        let level = if _fd == 2 {
+            LogLevel::Error
+        } else {
+            LogLevel::Info
+        };
+        console::log(level, &message);
     }
     return 0;
 }
@@ -118,24 +206,27 @@ pub extern "C" fn __wasi_environ_get(_environ: i32, _environ_buf: i32) -> i32 {
 #[no_mangle]
 //pub extern "C" fn __wasi_random_get(_buf: i32, _size: libc::size_t) -> i32 {
 pub extern "C" fn __wasi_random_get(_buf: i32, _size: i32) -> i32 {
-    env::log_str("__wasi_environ_get");
+    random::fill_random(unsafe { std::slice::from_raw_parts_mut(_buf as *mut u8, _size as usize) });
     return 0;
 }
 
 #[no_mangle]
-pub extern "C" fn _timegm_js(_p1: i32) -> i32 {
-    env::log_str("_timegm_js");
-    return 0;
+//pub extern "C" fn _timegm_js(_tm: *mut libc::tm) -> libc::time_t {
+pub extern "C" fn _timegm_js(_tm: i32) -> i64 {
+    read_tm(unsafe { &mut *(_tm as *mut Tm) })
 }
 
 #[no_mangle]
-pub extern "C" fn _mktime_js(_p1: i32) -> i32 {
-    env::log_str("_mktime_js");
-    return 0;
+//pub extern "C" fn _mktime_js(_tm: *mut libc::tm) -> libc::time_t {
+pub extern "C" fn _mktime_js(_tm: i32) -> i64 {
+    read_tm(unsafe { &mut *(_tm as *mut Tm) })
 }
 
 #[no_mangle]
-pub extern "C" fn _gmtime_js(_p1: i32, _p2: i32) {}
+//pub extern "C" fn _gmtime_js(_t: libc::time_t, _tm: *mut libc::tm) {
+pub extern "C" fn _gmtime_js(_t: i64, _tm: i32) {
+    write_tm(_t, unsafe { &mut *(_tm as *mut Tm) });
+}
 
 #[no_mangle]
 pub extern "C" fn _emscripten_get_now_is_monotonic() -> i32 {
@@ -169,3 +260,95 @@ pub extern "C" fn __wasilibc_find_relpath(_p1: i32, _p2: i32, _p3: i32, _p4: i32
 
 #[no_mangle]
 pub extern "C" fn __wasm_call_dtors() {}
+
+#[cfg(test)]
+mod tests {
+    use super::{Tm, _localtime_js, _timegm_js};
+    use crate::jslib::run_js;
+    use quickjs_rust_near_testenv::testenv::{set_block_timestamp, setup_test_env};
+
+    fn tm(year: i32, mon: i32, mday: i32, hour: i32, min: i32, sec: i32) -> Tm {
+        Tm {
+            tm_sec: sec,
+            tm_min: min,
+            tm_hour: hour,
+            tm_mday: mday,
+            tm_mon: mon,
+            tm_year: year - 1900,
+            tm_wday: 0,
+            tm_yday: 0,
+            tm_isdst: -1,
+            tm_gmtoff: 0,
+            tm_zone: 0,
+        }
+    }
+
+    #[test]
+    fn test_calendar_functions() {
+        let mut leap_day = tm(2024, 1, 29, 12, 30, 15);
+        assert_eq!(1709209815, _timegm_js(&mut leap_day as *mut Tm as i32));
+        assert_eq!(
+            (4, 59, 0),
+            (leap_day.tm_wday, leap_day.tm_yday, leap_day.tm_isdst)
+        );
+
+        // out of range fields are carried over, as by timegm
+        let mut new_year = tm(1999, 11, 31, 23, 59, 60);
+        assert_eq!(946684800, _timegm_js(&mut new_year as *mut Tm as i32));
+        assert_eq!(
+            (100, 0, 1, 0, 0),
+            (
+                new_year.tm_year,
+                new_year.tm_mon,
+                new_year.tm_mday,
+                new_year.tm_hour,
+                new_year.tm_sec
+            )
+        );
+
+        let mut before_epoch = tm(1970, 0, 1, 0, 0, 0);
+        _localtime_js(-1, &mut before_epoch as *mut Tm as i32);
+        assert_eq!(
+            (69, 11, 31, 23, 59, 59),
+            (
+                before_epoch.tm_year,
+                before_epoch.tm_mon,
+                before_epoch.tm_mday,
+                before_epoch.tm_hour,
+                before_epoch.tm_min,
+                before_epoch.tm_sec
+            )
+        );
+        assert_eq!((3, 364), (before_epoch.tm_wday, before_epoch.tm_yday));
+    }
+
+    #[test]
+    fn test_date_uses_block_time_in_utc() {
+        setup_test_env();
+        set_block_timestamp(1_709_209_815_123_456_789);
+
+        let result = run_js(
+            "
+            const now = new Date();
+            JSON.stringify([
+                Date.now(),
+                now.toISOString(),
+                now.getFullYear(),
+                now.getMonth(),
+                now.getDate(),
+                now.getDay(),
+                now.getHours(),
+                now.getTimezoneOffset(),
+                new Date(2024, 1, 29, 12, 30, 15, 123).getTime(),
+            ]);
+            "
+            .to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            "[1709209815123,\"2024-02-29T12:30:15.123Z\",2024,1,29,4,12,0,1709209815123]",
+            result.to_string()
+        );
+    }
+}
@@ -1,5 +1,5 @@
 use ed25519_dalek::Signature;
-use near_sdk::{AccountId, NearToken, PublicKey};
+use near_sdk::{AccountId, Gas, NearToken, PublicKey};
 use sha2::Digest;
 use std::collections::HashMap;
 use std::io::{self, Write};
@@ -21,21 +21,65 @@ pub fn carol() -> AccountId {
     "carol.near".parse().unwrap()
 }
 
+/// An action added to a promise, as recorded by the test environment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PromiseAction {
+    CreateAccount,
+    DeployContract {
+        code: Vec<u8>,
+    },
+    FunctionCall {
+        function_name: String,
+        arguments: Vec<u8>,
+        amount: NearToken,
+        gas: Gas,
+    },
+    Transfer {
+        amount: NearToken,
+    },
+}
+
+/// A promise created by the contract. Promises created with `promise_and` have no receiver or
+/// actions, and depend on the promises they combine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestPromise {
+    pub receiver_id: Option<AccountId>,
+    pub depends_on: Vec<u64>,
+    pub actions: Vec<PromiseAction>,
+}
+
 struct TestEnv {
     block_timestamp: u64,
+    block_height: u64,
+    epoch_height: u64,
+    account_balance: NearToken,
+    account_locked_balance: NearToken,
+    validator_stakes: HashMap<AccountId, NearToken>,
+    random_seed: [u8; 32],
     signer_account_id: AccountId,
     signer_account_pk: PublicKey,
     current_account_id: AccountId,
     predecessor_account_id: AccountId,
     attached_deposit: NearToken,
-    input: Vec<u8>,
+    input: Option<Vec<u8>>,
     returned_value: Vec<u8>,
+    used_gas: Gas,
+    promises: Vec<TestPromise>,
+    returned_promise: Option<u64>,
+    promise_results: Vec<Option<Vec<u8>>>,
+    logs: Vec<String>,
 }
 
 impl TestEnv {
     pub fn new() -> Self {
         Self {
             block_timestamp: 0,
+            block_height: 0,
+            epoch_height: 0,
+            account_balance: NearToken::from_near(100),
+            account_locked_balance: NearToken::from_near(0),
+            validator_stakes: HashMap::new(),
+            random_seed: [0; 32],
             signer_account_id: bob(),
             current_account_id: alice(),
             predecessor_account_id: bob(),
@@ -46,8 +90,13 @@ impl TestEnv {
             ]
             .try_into()
             .unwrap(),
-            input: "{}".to_string().into_bytes(),
+            input: Some("{}".to_string().into_bytes()),
             returned_value: Vec::default(),
+            used_gas: Gas::from_gas(0),
+            promises: Vec::new(),
+            returned_promise: None,
+            promise_results: Vec::new(),
+            logs: Vec::new(),
         }
     }
 }
@@ -109,7 +158,17 @@ pub fn set_predecessor_account_id(account_id: AccountId) {
 pub fn set_input(input: Vec<u8>) {
     unsafe {
         if let Some(test_env) = TESTENV.as_mut() {
-            test_env.input = input;
+            test_env.input = Some(input);
+        }
+    }
+}
+
+/// Makes the contract be called without input.
+#[allow(dead_code)]
+pub fn clear_input() {
+    unsafe {
+        if let Some(test_env) = TESTENV.as_mut() {
+            test_env.input = None;
         }
     }
 }
@@ -130,6 +189,111 @@ pub fn set_block_timestamp(timestamp_nanos: u64) {
     }
 }
 
+pub fn set_block_height(block_height: u64) {
+    unsafe {
+        if let Some(test_env) = TESTENV.as_mut() {
+            test_env.block_height = block_height;
+        }
+    }
+}
+
+pub fn set_epoch_height(epoch_height: u64) {
+    unsafe {
+        if let Some(test_env) = TESTENV.as_mut() {
+            test_env.epoch_height = epoch_height;
+        }
+    }
+}
+
+pub fn set_account_balance(balance: NearToken, locked_balance: NearToken) {
+    unsafe {
+        if let Some(test_env) = TESTENV.as_mut() {
+            test_env.account_balance = balance;
+            test_env.account_locked_balance = locked_balance;
+        }
+    }
+}
+
+/// Sets the stake of a validator. Accounts without a stake are not validators.
+pub fn set_validator_stake(account_id: AccountId, stake: NearToken) {
+    unsafe {
+        if let Some(test_env) = TESTENV.as_mut() {
+            test_env.validator_stakes.insert(account_id, stake);
+        }
+    }
+}
+
+pub fn set_random_seed(random_seed: [u8; 32]) {
+    unsafe {
+        if let Some(test_env) = TESTENV.as_mut() {
+            test_env.random_seed = random_seed;
+        }
+    }
+}
+
+pub fn set_used_gas(used_gas: Gas) {
+    unsafe {
+        if let Some(test_env) = TESTENV.as_mut() {
+            test_env.used_gas = used_gas;
+        }
+    }
+}
+
+/// Sets the results of the promises the current call is a callback of, with `None` for a
+/// failed promise.
+pub fn set_promise_results(promise_results: Vec<Option<Vec<u8>>>) {
+    unsafe {
+        if let Some(test_env) = TESTENV.as_mut() {
+            test_env.promise_results = promise_results;
+        }
+    }
+}
+
+/// The promises created so far, by promise index.
+pub fn promises() -> Vec<TestPromise> {
+    unsafe { TESTENV.as_ref().unwrap().promises.clone() }
+}
+
+/// The messages logged so far, e.g. with `env::log_str`.
+pub fn logs() -> Vec<String> {
+    unsafe { TESTENV.as_ref().unwrap().logs.clone() }
+}
+
+/// The index of the promise passed to `promise_return`, if any.
+pub fn returned_promise() -> Option<u64> {
+    unsafe { TESTENV.as_ref().unwrap().returned_promise }
+}
+
+unsafe fn read_str(len: i64, ptr: i64) -> String {
+    String::from_utf8(std::slice::from_raw_parts(ptr as *const u8, len as usize).to_vec()).unwrap()
+}
+
+unsafe fn write_amount(amount: NearToken, amount_ptr: i64) {
+    let amount = amount.as_yoctonear().to_le_bytes();
+    std::ptr::copy(amount.as_ptr(), amount_ptr as *mut u8, amount.len());
+}
+
+unsafe fn read_amount(amount_ptr: i64) -> NearToken {
+    let amount = std::slice::from_raw_parts(amount_ptr as *const u8, 16);
+    NearToken::from_yoctonear(u128::from_le_bytes(amount.try_into().unwrap()))
+}
+
+unsafe fn add_promise(receiver_id: Option<AccountId>, depends_on: Vec<u64>) -> i64 {
+    let promises = &mut TESTENV.as_mut().unwrap().promises;
+    promises.push(TestPromise {
+        receiver_id,
+        depends_on,
+        actions: Vec::new(),
+    });
+    (promises.len() - 1) as i64
+}
+
+unsafe fn add_promise_action(promise_index: i64, action: PromiseAction) {
+    TESTENV.as_mut().unwrap().promises[promise_index as usize]
+        .actions
+        .push(action);
+}
+
 #[no_mangle]
 pub extern "C" fn read_register(register_id: i64, data_ptr: i64) {
     unsafe {
@@ -198,7 +362,10 @@ pub extern "C" fn input(register: i64) {
     unsafe {
         let registers = REGISTERS.as_mut().unwrap();
         let testenv = TESTENV.as_ref().unwrap();
-        registers.insert(register, testenv.input.clone());
+        match &testenv.input {
+            Some(input) => registers.insert(register, input.clone()),
+            None => registers.remove(&register),
+        };
     }
 }
 
@@ -238,6 +405,9 @@ pub extern "C" fn log_utf8(len: i64, ptr: i64) {
         let buflen: usize = len as usize;
         let str = std::str::from_utf8_unchecked(std::slice::from_raw_parts(bufptr, buflen));
         println!("{}", str);
+        if let Some(test_env) = TESTENV.as_mut() {
+            test_env.logs.push(str.to_string());
+        }
     }
 }
 
@@ -326,6 +496,15 @@ pub fn assert_latest_return_value_contains(value_to_be_contained: String) {
     }
 }
 
+pub fn assert_latest_return_value_eq(expected_return_value: &[u8]) {
+    unsafe {
+        assert_eq!(
+            TESTENV.as_ref().unwrap().returned_value.as_slice(),
+            expected_return_value
+        );
+    }
+}
+
 pub fn assert_latest_return_value_string_eq(expected_return_value: String) {
     unsafe {
         assert_eq!(
@@ -352,6 +531,122 @@ pub extern "C" fn prepaid_gas() -> i64 {
     return 300000000000000;
 }
 
+#[no_mangle]
+pub extern "C" fn used_gas() -> i64 {
+    unsafe {
+        return TESTENV.as_ref().unwrap().used_gas.as_gas() as i64;
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn block_index() -> i64 {
+    unsafe { TESTENV.as_ref().unwrap().block_height as i64 }
+}
+
+#[no_mangle]
+pub extern "C" fn epoch_height() -> i64 {
+    unsafe { TESTENV.as_ref().unwrap().epoch_height as i64 }
+}
+
+#[no_mangle]
+pub extern "C" fn account_balance(balance_ptr: i64) {
+    unsafe { write_amount(TESTENV.as_ref().unwrap().account_balance, balance_ptr) }
+}
+
+#[no_mangle]
+pub extern "C" fn account_locked_balance(balance_ptr: i64) {
+    unsafe { write_amount(TESTENV.as_ref().unwrap().account_locked_balance, balance_ptr) }
+}
+
+#[no_mangle]
+pub extern "C" fn validator_stake(account_id_len: i64, account_id_ptr: i64, stake_ptr: i64) {
+    unsafe {
+        let account_id: AccountId = read_str(account_id_len, account_id_ptr).parse().unwrap();
+        let stakes = &TESTENV.as_ref().unwrap().validator_stakes;
+        let stake = stakes
+            .get(&account_id)
+            .copied()
+            .unwrap_or(NearToken::from_yoctonear(0));
+        write_amount(stake, stake_ptr);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn validator_total_stake(stake_ptr: i64) {
+    unsafe {
+        let stakes = &TESTENV.as_ref().unwrap().validator_stakes;
+        let total = stakes.values().map(|stake| stake.as_yoctonear()).sum();
+        write_amount(NearToken::from_yoctonear(total), stake_ptr);
+    }
+}
+
+unsafe fn hash_to_register<D: Digest>(value_len: i64, value_ptr: i64, register_id: i64) {
+    let value = std::slice::from_raw_parts(value_ptr as *const u8, value_len as usize);
+    REGISTERS
+        .as_mut()
+        .unwrap()
+        .insert(register_id, D::digest(value).to_vec());
+}
+
+#[no_mangle]
+pub extern "C" fn keccak256(value_len: i64, value_ptr: i64, register_id: i64) {
+    unsafe { hash_to_register::<sha3::Keccak256>(value_len, value_ptr, register_id) }
+}
+
+#[no_mangle]
+pub extern "C" fn keccak512(value_len: i64, value_ptr: i64, register_id: i64) {
+    unsafe { hash_to_register::<sha3::Keccak512>(value_len, value_ptr, register_id) }
+}
+
+#[no_mangle]
+pub extern "C" fn ripemd160(value_len: i64, value_ptr: i64, register_id: i64) {
+    unsafe { hash_to_register::<ripemd::Ripemd160>(value_len, value_ptr, register_id) }
+}
+
+/// Recovers the 64 byte uncompressed secp256k1 public key, without the `0x04` prefix, that
+/// signed `hash`. Returns 0 if the signature is invalid, or malleable while
+/// `malleability_flag` is set.
+#[no_mangle]
+pub extern "C" fn ecrecover(
+    hash_len: i64,
+    hash_ptr: i64,
+    sig_len: i64,
+    sig_ptr: i64,
+    v: i64,
+    malleability_flag: i64,
+    register_id: i64,
+) -> i64 {
+    use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+
+    unsafe {
+        let hash = std::slice::from_raw_parts(hash_ptr as *const u8, hash_len as usize);
+        let signature = std::slice::from_raw_parts(sig_ptr as *const u8, sig_len as usize);
+        let (Ok(mut signature), Some(mut recovery_id)) =
+            (Signature::from_slice(signature), RecoveryId::from_byte(v as u8))
+        else {
+            return 0;
+        };
+        // k256 only accepts signatures with a low s, which negates the recovered point
+        if let Some(normalized) = signature.normalize_s() {
+            if malleability_flag != 0 {
+                return 0;
+            }
+            signature = normalized;
+            recovery_id = RecoveryId::new(!recovery_id.is_y_odd(), recovery_id.is_x_reduced());
+        }
+        let Ok(public_key) = VerifyingKey::recover_from_prehash(hash, &signature, recovery_id)
+        else {
+            return 0;
+        };
+        let point = public_key.to_encoded_point(false);
+        REGISTERS
+            .as_mut()
+            .unwrap()
+            .insert(register_id, point.as_bytes()[1..].to_vec());
+        1
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn sha256(value_len: i64, value_ptr: i64, register_id: i64) {
     unsafe {
@@ -367,45 +662,120 @@ pub extern "C" fn sha256(value_len: i64, value_ptr: i64, register_id: i64) {
 }
 
 #[no_mangle]
-pub extern "C" fn promise_and(_promise_idx_ptr: i64, _promise_idx_count: i64) -> i64 {
-    return 0;
+pub extern "C" fn promise_create(
+    account_id_len: i64,
+    account_id_ptr: i64,
+    function_name_len: i64,
+    function_name_ptr: i64,
+    arguments_len: i64,
+    arguments_ptr: i64,
+    amount_ptr: i64,
+    gas: i64,
+) -> i64 {
+    let promise_index = promise_batch_create(account_id_len, account_id_ptr);
+    promise_batch_action_function_call(
+        promise_index,
+        function_name_len,
+        function_name_ptr,
+        arguments_len,
+        arguments_ptr,
+        amount_ptr,
+        gas,
+    );
+    promise_index
+}
+
+#[no_mangle]
+pub extern "C" fn promise_then(
+    promise_index: i64,
+    account_id_len: i64,
+    account_id_ptr: i64,
+    function_name_len: i64,
+    function_name_ptr: i64,
+    arguments_len: i64,
+    arguments_ptr: i64,
+    amount_ptr: i64,
+    gas: i64,
+) -> i64 {
+    let promise_index = promise_batch_then(promise_index, account_id_len, account_id_ptr);
+    promise_batch_action_function_call(
+        promise_index,
+        function_name_len,
+        function_name_ptr,
+        arguments_len,
+        arguments_ptr,
+        amount_ptr,
+        gas,
+    );
+    promise_index
 }
 
 #[no_mangle]
-pub extern "C" fn promise_batch_create(_account_id_len: i64, _account_id_ptr: i64) -> i64 {
-    return 0;
+pub extern "C" fn promise_and(promise_idx_ptr: i64, promise_idx_count: i64) -> i64 {
+    unsafe {
+        let indices =
+            std::slice::from_raw_parts(promise_idx_ptr as *const u64, promise_idx_count as usize);
+        add_promise(None, indices.to_vec())
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn promise_batch_create(account_id_len: i64, account_id_ptr: i64) -> i64 {
+    unsafe {
+        let account_id = read_str(account_id_len, account_id_ptr).parse().unwrap();
+        add_promise(Some(account_id), Vec::new())
+    }
 }
 
 #[no_mangle]
 pub extern "C" fn promise_batch_then(
-    _promise_index: i64,
-    _account_id_len: i64,
-    _account_id_ptr: i64,
+    promise_index: i64,
+    account_id_len: i64,
+    account_id_ptr: i64,
 ) -> i64 {
-    return 0;
+    unsafe {
+        let account_id = read_str(account_id_len, account_id_ptr).parse().unwrap();
+        add_promise(Some(account_id), vec![promise_index as u64])
+    }
 }
 
 #[no_mangle]
-pub extern "C" fn promise_batch_action_create_account(_promise_index: i64) {}
+pub extern "C" fn promise_batch_action_create_account(promise_index: i64) {
+    unsafe { add_promise_action(promise_index, PromiseAction::CreateAccount) }
+}
 
 #[no_mangle]
 pub extern "C" fn promise_batch_action_deploy_contract(
-    _promise_index: i64,
-    _code_len: i64,
-    _code_ptr: i64,
+    promise_index: i64,
+    code_len: i64,
+    code_ptr: i64,
 ) {
+    unsafe {
+        let code = std::slice::from_raw_parts(code_ptr as *const u8, code_len as usize).to_vec();
+        add_promise_action(promise_index, PromiseAction::DeployContract { code });
+    }
 }
 
 #[no_mangle]
 pub extern "C" fn promise_batch_action_function_call(
-    _promise_index: i64,
-    _method_name_len: i64,
-    _method_name_ptr: i64,
-    _arguments_len: i64,
-    _arguments_ptr: i64,
-    _amount_ptr: i64,
-    _gas: i64,
+    promise_index: i64,
+    method_name_len: i64,
+    method_name_ptr: i64,
+    arguments_len: i64,
+    arguments_ptr: i64,
+    amount_ptr: i64,
+    gas: i64,
 ) {
+    unsafe {
+        let action = PromiseAction::FunctionCall {
+            function_name: read_str(method_name_len, method_name_ptr),
+            arguments: std::slice::from_raw_parts(arguments_ptr as *const u8, arguments_len as usize)
+                .to_vec(),
+            amount: read_amount(amount_ptr),
+            gas: Gas::from_gas(gas as u64),
+        };
+        add_promise_action(promise_index, action);
+    }
 }
 
 #[no_mangle]
@@ -422,7 +792,12 @@ pub extern "C" fn promise_batch_action_function_call_weight(
 }
 
 #[no_mangle]
-pub extern "C" fn promise_batch_action_transfer(_promise_index: i64, _amount_ptr: i64) {}
+pub extern "C" fn promise_batch_action_transfer(promise_index: i64, amount_ptr: i64) {
+    unsafe {
+        let amount = read_amount(amount_ptr);
+        add_promise_action(promise_index, PromiseAction::Transfer { amount });
+    }
+}
 
 #[no_mangle]
 pub extern "C" fn promise_batch_action_stake(
@@ -473,12 +848,30 @@ pub extern "C" fn promise_batch_action_delete_account(
 }
 
 #[no_mangle]
-pub extern "C" fn promise_result(_result_idx: i64, _register_id: i64) -> i64 {
-    return 0;
+pub extern "C" fn promise_results_count() -> i64 {
+    unsafe { TESTENV.as_ref().unwrap().promise_results.len() as i64 }
 }
 
 #[no_mangle]
-pub extern "C" fn promise_return(_promise_idx: i64) {}
+pub extern "C" fn promise_result(result_idx: i64, register_id: i64) -> i64 {
+    unsafe {
+        let testenv = TESTENV.as_ref().unwrap();
+        match &testenv.promise_results[result_idx as usize] {
+            Some(data) => {
+                REGISTERS.as_mut().unwrap().insert(register_id, data.clone());
+                1
+            }
+            None => 2,
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn promise_return(promise_idx: i64) {
+    unsafe {
+        TESTENV.as_mut().unwrap().returned_promise = Some(promise_idx as u64);
+    }
+}
 
 #[no_mangle]
 pub extern "C" fn block_timestamp() -> i64 {
@@ -493,8 +886,11 @@ pub extern "C" fn block_timestamp() -> i64 {
 }
 
 #[no_mangle]
-pub extern "C" fn random_seed(_register: i64) {
-
+pub extern "C" fn random_seed(register_id: i64) {
+    unsafe {
+        let random_seed = TESTENV.as_ref().unwrap().random_seed.to_vec();
+        REGISTERS.as_mut().unwrap().insert(register_id, random_seed);
+    }
 }
 
 #[no_mangle]
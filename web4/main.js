@@ -120,7 +120,8 @@ customElements.define('app-wasm-music-share', class extends HTMLElement {
             
             setStatusText(`posting music script`);
             const wc = await checkSignedin();
-            const result = await wc.account().functionCall(contractAccountId, 'submit_script', {script: musicscript}, '300000000000000');
+            // 1 NEAR is added to the storage balance, which pays for the storage of the script
+            const result = await wc.account().functionCall(contractAccountId, 'submit_script', {script: musicscript}, '300000000000000', '1000000000000000000000000');
             setStatusText(JSON.stringify(result));
         });        
     }